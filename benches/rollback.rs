@@ -0,0 +1,97 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ggrs::{Frame, GGRSRequest, GameStateCell, PlayerType};
+use ggrs_test_game::box_game;
+
+/// Numbers of boxes to measure with, from a regular match up to far more than the game will ever have.
+const PLAYER_COUNTS: [usize; 4] = [2, 8, 32, 128];
+
+/// A game with `players` boxes, moved away from the starting positions so the state is not trivial.
+fn warmed_up_game(players: usize) -> box_game::Simulation {
+    let mut game = box_game::Simulation::new(players, 1);
+    for frame in 0..120 {
+        game.simulate_frame(inputs(frame, players)).unwrap();
+    }
+    game
+}
+
+/// A cell as GGRS hands it out for saving `frame`, cells only take states of the frame they were handed out for.
+fn cell_for(frame: Frame) -> GameStateCell {
+    let mut sess = ggrs::start_synctest_session(1, 1, 0).unwrap();
+    sess.add_player(PlayerType::Local, 0).unwrap();
+    sess.start_session().unwrap();
+    for _ in 0..frame {
+        sess.advance_frame(0, &[0]).unwrap();
+    }
+    match sess.advance_frame(0, &[0]).unwrap().remove(0) {
+        GGRSRequest::SaveGameState { cell, .. } => cell,
+        _ => unreachable!("GGRS saves before it advances"),
+    }
+}
+
+fn inputs(frame: i32, players: usize) -> Vec<Option<Vec<u8>>> {
+    (0..players)
+        .map(|player| {
+            let input = box_game::scripted_input(0, frame, player);
+            Some(box_game::input::encode(input).to_vec())
+        })
+        .collect()
+}
+
+/// One frame of simulation, what every `AdvanceFrame` request costs.
+fn advance_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("advance_frame");
+    for &players in PLAYER_COUNTS.iter() {
+        let mut game = warmed_up_game(players);
+        let inputs = inputs(0, players);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(players),
+            &inputs,
+            |b, inputs| b.iter(|| game.simulate_frame(black_box(inputs.clone())).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+/// A `SaveGameState` request, a snapshot for small games and a serialized state for the rest.
+fn save_game_state(c: &mut Criterion) {
+    let mut group = c.benchmark_group("save_game_state");
+    for &players in PLAYER_COUNTS.iter() {
+        let mut game = warmed_up_game(players);
+        let frame = game.current_frame();
+        let cell = cell_for(frame);
+        group.bench_function(BenchmarkId::from_parameter(players), |b| {
+            b.iter(|| {
+                let cell = black_box(cell.clone());
+                game.handle_request(GGRSRequest::SaveGameState { cell, frame })
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A `LoadGameState` request of a state saved by a `SaveGameState` request.
+fn load_game_state(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load_game_state");
+    for &players in PLAYER_COUNTS.iter() {
+        let mut game = warmed_up_game(players);
+        let frame = game.current_frame();
+        let cell = cell_for(frame);
+        game.handle_request(GGRSRequest::SaveGameState {
+            cell: cell.clone(),
+            frame,
+        })
+        .unwrap();
+        group.bench_function(BenchmarkId::from_parameter(players), |b| {
+            b.iter(|| {
+                let cell = black_box(cell.clone());
+                game.handle_request(GGRSRequest::LoadGameState { cell })
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, advance_frame, save_game_state, load_game_state);
+criterion_main!(benches);
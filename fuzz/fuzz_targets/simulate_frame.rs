@@ -0,0 +1,31 @@
+//! Feeds arbitrary peer inputs through the simulation, run it with `cargo fuzz run simulate_frame`.
+//! Inputs of any length and bit pattern, as well as disconnected peers (GGRS inputs with `NULL_FRAME`), must never
+//! make the simulation panic.
+#![no_main]
+
+use boxgame_sim::{BoxGameState, Tuning};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Match {
+    num_peers: u8,
+    two_local: bool,
+    /// the encoded inputs of every peer per frame, `None` for disconnected peers
+    frames: Vec<Vec<Option<Vec<u8>>>>,
+}
+
+fuzz_target!(|data: Match| {
+    let num_peers = 1 + data.num_peers as usize % 4;
+    let local_players = if data.two_local { 2 } else { 1 };
+    let mut state = BoxGameState::new(num_peers * local_players);
+    for inputs in data.frames {
+        for bytes in inputs.iter().flatten() {
+            boxgame_sim::input::decode(bytes);
+        }
+        state.advance(&inputs, local_players, &Tuning::DEFAULT);
+        // rollbacks restore whatever the simulation produced
+        let saved = boxgame_sim::encoding::serialize(&state).unwrap();
+        state = boxgame_sim::encoding::deserialize(&saved).unwrap();
+    }
+});
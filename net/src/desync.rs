@@ -0,0 +1,100 @@
+use ggrs::{Frame, PlayerHandle};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many checksums of each side are kept for diagnostics.
+const HISTORY_LEN: usize = 64;
+
+/// Compares the checksums of confirmed frames between the local client and a remote peer.
+#[derive(Default)]
+pub struct DesyncDetector {
+    local: VecDeque<(Frame, u64)>,
+    remote: VecDeque<(Frame, u64)>,
+    /// remote checksums for frames not confirmed locally yet
+    unmatched_remote: BTreeMap<Frame, u64>,
+    /// the first frame found to differ
+    pub desync_frame: Option<Frame>,
+}
+
+impl DesyncDetector {
+    /// Adds a locally confirmed checksum. Returns the frame if this revealed a desync.
+    pub fn add_local(&mut self, frame: Frame, checksum: u64) -> Option<Frame> {
+        push_bounded(&mut self.local, (frame, checksum));
+        // remote checksums of older frames can never be matched anymore
+        self.unmatched_remote = self.unmatched_remote.split_off(&frame);
+        match self.unmatched_remote.remove(&frame) {
+            Some(remote) => self.compare(frame, checksum, remote),
+            None => None,
+        }
+    }
+
+    /// Adds a checksum received from the remote peer. Returns the frame if this revealed a desync.
+    pub fn add_remote(&mut self, frame: Frame, checksum: u64) -> Option<Frame> {
+        push_bounded(&mut self.remote, (frame, checksum));
+        match self
+            .local
+            .iter()
+            .find(|(local_frame, _)| *local_frame == frame)
+        {
+            Some(&(_, local)) => self.compare(frame, local, checksum),
+            None => {
+                // only remember remote checksums that are ahead of us, older ones can never be compared anymore
+                let newest_local = self.local.back().map(|(frame, _)| *frame).unwrap_or(0);
+                if frame > newest_local {
+                    self.unmatched_remote.insert(frame, checksum);
+                }
+                None
+            }
+        }
+    }
+
+    fn compare(&mut self, frame: Frame, local: u64, remote: u64) -> Option<Frame> {
+        if local == remote || self.desync_frame.is_some() {
+            return None;
+        }
+        self.desync_frame = Some(frame);
+        Some(frame)
+    }
+
+    /// Writes a timestamped diagnostic bundle with both checksum histories and a copy of the replay, if any.
+    /// Returns the directory of the bundle. It is named after the local player as well, both peers detect the desync
+    /// within the same second and may run in the same directory.
+    pub fn write_bundle(
+        &self,
+        local_handle: PlayerHandle,
+        replay: Option<&Path>,
+    ) -> std::io::Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let dir = PathBuf::from(format!("desync-{}-p{}", timestamp, local_handle));
+        fs::create_dir_all(&dir)?;
+
+        let mut file = fs::File::create(dir.join("checksums.txt"))?;
+        writeln!(file, "first desynced frame: {:?}", self.desync_frame)?;
+        writeln!(file, "local checksums:")?;
+        for (frame, checksum) in &self.local {
+            writeln!(file, "{} {}", frame, checksum)?;
+        }
+        writeln!(file, "remote checksums:")?;
+        for (frame, checksum) in &self.remote {
+            writeln!(file, "{} {}", frame, checksum)?;
+        }
+
+        if let Some(replay) = replay {
+            fs::copy(replay, dir.join("replay.bin"))?;
+        }
+        Ok(dir)
+    }
+}
+
+fn push_bounded(history: &mut VecDeque<(Frame, u64)>, entry: (Frame, u64)) {
+    history.push_back(entry);
+    if history.len() > HISTORY_LEN {
+        history.pop_front();
+    }
+}
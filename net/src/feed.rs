@@ -0,0 +1,131 @@
+use ggrs::Frame;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The rate is measured over this window.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// A feed without new frames for this long is shown as stalled.
+pub const STALL_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Watches how steadily a spectator receives the match from its host. GGRS does not expose its packet counters, but a
+/// spectator can only advance a frame once the host's inputs for it arrived, so new frames stand in for packets.
+#[derive(Default)]
+pub struct FeedMonitor {
+    last_frame: Option<Frame>,
+    /// when each frame of the last `RATE_WINDOW` arrived
+    arrivals: VecDeque<Instant>,
+    last_arrival: Option<Instant>,
+}
+
+impl FeedMonitor {
+    /// Call after every tick with the spectator's current frame.
+    pub fn update(&mut self, frame: Frame, now: Instant) {
+        let new_frames = match self.last_frame {
+            Some(last_frame) => (frame - last_frame).max(0),
+            None => 0,
+        };
+        self.last_frame = Some(frame);
+        for _ in 0..new_frames {
+            self.arrivals.push_back(now);
+        }
+        if new_frames > 0 {
+            self.last_arrival = Some(now);
+        }
+        while let Some(&arrival) = self.arrivals.front() {
+            if now.saturating_duration_since(arrival) < RATE_WINDOW {
+                break;
+            }
+            self.arrivals.pop_front();
+        }
+    }
+
+    /// Frames received during the last second.
+    pub fn frames_per_second(&self) -> usize {
+        self.arrivals.len()
+    }
+
+    /// Time since the last new frame, `None` before the first one.
+    pub fn since_last_frame(&self, now: Instant) -> Option<Duration> {
+        self.last_arrival
+            .map(|arrival| now.saturating_duration_since(arrival))
+    }
+
+    pub fn stalled(&self, now: Instant) -> bool {
+        self.since_last_frame(now)
+            .is_none_or(|since| since >= STALL_THRESHOLD)
+    }
+}
+
+/// The last samples of how many frames a spectator trails its host, for a graph of the stream delay.
+pub struct LagHistory {
+    capacity: usize,
+    /// oldest first
+    samples: VecDeque<i32>,
+}
+
+impl LagHistory {
+    /// Keeps the last `capacity` samples.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Call once per tick with the frames between the host's latest frame and the one shown.
+    pub fn push(&mut self, frames_behind: i32) {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(frames_behind.max(0));
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = i32> + '_ {
+        self.samples.iter().copied()
+    }
+
+    pub fn max(&self) -> i32 {
+        self.samples().max().unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_rate_and_stalls() {
+        let start = Instant::now();
+        let tick = Duration::from_millis(10);
+        let mut monitor = FeedMonitor::default();
+        monitor.update(0, start);
+        assert!(monitor.stalled(start));
+        // 100 ticks with a new frame each, then a catch-up of 3 frames at once
+        for i in 1..=100 {
+            monitor.update(i, start + tick * i as u32);
+        }
+        monitor.update(103, start + tick * 101);
+        assert_eq!(monitor.frames_per_second(), 102);
+        assert!(!monitor.stalled(start + tick * 101));
+        let later = start + tick * 101 + STALL_THRESHOLD;
+        monitor.update(103, later);
+        assert_eq!(monitor.since_last_frame(later), Some(STALL_THRESHOLD));
+        assert!(monitor.stalled(later));
+        assert_eq!(monitor.frames_per_second(), 3 + 49);
+    }
+
+    #[test]
+    fn lag_history_keeps_the_last_samples() {
+        let mut history = LagHistory::new(3);
+        assert_eq!(history.max(), 0);
+        for frames_behind in [12, -1, 4, 7].iter() {
+            history.push(*frames_behind);
+        }
+        assert_eq!(history.samples().collect::<Vec<_>>(), vec![0, 4, 7]);
+        assert_eq!(history.max(), 7);
+    }
+}
@@ -0,0 +1,101 @@
+use ggrs::Frame;
+use std::time::{Duration, Instant};
+
+/// Measures the end-to-end input latency, from a key press until the first frame simulated with that input is
+/// rendered, and until that frame is confirmed. The rendered latency includes waiting for the next tick, the frame
+/// delay and the render loop. The confirmed latency is what it takes until the remote peer's input for the frame
+/// arrived too, so the frame can no longer be rolled back, and grows with the round trip time.
+#[derive(Default)]
+pub struct LatencyProbe {
+    /// a press that has not been sampled by a simulation tick yet
+    press: Option<Instant>,
+    /// sampled presses with the frame that shows their effect first, and whether it was rendered yet
+    in_flight: Vec<(Frame, Instant, bool)>,
+    rendered: Vec<Duration>,
+    confirmed: Vec<Duration>,
+}
+
+impl LatencyProbe {
+    pub fn key_pressed(&mut self) {
+        if self.press.is_none() {
+            self.press = Some(Instant::now());
+        }
+    }
+
+    /// Call once an input was accepted by the session. `visible_frame` is the first game state frame containing it.
+    pub fn input_sent(&mut self, visible_frame: Frame) {
+        if let Some(press) = self.press.take() {
+            self.in_flight.push((visible_frame, press, false));
+        }
+    }
+
+    pub fn frame_rendered(&mut self, frame: Frame) {
+        for (visible_frame, press, rendered) in self.in_flight.iter_mut() {
+            if !*rendered && *visible_frame <= frame {
+                self.rendered.push(press.elapsed());
+                *rendered = true;
+            }
+        }
+    }
+
+    /// Call with the newest confirmed game state frame.
+    pub fn frame_confirmed(&mut self, frame: Frame) {
+        let confirmed = &mut self.confirmed;
+        self.in_flight.retain(|&(visible_frame, press, _)| {
+            if visible_frame <= frame {
+                confirmed.push(press.elapsed());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// A summary line for each latency measured so far, rendered first.
+    pub fn reports(&self) -> Vec<String> {
+        let mut reports = Vec::new();
+        if !self.rendered.is_empty() {
+            reports.push(summary("Input to rendered", &self.rendered));
+        }
+        if !self.confirmed.is_empty() {
+            reports.push(summary("Input to confirmed", &self.confirmed));
+        }
+        reports
+    }
+}
+
+fn summary(name: &str, samples: &[Duration]) -> String {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+    format!(
+        "{} (n={}): min {:.1}ms, median {:.1}ms, p95 {:.1}ms, max {:.1}ms",
+        name,
+        sorted.len(),
+        percentile(0),
+        percentile(50),
+        percentile(95),
+        percentile(100)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_are_measured_until_rendered_and_confirmed() {
+        let mut probe = LatencyProbe::default();
+        probe.key_pressed();
+        probe.input_sent(10);
+        probe.frame_rendered(9);
+        assert!(probe.reports().is_empty());
+        probe.frame_rendered(10);
+        probe.frame_rendered(11);
+        assert_eq!(probe.reports().len(), 1);
+        assert!(probe.reports()[0].starts_with("Input to rendered (n=1)"));
+        probe.frame_confirmed(10);
+        assert!(probe.reports()[1].starts_with("Input to confirmed (n=1)"));
+        assert!(probe.in_flight.is_empty());
+    }
+}
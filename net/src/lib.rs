@@ -0,0 +1,10 @@
+//! Session and transport helpers for GGRS games: what the peers exchange besides GGRS itself, how to test them
+//! under bad network conditions, and how to tell whether they stayed in sync.
+
+pub mod desync;
+pub mod feed;
+pub mod latency;
+pub mod lossy_link;
+pub mod metrics;
+pub mod session;
+pub mod side_channel;
@@ -0,0 +1,234 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Length of one tick of the link, i.e. how often `pump` should be called.
+pub const TICK: Duration = Duration::from_millis(1);
+/// Average time between two outages in chaos mode.
+const OUTAGE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the link treats packets passing through it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkConditions {
+    /// one-way delay added to every packet
+    pub lag: Duration,
+    /// random extra delay of up to this much, which also reorders packets
+    pub jitter: Duration,
+    /// chance between 0 and 1 that a packet is dropped
+    pub loss: f64,
+    /// chaos mode: every `OUTAGE_INTERVAL` on average, the link drops everything for a random time up to this long
+    pub max_outage: Option<Duration>,
+}
+
+/// A UDP relay between two peers that delays, reorders and drops packets, to test the netcode under bad network
+/// conditions. This is not an in-memory socket: GGRS 0.2 binds its own `UdpSocket` in `start_p2p` and has no socket
+/// trait to plug one into, so sessions can only be tested over real ports. Instead of sending to each other, the
+/// peers send to the link, which forwards to the other side. Each peer has to use the address of its side's link
+/// socket (`addr_for`) as the remote address.
+///
+/// The link runs on ticks instead of the wall clock: every `pump` is one tick, delays are counted in ticks and every
+/// random decision comes from the seed. Packets arriving in the same ticks are dropped, delayed and reordered the same
+/// way for the same seed. When they arrive is still up to the threads of the sessions, so a whole match through the
+/// link does not play out the same twice.
+pub struct LossyLink {
+    /// the socket each peer talks to
+    sockets: [UdpSocket; 2],
+    /// the real address of the peer behind each side
+    peers: [SocketAddr; 2],
+    conditions: LinkConditions,
+    /// number of `pump` calls so far
+    tick: u64,
+    /// packets waiting for their delivery tick: (delivery tick, receiving side, bytes)
+    in_flight: Vec<(u64, usize, Vec<u8>)>,
+    /// in chaos mode, the tick the next outage starts at
+    next_outage: Option<u64>,
+    /// the tick the current outage ends at
+    outage_end: u64,
+    rng_state: u64,
+}
+
+/// Number of whole ticks in `duration`.
+fn ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() / TICK.as_nanos()) as u64
+}
+
+impl LossyLink {
+    /// Binds the link sockets on `ports` of localhost, relaying between the two peers.
+    pub fn bind(
+        ports: [u16; 2],
+        peers: [SocketAddr; 2],
+        conditions: LinkConditions,
+        seed: u64,
+    ) -> std::io::Result<Self> {
+        let local = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+        Self::bind_addrs([local(ports[0]), local(ports[1])], peers, conditions, seed)
+    }
+
+    /// Binds the link sockets on `addrs`, e.g. to put one side in front of a session and the other on the public
+    /// address a remote peer sends to.
+    pub fn bind_addrs(
+        addrs: [SocketAddr; 2],
+        peers: [SocketAddr; 2],
+        conditions: LinkConditions,
+        seed: u64,
+    ) -> std::io::Result<Self> {
+        let bind = |addr: SocketAddr| -> std::io::Result<UdpSocket> {
+            let socket = UdpSocket::bind(addr)?;
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        };
+        let mut link = Self {
+            sockets: [bind(addrs[0])?, bind(addrs[1])?],
+            peers,
+            conditions: LinkConditions::default(),
+            tick: 0,
+            in_flight: Vec::new(),
+            next_outage: None,
+            outage_end: 0,
+            // xorshift must not start at zero
+            rng_state: seed | 1,
+        };
+        link.set_conditions(conditions);
+        Ok(link)
+    }
+
+    /// The address peer `side` has to send to in order to reach the other peer.
+    pub fn addr_for(&self, side: usize) -> std::io::Result<SocketAddr> {
+        self.sockets[side].local_addr()
+    }
+
+    /// Changes how packets are treated from the next tick on. Packets already in flight keep their delivery tick.
+    pub fn set_conditions(&mut self, conditions: LinkConditions) {
+        self.conditions = conditions;
+        match conditions.max_outage {
+            Some(_) if self.next_outage.is_none() => self.schedule_outage(),
+            Some(_) => {}
+            None => self.next_outage = None,
+        }
+    }
+
+    /// Advances the link by one tick: receives pending packets and delivers the ones that are due. Call this every
+    /// `TICK`. Returns the length of an outage that started during this tick.
+    pub fn pump(&mut self) -> Option<Duration> {
+        self.tick += 1;
+        let mut started_outage = None;
+        if let (Some(next_outage), Some(max_outage)) =
+            (self.next_outage, self.conditions.max_outage)
+        {
+            if self.tick >= next_outage {
+                let outage = self.random_ticks(max_outage);
+                self.outage_end = self.tick + outage;
+                // packets already on their way are lost as well
+                self.in_flight.clear();
+                self.schedule_outage();
+                started_outage = Some(TICK * outage as u32);
+            }
+        }
+        let down = self.tick < self.outage_end;
+
+        let mut buffer = [0u8; 4096];
+        for side in 0..2 {
+            while let Ok((len, from)) = self.sockets[side].recv_from(&mut buffer) {
+                if down || from != self.peers[side] || self.random() < self.conditions.loss {
+                    continue;
+                }
+                let delivery = self.tick
+                    + ticks(self.conditions.lag)
+                    + self.random_ticks(self.conditions.jitter);
+                self.in_flight
+                    .push((delivery, 1 - side, buffer[..len].to_vec()));
+            }
+        }
+
+        let tick = self.tick;
+        let sockets = &self.sockets;
+        let peers = &self.peers;
+        self.in_flight.retain(|(delivery, side, bytes)| {
+            if *delivery > tick {
+                return true;
+            }
+            let _ = sockets[*side].send_to(bytes, peers[*side]);
+            false
+        });
+        started_outage
+    }
+
+    fn schedule_outage(&mut self) {
+        let interval = ticks(OUTAGE_INTERVAL.mul_f64(0.5 + self.random()));
+        self.next_outage = Some(self.tick + interval);
+    }
+
+    /// A random number of ticks up to `max`.
+    fn random_ticks(&mut self, max: Duration) -> u64 {
+        (ticks(max) as f64 * self.random()) as u64
+    }
+
+    /// A pseudo-random number in `[0, 1)`, see https://en.wikipedia.org/wiki/Xorshift
+    fn random(&mut self) -> f64 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        (self.rng_state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Sends numbered packets through a link from side 0 to side 1, pumping it once after every few packets, and
+    /// returns the numbers in the order they were delivered.
+    fn relay(port: u16, seed: u64) -> Vec<u8> {
+        let peers = [
+            UdpSocket::bind(addr(port)).unwrap(),
+            UdpSocket::bind(addr(port + 1)).unwrap(),
+        ];
+        peers[1].set_nonblocking(true).unwrap();
+        let conditions = LinkConditions {
+            lag: Duration::from_millis(5),
+            jitter: Duration::from_millis(20),
+            loss: 0.3,
+            max_outage: None,
+        };
+        let mut link = LossyLink::bind(
+            [port + 2, port + 3],
+            [addr(port), addr(port + 1)],
+            conditions,
+            seed,
+        )
+        .unwrap();
+        let mut delivered = Vec::new();
+        let mut buffer = [0u8; 16];
+        for number in 0..100u8 {
+            peers[0].send_to(&[number], addr(port + 2)).unwrap();
+            if number % 4 == 3 {
+                // the link runs on ticks, waiting only makes sure the packets reached its socket
+                thread::sleep(Duration::from_millis(5));
+                link.pump();
+            }
+        }
+        for _ in 0..50 {
+            link.pump();
+        }
+        thread::sleep(Duration::from_millis(50));
+        while let Ok((len, from)) = peers[1].recv_from(&mut buffer) {
+            assert_eq!((len, from), (1, addr(port + 3)));
+            delivered.push(buffer[0]);
+        }
+        delivered
+    }
+
+    #[test]
+    fn same_seed_same_delivery() {
+        let delivered = relay(47410, 7);
+        assert!(delivered.len() > 40 && delivered.len() < 95);
+        // jitter reorders packets
+        assert!(delivered.windows(2).any(|pair| pair[0] > pair[1]));
+        assert_eq!(relay(47420, 7), delivered);
+        assert_ne!(relay(47430, 8), delivered);
+    }
+}
@@ -0,0 +1,253 @@
+use ggrs::GGRSRequest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the histogram buckets, values above the last bound go into an overflow bucket.
+const BUCKET_BOUNDS: [f64; 12] = [
+    1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0,
+];
+
+/// Distribution of a measured value, with power of two buckets.
+#[derive(Serialize, Clone, Debug)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    /// number of samples up to each bound of `BUCKET_BOUNDS`, plus one bucket for larger samples
+    pub buckets: Vec<u64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            buckets: vec![0; BUCKET_BOUNDS.len() + 1],
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Bursts are summed up per window of this length, to find the seconds in which rollbacks cost the most.
+const BURST_WINDOW: Duration = Duration::from_secs(1);
+
+/// The requests of a single `advance_frame` call, which are handled in one go.
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Burst {
+    pub loads: u32,
+    pub saves: u32,
+    pub advances: u32,
+}
+
+impl Burst {
+    pub fn of(requests: &[GGRSRequest]) -> Self {
+        let mut burst = Self::default();
+        for request in requests {
+            match request {
+                GGRSRequest::LoadGameState { .. } => burst.loads += 1,
+                GGRSRequest::SaveGameState { .. } => burst.saves += 1,
+                GGRSRequest::AdvanceFrame { .. } => burst.advances += 1,
+            }
+        }
+        burst
+    }
+
+    pub fn requests(&self) -> u32 {
+        self.loads + self.saves + self.advances
+    }
+}
+
+/// The cost of the bursts within one `BURST_WINDOW`.
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct BurstWindow {
+    /// the burst that took longest, and how long it took
+    pub worst: Burst,
+    pub worst_ms: f64,
+    /// time spent in bursts that rolled back
+    pub rollback_ms: f64,
+}
+
+/// Runtime counters and histograms of a session, dumped to JSON for later analysis.
+pub struct Metrics {
+    start: Instant,
+    counters: BTreeMap<&'static str, u64>,
+    histograms: BTreeMap<&'static str, Histogram>,
+    /// start and bursts of the current window
+    burst_window: (Instant, BurstWindow),
+    /// the last complete window
+    last_burst_window: Option<BurstWindow>,
+    /// the complete window with the longest burst
+    worst_burst_window: Option<BurstWindow>,
+}
+
+/// What gets written to the JSON file.
+#[derive(Serialize)]
+struct MetricsDump<'a> {
+    elapsed_secs: f64,
+    rollbacks_per_second: f64,
+    worst_burst_window: Option<BurstWindow>,
+    counters: &'a BTreeMap<&'static str, u64>,
+    histograms: &'a BTreeMap<&'static str, Histogram>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            counters: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+            burst_window: (start, BurstWindow::default()),
+            last_burst_window: None,
+            worst_burst_window: None,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn increment(&mut self, counter: &'static str, by: u64) {
+        *self.counters.entry(counter).or_insert(0) += by;
+    }
+
+    pub fn record(&mut self, histogram: &'static str, value: f64) {
+        self.histograms.entry(histogram).or_default().record(value);
+    }
+
+    /// The current value of a counter, 0 if it was never incremented.
+    pub fn counter(&self, counter: &str) -> u64 {
+        self.counters.get(counter).copied().unwrap_or(0)
+    }
+
+    /// A histogram, `None` if nothing was recorded in it.
+    pub fn histogram(&self, histogram: &str) -> Option<&Histogram> {
+        self.histograms.get(histogram)
+    }
+
+    /// Counts the frames, rollbacks and resimulated frames of the requests of one `advance_frame` call.
+    pub fn record_requests(&mut self, requests: &[GGRSRequest]) {
+        let advanced = requests
+            .iter()
+            .filter(|request| matches!(request, GGRSRequest::AdvanceFrame { .. }))
+            .count();
+        self.increment("frames", 1);
+        if requests
+            .iter()
+            .any(|request| matches!(request, GGRSRequest::LoadGameState { .. }))
+        {
+            // all but the newest frame are simulated again
+            let resimulated = advanced.saturating_sub(1);
+            self.increment("rollbacks", 1);
+            self.increment("resimulated_frames", resimulated as u64);
+            self.record("rollback_length_frames", resimulated as f64);
+        }
+    }
+
+    /// Records how long handling the requests of one `advance_frame` call took, as measured at `now`. Every
+    /// `BURST_WINDOW`, the longest burst and the total time spent rolling back are recorded as well, the worst case
+    /// per second is what a frame budget has to allow for.
+    pub fn record_burst(&mut self, burst: Burst, elapsed: Duration, now: Instant) {
+        let ms = elapsed.as_nanos() as f64 / 1e6;
+        self.record("burst_requests", burst.requests() as f64);
+        self.record("burst_ms", ms);
+        if burst.loads > 0 {
+            self.record("rollback_burst_ms", ms);
+        }
+        let (window_start, mut window) = self.burst_window;
+        if ms > window.worst_ms {
+            window.worst = burst;
+            window.worst_ms = ms;
+        }
+        if burst.loads > 0 {
+            window.rollback_ms += ms;
+        }
+        if now.saturating_duration_since(window_start) < BURST_WINDOW {
+            self.burst_window = (window_start, window);
+            return;
+        }
+        self.record("worst_burst_ms_per_second", window.worst_ms);
+        self.record("rollback_ms_per_second", window.rollback_ms);
+        if self
+            .worst_burst_window
+            .is_none_or(|worst| window.worst_ms > worst.worst_ms)
+        {
+            self.worst_burst_window = Some(window);
+        }
+        self.last_burst_window = Some(window);
+        self.burst_window = (now, BurstWindow::default());
+    }
+
+    /// The bursts of the last complete `BURST_WINDOW`, e.g. to show them while playing.
+    pub fn last_burst_window(&self) -> Option<BurstWindow> {
+        self.last_burst_window
+    }
+
+    pub fn write_json(&self, path: &Path) -> anyhow::Result<()> {
+        let elapsed_secs = self.start.elapsed().as_secs_f64();
+        let rollbacks = self.counters.get("rollbacks").copied().unwrap_or(0);
+        let dump = MetricsDump {
+            elapsed_secs,
+            rollbacks_per_second: rollbacks as f64 / elapsed_secs.max(f64::EPSILON),
+            worst_burst_window: self.worst_burst_window,
+            counters: &self.counters,
+            histograms: &self.histograms,
+        };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &dump)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_are_summed_up_per_window() {
+        let mut metrics = Metrics::default();
+        let start = metrics.burst_window.0;
+        let rollback = Burst {
+            loads: 1,
+            saves: 4,
+            advances: 4,
+        };
+        let frame = Burst {
+            loads: 0,
+            saves: 1,
+            advances: 1,
+        };
+        metrics.record_burst(rollback, Duration::from_millis(3), start);
+        metrics.record_burst(frame, Duration::from_millis(5), start);
+        metrics.record_burst(rollback, Duration::from_millis(2), start);
+        assert_eq!(metrics.last_burst_window(), None);
+        metrics.record_burst(frame, Duration::from_millis(1), start + BURST_WINDOW);
+        assert_eq!(
+            metrics.last_burst_window(),
+            Some(BurstWindow {
+                worst: frame,
+                worst_ms: 5.0,
+                rollback_ms: 5.0,
+            })
+        );
+        assert_eq!(metrics.histogram("burst_requests").unwrap().max, 9.0);
+        assert_eq!(metrics.histogram("rollback_burst_ms").unwrap().count, 2);
+    }
+}
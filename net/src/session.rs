@@ -0,0 +1,214 @@
+//! Setting up GGRS sessions. Every binary used to repeat the same calls to add its players, spectators and frame
+//! delay; `SessionBuilder` collects those settings, checks them before a socket is bound and starts the session.
+//! GGRS 0.2 saves every frame, sparse saving only came with later versions, so there is no setting for it.
+
+use ggrs::{P2PSession, P2PSpectatorSession, PlayerHandle, PlayerType};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The settings of a session, from which either side of it can be started: `start_p2p` for a peer, `start_spectator`
+/// for a spectator of a peer.
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    num_players: usize,
+    input_size: usize,
+    local_handles: Vec<PlayerHandle>,
+    remotes: Vec<(PlayerHandle, SocketAddr)>,
+    spectators: Vec<SocketAddr>,
+    frame_delay: u32,
+    disconnect_timeout: Option<Duration>,
+    disconnect_notify_delay: Option<Duration>,
+}
+
+impl SessionBuilder {
+    /// `input_size` is the size of the input of a single peer, i.e. of all its local players together.
+    pub fn new(num_players: usize, input_size: usize) -> Self {
+        Self {
+            num_players,
+            input_size,
+            local_handles: Vec::new(),
+            remotes: Vec::new(),
+            spectators: Vec::new(),
+            frame_delay: 0,
+            disconnect_timeout: None,
+            disconnect_notify_delay: None,
+        }
+    }
+
+    pub fn local_player(mut self, handle: PlayerHandle) -> Self {
+        self.local_handles.push(handle);
+        self
+    }
+
+    pub fn remote_player(mut self, handle: PlayerHandle, addr: SocketAddr) -> Self {
+        self.remotes.push((handle, addr));
+        self
+    }
+
+    /// Spectators get the handles after the players', in the order they are added.
+    pub fn spectator(mut self, addr: SocketAddr) -> Self {
+        self.spectators.push(addr);
+        self
+    }
+
+    /// Input delay of every local player.
+    pub fn frame_delay(mut self, frame_delay: u32) -> Self {
+        self.frame_delay = frame_delay;
+        self
+    }
+
+    /// How long a peer may stay silent before it is disconnected. GGRS' default if not set.
+    pub fn disconnect_timeout(mut self, timeout: Duration) -> Self {
+        self.disconnect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a peer may stay silent before `NetworkInterrupted` is reported. GGRS' default if not set.
+    pub fn disconnect_notify_delay(mut self, delay: Duration) -> Self {
+        self.disconnect_notify_delay = Some(delay);
+        self
+    }
+
+    /// Checks that every player handle is taken exactly once, so mistakes are reported before binding a socket instead
+    /// of as a GGRS error halfway through the setup.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let handles = self
+            .local_handles
+            .iter()
+            .chain(self.remotes.iter().map(|(handle, _)| handle));
+        let mut taken = vec![false; self.num_players];
+        for &handle in handles {
+            match taken.get_mut(handle) {
+                Some(true) => anyhow::bail!("player {} was added twice", handle),
+                Some(taken) => *taken = true,
+                None => anyhow::bail!(
+                    "player {} does not exist in a session of {} players",
+                    handle,
+                    self.num_players
+                ),
+            }
+        }
+        if let Some(missing) = taken.iter().position(|taken| !taken) {
+            anyhow::bail!("player {} was not added", missing);
+        }
+        if self.local_handles.is_empty() {
+            anyhow::bail!("a peer needs at least one local player");
+        }
+        Ok(())
+    }
+
+    /// Binds `port` and starts the session of a peer.
+    pub fn start_p2p(&self, port: u16) -> anyhow::Result<P2PSession> {
+        self.validate()?;
+        let mut sess = ggrs::start_p2p_session(self.num_players as u32, self.input_size, port)?;
+        for &handle in &self.local_handles {
+            sess.add_player(PlayerType::Local, handle)?;
+        }
+        for &(handle, addr) in &self.remotes {
+            sess.add_player(PlayerType::Remote(addr), handle)?;
+        }
+        for (i, &addr) in self.spectators.iter().enumerate() {
+            sess.add_player(PlayerType::Spectator(addr), self.num_players + i)?;
+        }
+        for &handle in &self.local_handles {
+            sess.set_frame_delay(self.frame_delay, handle)?;
+        }
+        if let Some(timeout) = self.disconnect_timeout {
+            sess.set_disconnect_timeout(timeout);
+        }
+        if let Some(delay) = self.disconnect_notify_delay {
+            sess.set_disconnect_notify_delay(delay);
+        }
+        sess.start_session()?;
+        Ok(sess)
+    }
+
+    /// Binds `port` and starts spectating the peer at `host_addr`. Only the number of players and the input size
+    /// apply to spectators, the players and timeouts are the host's business.
+    pub fn start_spectator(
+        &self,
+        port: u16,
+        host_addr: SocketAddr,
+    ) -> anyhow::Result<P2PSpectatorSession> {
+        let mut sess = ggrs::start_p2p_spectator_session(
+            self.num_players as u32,
+            self.input_size,
+            port,
+            host_addr,
+        )?;
+        sess.start_session()?;
+        Ok(sess)
+    }
+}
+
+/// Delays between attempts to start a session again, e.g. after a spectator lost its host. The delay doubles with every
+/// failed attempt, up to `max`, so a host that is gone for good is not flooded with sync requests.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    /// The delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over with the initial delay, once an attempt succeeded.
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn every_player_is_added_once() {
+        let builder = SessionBuilder::new(2, 4).local_player(0);
+        assert!(builder.validate().is_err());
+        assert!(builder.clone().local_player(0).validate().is_err());
+        assert!(builder
+            .clone()
+            .remote_player(2, addr(7001))
+            .validate()
+            .is_err());
+        assert!(SessionBuilder::new(2, 4)
+            .remote_player(0, addr(7000))
+            .remote_player(1, addr(7001))
+            .validate()
+            .is_err());
+        let builder = builder.remote_player(1, addr(7001)).spectator(addr(7002));
+        assert!(builder.validate().is_ok());
+        // port 0 lets the OS pick a free port
+        assert!(builder.frame_delay(2).start_p2p(0).is_ok());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(3));
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,240 @@
+use ggrs::Frame;
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Messages exchanged next to the GGRS session.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SideMessage {
+    Chat(String),
+    /// the checksum of a confirmed frame, to detect desyncs
+    Checksum {
+        frame: Frame,
+        checksum: u64,
+    },
+    /// the name of a player, sent to the peer until it answers with `NameReceived`, see `NameAnnouncement`
+    PlayerName {
+        handle: usize,
+        name: String,
+    },
+    /// the answer to every `PlayerName` of `handle`
+    NameReceived {
+        handle: usize,
+    },
+    /// the player is quitting, so the peer can disconnect it right away instead of waiting for a timeout
+    Leaving {
+        handle: usize,
+    },
+    /// new gameplay constants, encoded by the game, for both peers and their listeners to use from `frame` on. Sent
+    /// repeatedly until that frame, as any single message may be lost.
+    Tuning {
+        frame: Frame,
+        tuning: Vec<u8>,
+    },
+}
+
+/// Unless configured otherwise, a client's side channel runs this far above its GGRS port. Peers on adjacent ports,
+/// as in most local tests, then do not take each other's GGRS port.
+pub const PORT_OFFSET: u16 = 100;
+
+/// The largest payload of a UDP datagram. Messages are received into a buffer of this size, so a long chat message or
+/// player name is never cut off.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+/// A player name the peer has not answered yet is sent again this often.
+const NAME_RETRY_PERIOD: Duration = Duration::from_secs(1);
+
+/// Out-of-band messaging over its own UDP socket, next to the GGRS socket, on `default_port` unless configured
+/// otherwise. Delivery is best effort, like the UDP it runs on.
+pub struct SideChannel {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    /// clients that only receive, see `add_listener`
+    listeners: Vec<SocketAddr>,
+    /// reused by every `receive`, it is too large for the stack
+    buffer: Box<[u8]>,
+}
+
+impl SideChannel {
+    /// Binds the side channel socket on `port`.
+    pub fn bind(port: u16) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            peers: Vec::new(),
+            listeners: Vec::new(),
+            buffer: vec![0; MAX_DATAGRAM_SIZE].into_boxed_slice(),
+        })
+    }
+
+    /// Adds a peer by the address of its side channel.
+    pub fn add_peer(&mut self, addr: SocketAddr) {
+        self.peers.push(addr);
+    }
+
+    /// Adds a client by the address of its side channel that only gets what is passed to `send_to_listeners`, e.g. a
+    /// spectator that is shown the chat. Messages from it are dropped, it cannot take part.
+    pub fn add_listener(&mut self, addr: SocketAddr) {
+        self.listeners.push(addr);
+    }
+
+    pub fn send(&self, message: &SideMessage) {
+        send_to(&self.socket, &self.peers, message);
+    }
+
+    pub fn send_to_listeners(&self, message: &SideMessage) {
+        send_to(&self.socket, &self.listeners, message);
+    }
+
+    /// Returns all messages received from peers since the last call. Malformed ones are skipped, and so is anything
+    /// from listeners or strangers, who must not disconnect players or change the tuning of one side only.
+    pub fn receive(&mut self) -> Vec<SideMessage> {
+        let mut messages = Vec::new();
+        while let Ok((len, sender)) = self.socket.recv_from(&mut self.buffer) {
+            if !self.peers.contains(&sender) {
+                continue;
+            }
+            if let Ok(message) = bincode::deserialize(&self.buffer[..len]) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+}
+
+fn send_to(socket: &UdpSocket, addrs: &[SocketAddr], message: &SideMessage) {
+    let bytes = bincode::serialize(message).unwrap();
+    for addr in addrs {
+        // a lost message is not worth interrupting the game for
+        let _ = socket.send_to(&bytes, addr);
+    }
+}
+
+/// Tells the peers the name of a local player: once, and again every `NAME_RETRY_PERIOD` until they answer with
+/// `NameReceived`, as either message may be lost.
+pub struct NameAnnouncement {
+    handle: usize,
+    name: String,
+    last_sent: Option<Instant>,
+    received: bool,
+}
+
+impl NameAnnouncement {
+    pub fn new(handle: usize, name: String) -> Self {
+        Self {
+            handle,
+            name,
+            last_sent: None,
+            received: false,
+        }
+    }
+
+    /// Sends the name if it is due. Call this regularly once the session is connected.
+    pub fn poll(&mut self, channel: &SideChannel, now: Instant) {
+        let due = self
+            .last_sent
+            .map_or(true, |sent| now.duration_since(sent) >= NAME_RETRY_PERIOD);
+        if !self.received && due {
+            channel.send(&SideMessage::PlayerName {
+                handle: self.handle,
+                name: self.name.clone(),
+            });
+            self.last_sent = Some(now);
+        }
+    }
+
+    /// Handles a `NameReceived` from a peer, which stops the announcement if it answers this name.
+    pub fn answered(&mut self, handle: usize) {
+        self.received |= handle == self.handle;
+    }
+}
+
+/// The side channel port of a client whose GGRS session runs on `ggrs_port`, if not configured otherwise.
+pub fn default_port(ggrs_port: u16) -> u16 {
+    ggrs_port.wrapping_add(PORT_OFFSET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn listeners_only_receive() {
+        let mut host = SideChannel::bind(47310).unwrap();
+        host.add_listener(addr(47320));
+        let mut listener = SideChannel::bind(47320).unwrap();
+        listener.add_peer(addr(47310));
+
+        let chat = SideMessage::Chat("P0: gg".to_string());
+        host.send(&chat);
+        host.send_to_listeners(&chat);
+        listener.send(&SideMessage::Chat("spectator: hi".to_string()));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(listener.receive(), vec![chat]);
+        assert!(host.receive().is_empty());
+    }
+
+    #[test]
+    fn strangers_are_ignored() {
+        let mut peer = SideChannel::bind(47330).unwrap();
+        peer.add_peer(addr(47340));
+        let mut other = SideChannel::bind(47340).unwrap();
+        other.add_peer(addr(47330));
+        let mut stranger = SideChannel::bind(47350).unwrap();
+        stranger.add_peer(addr(47330));
+
+        stranger.send(&SideMessage::Leaving { handle: 1 });
+        other.send(&SideMessage::Leaving { handle: 1 });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(peer.receive(), vec![SideMessage::Leaving { handle: 1 }]);
+    }
+
+    #[test]
+    fn names_are_sent_until_answered() {
+        let mut announcer = SideChannel::bind(47380).unwrap();
+        announcer.add_peer(addr(47390));
+        let mut peer = SideChannel::bind(47390).unwrap();
+        peer.add_peer(addr(47380));
+        let mut announcement = NameAnnouncement::new(0, "alice".to_string());
+        let name = SideMessage::PlayerName {
+            handle: 0,
+            name: "alice".to_string(),
+        };
+
+        let start = Instant::now();
+        announcement.poll(&announcer, start);
+        announcement.poll(&announcer, start + NAME_RETRY_PERIOD / 2);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(peer.receive(), vec![name.clone()]);
+        announcement.poll(&announcer, start + NAME_RETRY_PERIOD);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(peer.receive(), vec![name]);
+
+        // answers for other players do not count
+        announcement.answered(1);
+        announcement.poll(&announcer, start + NAME_RETRY_PERIOD * 2);
+        announcement.answered(0);
+        announcement.poll(&announcer, start + NAME_RETRY_PERIOD * 3);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(peer.receive().len(), 1);
+    }
+
+    #[test]
+    fn long_messages_arrive_whole() {
+        let mut sender = SideChannel::bind(47360).unwrap();
+        sender.add_peer(addr(47370));
+        let mut receiver = SideChannel::bind(47370).unwrap();
+        receiver.add_peer(addr(47360));
+
+        let chat = SideMessage::Chat(format!("{}: {}", "a".repeat(2000), "🎉".repeat(200)));
+        sender.send(&chat);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(receiver.receive(), vec![chat]);
+    }
+}
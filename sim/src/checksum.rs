@@ -0,0 +1,94 @@
+//! Checksums computed from the fields of the game state, without serializing it first.
+
+/// A value that can be checksummed field by field. Floats are hashed by their bit patterns, so the checksum only
+/// depends on the values and not on how an encoder lays them out, and computing it allocates nothing.
+pub trait Checksum {
+    /// Feeds every field into the hasher, in a fixed order.
+    fn hash_into(&self, hasher: &mut ChecksumHasher);
+
+    fn checksum(&self) -> u64 {
+        let mut hasher = ChecksumHasher::new();
+        self.hash_into(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// 64 bit FNV-1a over the little endian bytes of each field, see <http://www.isthe.com/chongo/tech/comp/fnv/>.
+/// Unlike `std::hash::Hasher` implementations, the result is the same on every platform and Rust version.
+pub struct ChecksumHasher(u64);
+
+impl ChecksumHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        for byte in value.to_le_bytes().iter() {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for ChecksumHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for i32 {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        hasher.write_u64(*self as u32 as u64);
+    }
+}
+
+impl Checksum for usize {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        hasher.write_u64(*self as u64);
+    }
+}
+
+impl Checksum for f64 {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        hasher.write_u64(self.to_bits());
+    }
+}
+
+impl<A: Checksum, B: Checksum> Checksum for (A, B) {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        self.0.hash_into(hasher);
+        self.1.hash_into(hasher);
+    }
+}
+
+/// The length goes first, so moving an element from one list to the next changes the checksum.
+impl<T: Checksum> Checksum for [T] {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        self.len().hash_into(hasher);
+        for element in self {
+            element.hash_into(hasher);
+        }
+    }
+}
+
+impl<T: Checksum> Checksum for Vec<T> {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        self.as_slice().hash_into(hasher);
+    }
+}
+
+impl<A: smallvec::Array> Checksum for smallvec::SmallVec<A>
+where
+    A::Item: Checksum,
+{
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        self.as_slice().hash_into(hasher);
+    }
+}
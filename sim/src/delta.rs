@@ -0,0 +1,89 @@
+//! Delta encoding of serialized states against an earlier one, for saves of large states that change little per
+//! frame. A delta is the length of the new state followed by runs of changed bytes, each written as the number of
+//! unchanged bytes before it, its length and its bytes. All numbers are little endian `u32`s.
+
+/// Appends the delta turning `base` into `state` to `out`.
+pub fn encode_into(base: &[u8], state: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(state.len() as u32).to_le_bytes());
+    let changed = |i: usize| base.get(i) != Some(&state[i]);
+    let mut unchanged_from = 0;
+    let mut i = 0;
+    while i < state.len() {
+        if !changed(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < state.len() && changed(i) {
+            i += 1;
+        }
+        out.extend_from_slice(&((start - unchanged_from) as u32).to_le_bytes());
+        out.extend_from_slice(&((i - start) as u32).to_le_bytes());
+        out.extend_from_slice(&state[start..i]);
+        unchanged_from = i;
+    }
+}
+
+/// Applies a delta made by `encode_into` to `base`, writing the state to `out`. Returns `None` if the delta is
+/// malformed, e.g. its runs do not fit in the length of the state, or was made against a shorter base.
+pub fn decode_into(base: &[u8], mut delta: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    fn read_u32(delta: &mut &[u8]) -> Option<usize> {
+        let (bytes, rest) = (delta.get(..4)?, delta.get(4..)?);
+        *delta = rest;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+    out.clear();
+    let len = read_u32(&mut delta)?;
+    while !delta.is_empty() {
+        let unchanged = read_u32(&mut delta)?;
+        let changed = read_u32(&mut delta)?;
+        out.extend_from_slice(base.get(out.len()..out.len() + unchanged)?);
+        out.extend_from_slice(delta.get(..changed)?);
+        delta = &delta[changed..];
+    }
+    if out.len() > len {
+        return None;
+    }
+    // the runs stop at the last changed byte, the unchanged bytes after it are copied from the base
+    if out.len() < len {
+        out.extend_from_slice(base.get(out.len()..len)?);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn delta_round_trip(base in vec(0u8..4, 0..64), state in vec(0u8..4, 0..64)) {
+            let mut delta = Vec::new();
+            encode_into(&base, &state, &mut delta);
+            let mut decoded = Vec::new();
+            prop_assert!(decode_into(&base, &delta, &mut decoded).is_some());
+            prop_assert_eq!(decoded, state);
+        }
+    }
+
+    #[test]
+    fn runs_past_the_length_are_malformed() {
+        let base = vec![0; 8];
+        let mut delta = Vec::new();
+        encode_into(&base, &[0, 0, 0, 0, 0, 1], &mut delta);
+        let mut decoded = Vec::new();
+        assert!(decode_into(&base, &delta, &mut decoded).is_some());
+        delta[..4].copy_from_slice(&5u32.to_le_bytes());
+        assert!(decode_into(&base, &delta, &mut decoded).is_none());
+    }
+
+    #[test]
+    fn unchanged_state_is_just_its_length() {
+        let state = vec![7; 100];
+        let mut delta = Vec::new();
+        encode_into(&state, &state, &mut delta);
+        assert_eq!(delta, 100u32.to_le_bytes());
+    }
+}
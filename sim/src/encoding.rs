@@ -0,0 +1,72 @@
+//! The bincode configuration of everything that outlives a single run: saved states, keyframes and replays.
+//! It is spelled out instead of relying on the defaults of `bincode::serialize`, so a dependency upgrade that changes
+//! them cannot silently change the encoding. The options are the ones `bincode::serialize` used when the first
+//! replays were written, plus a size limit, so those replays stay readable.
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Nothing encoded with these options gets anywhere near this size. Corrupted lengths, e.g. in a truncated replay,
+/// fail with an error instead of trying to allocate gigabytes.
+pub const MAX_ENCODED_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Little endian, fixed size integers and lengths, trailing bytes allowed.
+pub fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_limit(MAX_ENCODED_SIZE)
+        .with_little_endian()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+pub fn serialize<T: Serialize + ?Sized>(value: &T) -> bincode::Result<Vec<u8>> {
+    options().serialize(value)
+}
+
+pub fn serialize_into<W: Write, T: Serialize + ?Sized>(
+    writer: W,
+    value: &T,
+) -> bincode::Result<()> {
+    options().serialize_into(writer, value)
+}
+
+pub fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> bincode::Result<T> {
+    options().deserialize(bytes)
+}
+
+pub fn deserialize_from<R: Read, T: DeserializeOwned>(reader: R) -> bincode::Result<T> {
+    options().deserialize_from(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_is_stable() {
+        let value: (u8, i32, Vec<u16>, Option<f64>) = (1, -2, vec![3, 4], Some(0.5));
+        let bytes = serialize(&value).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                1, 254, 255, 255, 255, 2, 0, 0, 0, 0, 0, 0, 0, 3, 0, 4, 0, 1, 0, 0, 0, 0, 0, 0,
+                224, 63
+            ]
+        );
+        // the encoding of the first replays
+        assert_eq!(bytes, bincode::serialize(&value).unwrap());
+        assert_eq!(
+            deserialize::<(u8, i32, Vec<u16>, Option<f64>)>(&bytes).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn huge_lengths_are_rejected() {
+        let mut bytes = u64::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(deserialize::<Vec<u8>>(&bytes).is_err());
+    }
+}
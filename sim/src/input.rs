@@ -0,0 +1,69 @@
+//! The wire format of `Input`, used for everything the peers send to each other, replays and input recordings.
+//! It is written by hand instead of with bincode, so the encoded size is part of the types: `encode` returns exactly
+//! `INPUT_SIZE` bytes and `decode` takes them apart again, and changing one without the other does not compile.
+//!
+//! | byte | content                                                               |
+//! |------|-----------------------------------------------------------------------|
+//! | 0    | `InputFlags` bits: 0 up, 1 down, 2 left, 3 right, 4 dash              |
+//! | 1    | `stick_x` as a two's complement `i8`, between -4 and 4                |
+//! | 2    | `stick_y` as a two's complement `i8`, between -4 and 4                |
+//!
+//! These are the same bytes bincode produced for `Input` before, so existing replays and recordings still load.
+
+use crate::{fletcher16, Input, InputFlags, ANALOG_STEPS};
+use std::convert::TryInto;
+
+/// Size of a single player's encoded `Input`. A peer sends one of these per local player.
+pub const INPUT_SIZE: usize = 3;
+/// Describes the encoding. Update it whenever the input changes, so old replays are recognized.
+const LAYOUT: &str = "buttons:u8(up,down,left,right,dash) stick_x:i8 stick_y:i8";
+
+/// Encodes the input of a single player.
+pub fn encode(input: Input) -> [u8; INPUT_SIZE] {
+    [
+        input.buttons.bits(),
+        input.stick_x as u8,
+        input.stick_y as u8,
+    ]
+}
+
+/// Decodes the input of a single player, as produced by `encode`. Bytes after the first `INPUT_SIZE` are ignored.
+/// Inputs come from the network, so corrupted or hostile bytes must not crash the game: unknown buttons are dropped,
+/// stick values are clamped to the valid range and inputs that are too short count as no input at all.
+pub fn decode(bytes: &[u8]) -> Input {
+    let encoded: [u8; INPUT_SIZE] = match bytes.get(..INPUT_SIZE).map(|bytes| bytes.try_into()) {
+        Some(Ok(encoded)) => encoded,
+        _ => return Input::default(),
+    };
+    let [buttons, stick_x, stick_y] = encoded;
+    Input {
+        buttons: InputFlags::from_bits_truncate(buttons),
+        stick_x: (stick_x as i8).clamp(-ANALOG_STEPS, ANALOG_STEPS),
+        stick_y: (stick_y as i8).clamp(-ANALOG_STEPS, ANALOG_STEPS),
+    }
+}
+
+/// A hash of the input encoding, see `LAYOUT`.
+pub fn layout_hash() -> u64 {
+    fletcher16(LAYOUT.as_bytes()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn valid_inputs_round_trip(buttons in 0u8..32, stick_x in -ANALOG_STEPS..=ANALOG_STEPS, stick_y in -ANALOG_STEPS..=ANALOG_STEPS) {
+            let input = Input { buttons: InputFlags::from_bits_truncate(buttons), stick_x, stick_y };
+            prop_assert_eq!(decode(&encode(input)), input);
+        }
+
+        #[test]
+        fn any_bytes_decode_to_a_valid_input(bytes in proptest::collection::vec(any::<u8>(), 0..8)) {
+            let input = decode(&bytes);
+            prop_assert_eq!(decode(&encode(input)), input);
+        }
+    }
+}
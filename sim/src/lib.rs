@@ -0,0 +1,748 @@
+//! The deterministic simulation of the box game: the game state, how inputs advance it and how it is checksummed.
+//! Everything here has to produce bit-identical results on every peer, so it must not depend on anything but the
+//! state and the inputs of a frame.
+
+use bitflags::bitflags;
+use ggrs::Frame;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+mod checksum;
+pub mod delta;
+pub mod encoding;
+pub mod input;
+pub mod snapshot;
+pub mod tuning;
+pub mod versioned;
+
+pub use checksum::{Checksum, ChecksumHasher};
+pub use input::INPUT_SIZE;
+pub use tuning::Tuning;
+
+pub const FPS: u64 = 60;
+/// Games with up to this many boxes keep their state inline, without any heap allocations. Larger ones still work,
+/// their per-player fields spill to the heap.
+pub const MAX_INLINE_PLAYERS: usize = 8;
+
+pub const PLAYER_SIZE: f64 = 50.0;
+/// Size of the arena the boxes are confined to.
+pub const ARENA_WIDTH: u32 = 600;
+pub const ARENA_HEIGHT: u32 = 800;
+
+bitflags! {
+    /// The buttons held by a player during a frame.
+    #[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+    pub struct InputFlags: u8 {
+        const UP = 1 << 0;
+        const DOWN = 1 << 1;
+        const LEFT = 1 << 2;
+        const RIGHT = 1 << 3;
+        const DASH = 1 << 4;
+    }
+}
+
+impl InputFlags {
+    /// 1 when thrusting forward, -1 when braking, 0 when neither or both of UP and DOWN are held.
+    pub fn thrusting(self) -> i8 {
+        self.axis(Self::UP, Self::DOWN)
+    }
+
+    /// 1 when turning right, -1 when turning left, 0 when neither or both of LEFT and RIGHT are held.
+    pub fn turning(self) -> i8 {
+        self.axis(Self::RIGHT, Self::LEFT)
+    }
+
+    fn axis(self, positive: Self, negative: Self) -> i8 {
+        match (self.contains(positive), self.contains(negative)) {
+            (true, false) => 1,
+            (false, true) => -1,
+            _ => 0,
+        }
+    }
+}
+
+/// Number of discrete steps an analog stick axis is quantized to in each direction.
+pub const ANALOG_STEPS: i8 = 4;
+/// Stick deflections below this magnitude are treated as centered.
+const ANALOG_DEADZONE: f64 = 0.2;
+
+const DASH_SPEED: f64 = 5.0;
+/// With the `parallel` feature, states with at least this many players are advanced on the rayon thread pool. Below
+/// it, e.g. in a regular match, handing out the work costs more than moving the players.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_PLAYERS: usize = 256;
+/// Players moved per rayon task.
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK: usize = 64;
+
+/// Computes the fletcher16 checksum, copied from wikipedia: <https://en.wikipedia.org/wiki/Fletcher%27s_checksum>
+pub fn fletcher16(data: &[u8]) -> u16 {
+    let mut sum1: u16 = 0;
+    let mut sum2: u16 = 0;
+
+    for &byte in data {
+        sum1 = (sum1 + byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+
+    (sum2 << 8) | sum1
+}
+
+/// Integer hash with good avalanche, see https://nullprogram.com/blog/2018/07/31/
+pub fn hash32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+/// A pseudo-random but reproducible input for the given player and frame, for scripted test runs.
+/// Equal seeds produce equal inputs on every platform, since only integer arithmetic is involved.
+pub fn scripted_input(seed: u32, frame: Frame, player: usize) -> Input {
+    let x = hash32(seed ^ hash32(frame as u32 ^ hash32(player as u32)));
+    let stick = |bits: u32| (bits % (2 * ANALOG_STEPS as u32 + 1)) as i8 - ANALOG_STEPS;
+    Input {
+        buttons: InputFlags::from_bits_truncate(x as u8),
+        stick_x: stick(x >> 8),
+        stick_y: stick(x >> 16),
+    }
+}
+
+/// `cos` as used by the simulation. It comes from the platform's math library, which does not have to round the same
+/// way on every platform, so the fp-audit feature warns about it.
+fn sim_cos(x: f64) -> f64 {
+    audit_operation("cos");
+    x.cos()
+}
+
+/// `sin` as used by the simulation, see `sim_cos`.
+fn sim_sin(x: f64) -> f64 {
+    audit_operation("sin");
+    x.sin()
+}
+
+/// Warns once per operation that its result may differ between platforms.
+#[cfg(feature = "fp-audit")]
+fn audit_operation(operation: &'static str) {
+    static WARNED: std::sync::Mutex<Vec<&str>> = std::sync::Mutex::new(Vec::new());
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.contains(&operation) {
+        warned.push(operation);
+        tracing::warn!(
+            "fp-audit: the simulation uses `{}`, which may differ between platforms. \
+             Use a fixed-point or lookup table implementation for cross-platform matches.",
+            operation
+        );
+    }
+}
+
+#[cfg(not(feature = "fp-audit"))]
+fn audit_operation(_operation: &'static str) {}
+
+/// Whether two boxes centered at the given positions overlap, using their bounding circles.
+pub fn touching(a: (f64, f64), b: (f64, f64)) -> bool {
+    let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+    dx * dx + dy * dy < PLAYER_SIZE * PLAYER_SIZE
+}
+
+/// Pairs of boxes that touch at `positions` but did not at `old_positions`, i.e. that just collided.
+pub fn new_contacts(old_positions: &[(f64, f64)], positions: &[(f64, f64)]) -> Vec<(usize, usize)> {
+    let mut contacts = Vec::new();
+    for i in 0..positions.len() {
+        for j in (i + 1)..positions.len() {
+            if touching(positions[i], positions[j]) && !touching(old_positions[i], old_positions[j])
+            {
+                contacts.push((i, j));
+            }
+        }
+    }
+    contacts
+}
+
+/// Keeps a box position inside the arena.
+pub fn constrain_to_arena((x, y): (f64, f64)) -> (f64, f64) {
+    (
+        x.max(0.0).min(ARENA_WIDTH as f64),
+        y.max(0.0).min(ARENA_HEIGHT as f64),
+    )
+}
+
+/// The input `player` simulates with, given the encoded input of every peer and `None` for disconnected peers.
+pub fn player_input(inputs: &[Option<Vec<u8>>], player: usize, local_players: usize) -> Input {
+    // each peer sends the inputs of all its local players back to back
+    let peer = player / local_players;
+    let slot = player % local_players;
+    // check if the player is disconnected (disconnected players might maybe do something different)
+    if let Some(Some(peer_input)) = inputs.get(peer) {
+        let bytes = peer_input.get(slot * INPUT_SIZE..(slot + 1) * INPUT_SIZE);
+        input::decode(bytes.unwrap_or_default())
+    } else {
+        // disconnected players spin
+        Input {
+            buttons: InputFlags::LEFT,
+            ..Default::default()
+        }
+    }
+}
+
+/// Moves a single box by one frame according to its input.
+pub fn move_player(player: PlayerState, input: Input, tuning: &Tuning) -> PlayerState {
+    let buttons = input.buttons;
+    let movement_speed = tuning.movement_per_frame();
+    let rotation_speed = tuning.rotation_per_frame();
+    let (old_vel_x, old_vel_y) = player.velocity;
+    let mut rot = player.rotation;
+
+    // slow down
+    let mut vel_x = old_vel_x * tuning.friction;
+    let mut vel_y = old_vel_y * tuning.friction;
+
+    // thrust or break
+    let thrust = buttons.thrusting();
+    if thrust != 0 {
+        vel_x += movement_speed * thrust as f64 * sim_cos(rot);
+        vel_y += movement_speed * thrust as f64 * sim_sin(rot);
+    }
+    // dash
+    if buttons.contains(InputFlags::DASH) {
+        vel_x += DASH_SPEED * sim_cos(rot);
+        vel_y += DASH_SPEED * sim_sin(rot);
+    }
+    // turn left or right
+    let turn = buttons.turning();
+    if turn != 0 {
+        rot = (rot + rotation_speed * turn as f64).rem_euclid(2.0 * std::f64::consts::PI);
+    }
+
+    // analog thrust and turning, scaled by the quantized stick deflection (stick up is negative y)
+    if input.stick_y != 0 {
+        let amount = -(input.stick_y as f64) / ANALOG_STEPS as f64;
+        vel_x += movement_speed * amount * sim_cos(rot);
+        vel_y += movement_speed * amount * sim_sin(rot);
+    }
+    if input.stick_x != 0 {
+        let amount = input.stick_x as f64 / ANALOG_STEPS as f64;
+        rot = (rot + rotation_speed * amount).rem_euclid(2.0 * std::f64::consts::PI);
+    }
+
+    // limit speed
+    let magnitude = (vel_x * vel_x + vel_y * vel_y).sqrt();
+    if magnitude > tuning.max_speed {
+        vel_x = (vel_x * tuning.max_speed) / magnitude;
+        vel_y = (vel_y * tuning.max_speed) / magnitude;
+    }
+
+    // compute new position, constrained to the canvas borders
+    let (old_x, old_y) = player.position;
+    PlayerState {
+        position: constrain_to_arena((old_x + vel_x, old_y + vel_y)),
+        velocity: (vel_x, vel_y),
+        rotation: rot,
+    }
+}
+
+/// Moves the players `first..first + positions.len()`, whose values are given as slices starting at `first`.
+fn advance_players(
+    first: usize,
+    positions: &mut [(f64, f64)],
+    velocities: &mut [(f64, f64)],
+    rotations: &mut [f64],
+    inputs: &[Option<Vec<u8>>],
+    local_players: usize,
+    tuning: &Tuning,
+) {
+    for i in 0..positions.len() {
+        let player = PlayerState {
+            position: positions[i],
+            velocity: velocities[i],
+            rotation: rotations[i],
+        };
+        let input = player_input(inputs, first + i, local_players);
+        let player = move_player(player, input, tuning);
+        positions[i] = player.position;
+        velocities[i] = player.velocity;
+        rotations[i] = player.rotation;
+    }
+}
+
+/// Quantizes a raw stick axis value in `[-1.0, 1.0]` to an integer step in `[-ANALOG_STEPS, ANALOG_STEPS]`.
+/// Only the quantized value ever reaches the simulation, so peers never exchange or simulate raw floats.
+/// The computation uses nothing but comparisons, basic arithmetic and `ceil`, which are exactly rounded by IEEE 754,
+/// so native and WASM clients produce identical steps for identical raw values.
+/// Non-finite values (broken drivers) are treated as a centered stick.
+pub fn quantize_axis(value: f64) -> i8 {
+    if !value.is_finite() {
+        return 0;
+    }
+    let value = value.clamp(-1.0, 1.0);
+    let magnitude = value.abs();
+    if magnitude < ANALOG_DEADZONE {
+        return 0;
+    }
+    let scaled = (magnitude - ANALOG_DEADZONE) / (1.0 - ANALOG_DEADZONE) * ANALOG_STEPS as f64;
+    let step = (scaled.ceil() as i8).clamp(1, ANALOG_STEPS);
+    if value < 0.0 {
+        -step
+    } else {
+        step
+    }
+}
+
+/// The input sent over the network for a single player and frame, encoded by the `input` module.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Input {
+    pub buttons: InputFlags,
+    /// quantized left stick axes, see `quantize_axis`
+    pub stick_x: i8,
+    pub stick_y: i8,
+}
+
+/// The simulated values of a single box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerState {
+    pub position: (f64, f64),
+    pub velocity: (f64, f64),
+    pub rotation: f64,
+}
+
+/// One value per box. Serialized like a `Vec`, so states saved before it was introduced still load.
+pub type PerPlayer<T> = SmallVec<[T; MAX_INLINE_PLAYERS]>;
+
+/// BoxGameState holds all relevant information about the game state
+///
+/// The values of the boxes are stored as one array per field, indexed by player handle, so the advance loop, the
+/// checksum and the snapshots walk contiguous memory. All three arrays always have the same length.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BoxGameState {
+    pub frame: i32,
+    pub positions: PerPlayer<(f64, f64)>,
+    pub velocities: PerPlayer<(f64, f64)>,
+    pub rotations: PerPlayer<f64>,
+}
+
+impl BoxGameState {
+    /// Advances the state by one frame, given the encoded input of every peer and `None` for disconnected peers.
+    pub fn advance(&mut self, inputs: &[Option<Vec<u8>>], local_players: usize, tuning: &Tuning) {
+        // increase the frame counter
+        self.frame += 1;
+
+        #[cfg(feature = "parallel")]
+        if self.positions.len() >= PARALLEL_PLAYERS {
+            self.advance_players_parallel(inputs, local_players, tuning);
+            return;
+        }
+        advance_players(
+            0,
+            &mut self.positions,
+            &mut self.velocities,
+            &mut self.rotations,
+            inputs,
+            local_players,
+            tuning,
+        );
+    }
+
+    /// Moves the players in chunks on the rayon thread pool. Every player only reads its own values and its input and
+    /// only writes its own values, so the chunks write disjoint slices and nothing is combined across players: the
+    /// result does not depend on the order the chunks run in and is bit-identical to the serial path. Anything that
+    /// does combine values of several players (e.g. a sum) has to be reduced serially in player order afterwards,
+    /// because a parallel reduction regroups the floating point operations.
+    #[cfg(feature = "parallel")]
+    fn advance_players_parallel(
+        &mut self,
+        inputs: &[Option<Vec<u8>>],
+        local_players: usize,
+        tuning: &Tuning,
+    ) {
+        use rayon::prelude::*;
+        self.positions
+            .par_chunks_mut(PARALLEL_CHUNK)
+            .zip(self.velocities.par_chunks_mut(PARALLEL_CHUNK))
+            .zip(self.rotations.par_chunks_mut(PARALLEL_CHUNK))
+            .enumerate()
+            .for_each(|(chunk, ((positions, velocities), rotations))| {
+                advance_players(
+                    chunk * PARALLEL_CHUNK,
+                    positions,
+                    velocities,
+                    rotations,
+                    inputs,
+                    local_players,
+                    tuning,
+                )
+            });
+    }
+
+    /// Warns about every NaN or infinite value, they spread through the simulation and compare unequal to themselves.
+    #[cfg(feature = "fp-audit")]
+    pub fn audit_finite(&self) {
+        for i in 0..self.positions.len() {
+            let fields = [
+                ("position.x", self.positions[i].0),
+                ("position.y", self.positions[i].1),
+                ("velocity.x", self.velocities[i].0),
+                ("velocity.y", self.velocities[i].1),
+                ("rotation", self.rotations[i]),
+            ];
+            for (field, value) in fields.iter() {
+                if !value.is_finite() {
+                    tracing::warn!(
+                        "fp-audit: player {} {} is {} at frame {}",
+                        i,
+                        field,
+                        value,
+                        self.frame
+                    );
+                }
+            }
+        }
+    }
+
+    /// Describes every field that differs between the two states, with the old value, the new value and the delta.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |field: String, old: f64, new: f64| {
+            if old.to_bits() != new.to_bits() {
+                differences.push(format!(
+                    "{}: {:.6} -> {:.6} ({:+.6})",
+                    field,
+                    old,
+                    new,
+                    new - old
+                ));
+            }
+        };
+        compare("frame".to_string(), self.frame as f64, other.frame as f64);
+        for i in 0..self.positions.len().min(other.positions.len()) {
+            compare(
+                format!("player {} position.x", i),
+                self.positions[i].0,
+                other.positions[i].0,
+            );
+            compare(
+                format!("player {} position.y", i),
+                self.positions[i].1,
+                other.positions[i].1,
+            );
+            compare(
+                format!("player {} velocity.x", i),
+                self.velocities[i].0,
+                other.velocities[i].0,
+            );
+            compare(
+                format!("player {} velocity.y", i),
+                self.velocities[i].1,
+                other.velocities[i].1,
+            );
+            compare(
+                format!("player {} rotation", i),
+                self.rotations[i],
+                other.rotations[i],
+            );
+        }
+        differences
+    }
+
+    pub fn new(num_players: usize) -> Self {
+        let mut positions = PerPlayer::new();
+        let mut velocities = PerPlayer::new();
+        let mut rotations = PerPlayer::new();
+        // spread the players evenly around the center of the canvas
+        let n = num_players as i32;
+        let spacing = ARENA_WIDTH as i32 / (4 * (n - 1).max(1));
+        for i in 0..n {
+            let x = ARENA_WIDTH as i32 / 2 + (2 * i - (n - 1)) * spacing;
+            let y = ARENA_HEIGHT as i32 / 2;
+            positions.push((x as f64, y as f64));
+            velocities.push((0.0, 0.0));
+            rotations.push(0.0);
+        }
+
+        Self {
+            frame: 0,
+            positions,
+            velocities,
+            rotations,
+        }
+    }
+}
+
+impl Checksum for BoxGameState {
+    fn hash_into(&self, hasher: &mut ChecksumHasher) {
+        self.frame.hash_into(hasher);
+        self.positions.hash_into(hasher);
+        self.velocities.hash_into(hasher);
+        self.rotations.hash_into(hasher);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::num::f64::ANY;
+    use proptest::prelude::*;
+
+    fn game_states() -> impl Strategy<Value = BoxGameState> {
+        // also more players than fit inline
+        (0usize..2 * MAX_INLINE_PLAYERS).prop_flat_map(|players| {
+            (
+                any::<i32>(),
+                vec((ANY, ANY), players),
+                vec((ANY, ANY), players),
+                vec(ANY, players),
+            )
+                .prop_map(|(frame, positions, velocities, rotations)| BoxGameState {
+                    frame,
+                    positions: positions.into(),
+                    velocities: velocities.into(),
+                    rotations: rotations.into(),
+                })
+        })
+    }
+
+    /// Float fields compared bit for bit, so NaNs count as preserved as long as their payload is.
+    fn bits(state: &BoxGameState) -> (i32, Vec<u64>) {
+        let pairs = state.positions.iter().chain(state.velocities.iter());
+        let floats = pairs
+            .flat_map(|&(x, y)| vec![x, y])
+            .chain(state.rotations.iter().copied());
+        (state.frame, floats.map(f64::to_bits).collect())
+    }
+
+    proptest! {
+        #[test]
+        fn save_load_round_trip_preserves_state(state in game_states()) {
+            // the same encoding the game uses to save and load states for GGRS
+            let buffer = encoding::serialize(&state).unwrap();
+            let loaded: BoxGameState = encoding::deserialize(&buffer).unwrap();
+            let reloaded = encoding::serialize(&loaded).unwrap();
+
+            prop_assert_eq!(bits(&state), bits(&loaded));
+            prop_assert_eq!(loaded.positions.len(), state.positions.len());
+            prop_assert_eq!(loaded.velocities.len(), state.velocities.len());
+            prop_assert_eq!(fletcher16(&buffer), fletcher16(&reloaded));
+            prop_assert_eq!(state.checksum(), loaded.checksum());
+        }
+    }
+
+    #[test]
+    fn states_up_to_the_inline_limit_do_not_allocate() {
+        let state = BoxGameState::new(MAX_INLINE_PLAYERS);
+        let buffer = encoding::serialize(&state).unwrap();
+        let loaded: BoxGameState = encoding::deserialize(&buffer).unwrap();
+        assert!(!loaded.positions.spilled());
+        assert!(!loaded.velocities.spilled());
+        assert!(!loaded.rotations.spilled());
+        assert!(BoxGameState::new(MAX_INLINE_PLAYERS + 1)
+            .positions
+            .spilled());
+    }
+
+    #[test]
+    fn checksum_covers_every_field() {
+        let state = BoxGameState::new(2);
+        let changes: [fn(&mut BoxGameState); 5] = [
+            |s| s.frame += 1,
+            |s| s.positions[1].0 += 1.0,
+            |s| s.velocities[0].1 = -0.0,
+            |s| s.rotations[1] = f64::EPSILON,
+            |s| s.positions.swap(0, 1),
+        ];
+        for change in changes.iter() {
+            let mut changed = BoxGameState::new(2);
+            change(&mut changed);
+            assert_ne!(state.checksum(), changed.checksum());
+        }
+    }
+
+    /// Rounded, so the snapshots do not depend on the last bits of the platform's `sin` and `cos`.
+    fn describe(player: &PlayerState) -> String {
+        format!(
+            "position ({:.4}, {:.4}) velocity ({:.4}, {:.4}) rotation {:.4}",
+            player.position.0,
+            player.position.1,
+            player.velocity.0,
+            player.velocity.1,
+            player.rotation
+        )
+    }
+
+    fn input(buttons: InputFlags, stick_x: i8, stick_y: i8) -> Input {
+        Input {
+            buttons,
+            stick_x,
+            stick_y,
+        }
+    }
+
+    #[test]
+    fn move_player_inputs() {
+        let moving = PlayerState {
+            position: (300.0, 400.0),
+            velocity: (3.0, -2.0),
+            rotation: 1.0,
+        };
+        let cases = [
+            ("nothing", input(InputFlags::empty(), 0, 0)),
+            ("up", input(InputFlags::UP, 0, 0)),
+            ("down", input(InputFlags::DOWN, 0, 0)),
+            ("left", input(InputFlags::LEFT, 0, 0)),
+            ("right", input(InputFlags::RIGHT, 0, 0)),
+            ("dash", input(InputFlags::DASH, 0, 0)),
+            ("up+left", input(InputFlags::UP | InputFlags::LEFT, 0, 0)),
+            ("up+down", input(InputFlags::UP | InputFlags::DOWN, 0, 0)),
+            ("stick (4, -4)", input(InputFlags::empty(), 4, -4)),
+            ("stick (-2, 1)", input(InputFlags::empty(), -2, 1)),
+            (
+                "dash+right+stick (1, -1)",
+                input(InputFlags::DASH | InputFlags::RIGHT, 1, -1),
+            ),
+        ];
+        let movement: Vec<_> = cases
+            .iter()
+            .map(|(name, input)| {
+                format!(
+                    "{}: {}",
+                    name,
+                    describe(&move_player(moving, *input, &Tuning::DEFAULT))
+                )
+            })
+            .collect();
+        insta::assert_snapshot!(movement.join("\n"));
+    }
+
+    #[test]
+    fn move_player_limits() {
+        let player = |position, velocity, rotation| PlayerState {
+            position,
+            velocity,
+            rotation,
+        };
+        let cases = [
+            (
+                "top left corner",
+                player((2.0, 3.0), (-4.0, -4.0), 0.0),
+                InputFlags::empty(),
+            ),
+            (
+                "bottom right corner",
+                player((598.0, 797.0), (4.0, 4.0), 0.0),
+                InputFlags::empty(),
+            ),
+            (
+                "top speed",
+                player((300.0, 400.0), (6.9, 0.0), 0.0),
+                InputFlags::DASH,
+            ),
+            (
+                "left at rotation 0",
+                player((300.0, 400.0), (0.0, 0.0), 0.0),
+                InputFlags::LEFT,
+            ),
+        ];
+        let limits: Vec<_> = cases
+            .iter()
+            .map(|(name, player, buttons)| {
+                let moved = move_player(*player, input(*buttons, 0, 0), &Tuning::DEFAULT);
+                format!("{}: {}", name, describe(&moved))
+            })
+            .collect();
+        insta::assert_snapshot!(limits.join("\n"));
+    }
+
+    /// A named case of old and new positions.
+    type ContactCase = (&'static str, &'static [(f64, f64)], &'static [(f64, f64)]);
+
+    #[test]
+    fn new_contacts_cases() {
+        let cases: [ContactCase; 5] = [
+            (
+                "apart",
+                &[(0.0, 0.0), (100.0, 0.0)],
+                &[(0.0, 0.0), (60.0, 0.0)],
+            ),
+            (
+                "collide",
+                &[(0.0, 0.0), (60.0, 0.0)],
+                &[(0.0, 0.0), (40.0, 0.0)],
+            ),
+            (
+                "already touching",
+                &[(0.0, 0.0), (40.0, 0.0)],
+                &[(0.0, 0.0), (30.0, 0.0)],
+            ),
+            (
+                "three boxes",
+                &[(0.0, 0.0), (100.0, 0.0), (200.0, 0.0)],
+                &[(60.0, 0.0), (100.0, 0.0), (140.0, 0.0)],
+            ),
+            (
+                "diagonal graze",
+                &[(0.0, 0.0), (50.0, 50.0)],
+                &[(0.0, 0.0), (35.0, 35.0)],
+            ),
+        ];
+        let contacts: Vec<_> = cases
+            .iter()
+            .map(|(name, old, new)| format!("{}: {:?}", name, new_contacts(old, new)))
+            .collect();
+        insta::assert_snapshot!(contacts.join("\n"));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_advance_equals_serial() {
+        // one partial chunk at the end as well
+        let players = PARALLEL_PLAYERS + PARALLEL_CHUNK / 2;
+        let tuning = Tuning::DEFAULT;
+        let mut parallel = BoxGameState::new(players);
+        let mut serial = BoxGameState::new(players);
+        for frame in 0..120 {
+            // the last peer disconnected, so its player spins
+            let mut inputs: Vec<_> = (0..players)
+                .map(|player| Some(input::encode(scripted_input(3, frame, player)).to_vec()))
+                .collect();
+            inputs[players - 1] = None;
+            parallel.advance(&inputs, 1, &tuning);
+            serial.frame += 1;
+            advance_players(
+                0,
+                &mut serial.positions,
+                &mut serial.velocities,
+                &mut serial.rotations,
+                &inputs,
+                1,
+                &tuning,
+            );
+            assert_eq!(bits(&parallel), bits(&serial), "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn player_input_cases() {
+        let connected = input::encode(input(InputFlags::UP | InputFlags::DASH, 1, -2)).to_vec();
+        let mut two_local = input::encode(input(InputFlags::RIGHT, 0, 0)).to_vec();
+        two_local.extend_from_slice(&input::encode(input(InputFlags::DOWN, -4, 4)));
+        let cases = [
+            ("connected", vec![Some(connected.clone()), None], 0, 1),
+            ("disconnected", vec![Some(connected.clone()), None], 1, 1),
+            ("missing peer", vec![Some(connected), None], 2, 1),
+            ("second local player", vec![Some(two_local)], 1, 2),
+            ("truncated", vec![Some(vec![1])], 0, 1),
+        ];
+        let inputs: Vec<_> = cases
+            .iter()
+            .map(|(name, inputs, player, local_players)| {
+                let input = player_input(inputs, *player, *local_players);
+                format!(
+                    "{}: {:?} stick ({}, {})",
+                    name, input.buttons, input.stick_x, input.stick_y
+                )
+            })
+            .collect();
+        insta::assert_snapshot!(inputs.join("\n"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Fixed size copies of `BoxGameState` for saving states during rollbacks. A snapshot is plain old data, so saving
+//! one copies its bytes and loading one copies them back, without serde in between.
+
+use crate::{BoxGameState, MAX_INLINE_PLAYERS};
+use bytemuck::{Pod, Zeroable};
+
+/// The state of a game with up to `MAX_INLINE_PLAYERS` boxes. Slots after the first `num_players` are zeroed.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct Snapshot {
+    frame: i32,
+    num_players: u32,
+    positions: [[f64; 2]; MAX_INLINE_PLAYERS],
+    velocities: [[f64; 2]; MAX_INLINE_PLAYERS],
+    rotations: [f64; MAX_INLINE_PLAYERS],
+}
+
+/// Size in bytes of every snapshot, regardless of the number of boxes.
+pub const SNAPSHOT_SIZE: usize = std::mem::size_of::<Snapshot>();
+
+impl Snapshot {
+    /// `None` if the game has more boxes than a snapshot holds.
+    pub fn capture(state: &BoxGameState) -> Option<Self> {
+        let num_players = state.positions.len();
+        if num_players > MAX_INLINE_PLAYERS {
+            return None;
+        }
+        let mut snapshot = Self::zeroed();
+        snapshot.frame = state.frame;
+        snapshot.num_players = num_players as u32;
+        for i in 0..num_players {
+            snapshot.positions[i] = [state.positions[i].0, state.positions[i].1];
+            snapshot.velocities[i] = [state.velocities[i].0, state.velocities[i].1];
+            snapshot.rotations[i] = state.rotations[i];
+        }
+        Some(snapshot)
+    }
+
+    /// The state the snapshot was captured from. It fits inline, so this does not allocate.
+    pub fn to_state(&self) -> BoxGameState {
+        let num_players = self.num_players as usize;
+        BoxGameState {
+            frame: self.frame,
+            positions: self.positions[..num_players]
+                .iter()
+                .map(|p| (p[0], p[1]))
+                .collect(),
+            velocities: self.velocities[..num_players]
+                .iter()
+                .map(|v| (v[0], v[1]))
+                .collect(),
+            rotations: self.rotations[..num_players].iter().copied().collect(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+
+    /// Reads a snapshot written by `as_bytes`. The bytes do not have to be aligned. `None` if they have the wrong
+    /// size or claim more boxes than a snapshot holds.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != SNAPSHOT_SIZE {
+            return None;
+        }
+        let snapshot: Self = bytemuck::pod_read_unaligned(bytes);
+        if snapshot.num_players as usize > MAX_INLINE_PLAYERS {
+            return None;
+        }
+        Some(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Checksum;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut state = BoxGameState::new(MAX_INLINE_PLAYERS);
+        state.frame = 42;
+        state.velocities[3] = (1.5, -0.25);
+        state.rotations[7] = f64::NAN;
+        let snapshot = Snapshot::capture(&state).unwrap();
+        // one byte in, as saves put a tag in front of it
+        let mut buffer = vec![0];
+        buffer.extend_from_slice(snapshot.as_bytes());
+        let loaded = Snapshot::from_bytes(&buffer[1..]).unwrap().to_state();
+        assert_eq!(loaded.checksum(), state.checksum());
+        assert!(!loaded.positions.spilled());
+    }
+
+    #[test]
+    fn invalid_snapshots_are_rejected() {
+        assert!(Snapshot::capture(&BoxGameState::new(MAX_INLINE_PLAYERS + 1)).is_none());
+        assert!(Snapshot::from_bytes(&[0; SNAPSHOT_SIZE - 1]).is_none());
+        let mut bytes = [0; SNAPSHOT_SIZE];
+        bytes[4] = MAX_INLINE_PLAYERS as u8 + 1;
+        assert!(Snapshot::from_bytes(&bytes).is_none());
+    }
+}
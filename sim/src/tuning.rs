@@ -0,0 +1,122 @@
+//! The gameplay constants of the simulation, adjustable during playtests. Both peers have to simulate every frame
+//! with the same values, so a change is never applied right away: it is scheduled for a frame far enough ahead that
+//! the other peer learns about it before simulating that frame, and from then on both use the new values.
+
+use crate::FPS;
+use ggrs::Frame;
+use serde::{Deserialize, Serialize};
+
+/// How fast the boxes move. Speeds are given per second, so they read the same regardless of the frame rate.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    /// velocity gained per second of thrust
+    pub movement_speed: f64,
+    /// radians turned per second
+    pub rotation_speed: f64,
+    /// the velocity is capped to this many pixels per frame
+    pub max_speed: f64,
+    /// the velocity is multiplied by this every frame
+    pub friction: f64,
+}
+
+impl Tuning {
+    /// The values the game shipped with.
+    pub const DEFAULT: Self = Self {
+        movement_speed: 15.0,
+        rotation_speed: 2.5,
+        max_speed: 7.0,
+        friction: 0.98,
+    };
+
+    pub(crate) fn movement_per_frame(&self) -> f64 {
+        self.movement_speed / FPS as f64
+    }
+
+    pub(crate) fn rotation_per_frame(&self) -> f64 {
+        self.rotation_speed / FPS as f64
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The tuning of every frame: the default values, followed by the scheduled changes in order of their frames.
+/// Which values apply only depends on the frame, so rollbacks resimulate frames with the values they had.
+#[derive(Clone, Debug, Default)]
+pub struct TuningSchedule {
+    /// `(first frame, tuning)`, sorted by frame
+    changes: Vec<(Frame, Tuning)>,
+}
+
+impl TuningSchedule {
+    /// Uses `tuning` for `frame` and all later frames. Scheduling the same change again, e.g. because the announcement
+    /// was received twice, changes nothing. A later change replaces one scheduled for the same frame.
+    pub fn schedule(&mut self, frame: Frame, tuning: Tuning) {
+        match self
+            .changes
+            .binary_search_by_key(&frame, |(first, _)| *first)
+        {
+            Ok(index) => self.changes[index].1 = tuning,
+            Err(index) => self.changes.insert(index, (frame, tuning)),
+        }
+    }
+
+    /// The values to simulate `frame` with, i.e. to advance the state from `frame - 1` to `frame`.
+    pub fn at(&self, frame: Frame) -> &Tuning {
+        self.changes
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= frame)
+            .map_or(&Tuning::DEFAULT, |(_, tuning)| tuning)
+    }
+
+    /// The change that applies from exactly `frame` on, if there is one, e.g. to record it along with that frame.
+    pub fn change_at(&self, frame: Frame) -> Option<&Tuning> {
+        self.changes
+            .binary_search_by_key(&frame, |(first, _)| *first)
+            .ok()
+            .map(|index| &self.changes[index].1)
+    }
+
+    /// Forgets changes superseded by a later one that applies from `frame` on, once `frame` can no longer be rolled
+    /// back to.
+    pub fn prune(&mut self, frame: Frame) {
+        let current = self.changes.iter().rposition(|(first, _)| *first <= frame);
+        if let Some(current) = current {
+            self.changes.drain(..current);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuning(friction: f64) -> Tuning {
+        Tuning {
+            friction,
+            ..Tuning::DEFAULT
+        }
+    }
+
+    #[test]
+    fn changes_apply_from_their_frame() {
+        let mut schedule = TuningSchedule::default();
+        schedule.schedule(200, tuning(0.5));
+        schedule.schedule(100, tuning(0.9));
+        schedule.schedule(100, tuning(0.9));
+        assert_eq!(schedule.at(99), &Tuning::DEFAULT);
+        assert_eq!(schedule.at(100).friction, 0.9);
+        assert_eq!(schedule.at(199).friction, 0.9);
+        assert_eq!(schedule.at(200).friction, 0.5);
+        assert_eq!(schedule.change_at(100), Some(&tuning(0.9)));
+        assert_eq!(schedule.change_at(150), None);
+        schedule.prune(150);
+        assert_eq!(schedule.at(150).friction, 0.9);
+        assert_eq!(schedule.at(250).friction, 0.5);
+    }
+}
@@ -0,0 +1,94 @@
+//! The format of `BoxGameState` wherever it outlives a session: keyframes in replays and the states matches are
+//! resumed from. Every stored state starts with its format version. Changing the fields of `BoxGameState` bumps the
+//! version and adds a migration from the older ones to `decode`, so it does not invalidate every replay recorded before.
+//!
+//! States saved for GGRS during a session are never stored, they use the plain encoding or a `Snapshot` instead.
+
+use crate::{encoding, BoxGameState};
+use serde::Serialize;
+
+/// Version of the stored state format.
+///
+/// - 1: the fields of `BoxGameState`. States stored before versions were introduced have the same fields.
+pub const STATE_VERSION: u32 = 1;
+
+/// Size of the version in front of every stored state.
+const VERSION_SIZE: usize = std::mem::size_of::<u32>();
+
+/// The state as stored with the current version.
+#[derive(Serialize)]
+struct Stored<'a> {
+    version: u32,
+    state: &'a BoxGameState,
+}
+
+/// Fails for states beyond `encoding::MAX_ENCODED_SIZE`.
+pub fn encode(state: &BoxGameState) -> bincode::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    encode_into(state, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Like `encode`, but reuses the allocation of `bytes`, which is overwritten.
+pub fn encode_into(state: &BoxGameState, bytes: &mut Vec<u8>) -> bincode::Result<()> {
+    bytes.clear();
+    encoding::serialize_into(
+        &mut *bytes,
+        &Stored {
+            version: STATE_VERSION,
+            state,
+        },
+    )
+}
+
+/// Decodes a state written by `encode` of this or an earlier version. Stored states come from files, so they may be
+/// corrupt, or from a newer build this one cannot read.
+pub fn decode(bytes: &[u8]) -> bincode::Result<BoxGameState> {
+    if bytes.len() < VERSION_SIZE {
+        return Err(Box::new(bincode::ErrorKind::Custom(
+            "the stored state has no version".to_string(),
+        )));
+    }
+    let version: u32 = encoding::deserialize(&bytes[..VERSION_SIZE])?;
+    let state = &bytes[VERSION_SIZE..];
+    match version {
+        STATE_VERSION => encoding::deserialize(state),
+        _ => Err(Box::new(bincode::ErrorKind::Custom(format!(
+            "unsupported state version {}, this build reads versions up to {}",
+            version, STATE_VERSION
+        )))),
+    }
+}
+
+/// Puts the version in front of a state stored before versions were introduced, so `decode` can read it as version 1.
+pub fn from_unversioned(state: &[u8]) -> Vec<u8> {
+    let mut bytes = encoding::serialize(&1u32).unwrap();
+    bytes.extend_from_slice(state);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Checksum;
+
+    #[test]
+    fn unversioned_states_are_read_as_version_1() {
+        let mut state = BoxGameState::new(3);
+        state.frame = 17;
+        state.velocities[2] = (0.5, -1.0);
+        let current = decode(&encode(&state).unwrap()).unwrap();
+        assert_eq!(current.checksum(), state.checksum());
+        let unversioned = encoding::serialize(&state).unwrap();
+        let migrated = decode(&from_unversioned(&unversioned)).unwrap();
+        assert_eq!(migrated.checksum(), state.checksum());
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut bytes = encode(&BoxGameState::new(2)).unwrap();
+        bytes[..VERSION_SIZE].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(decode(&bytes).is_err());
+        assert!(decode(&[2, 0]).is_err());
+    }
+}
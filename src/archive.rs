@@ -0,0 +1,380 @@
+//! Spectating a host and recording its matches: the spectator session, reconnecting to a host that left, where the
+//! replay and the stats of every match go, and writing them as the match starts and ends. Shared by the spectator and
+//! the archiver, which records without a window.
+
+use crate::box_game::{self, HitEvent, Simulation, Tuning};
+use crate::game_loop::{Advance, GameLoop};
+use crate::match_stats::MatchStats;
+use crate::replay;
+use boxgame_net::session::{Backoff, SessionBuilder};
+use ggrs::{Frame, GGRSEvent, P2PSpectatorSession, SessionState};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Delays between attempts to reconnect to a host that disconnected.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// A reconnect attempt that did not synchronize with the host by then failed.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The files a match is recorded to.
+pub struct MatchFiles {
+    pub replay: Option<PathBuf>,
+    pub stats: Option<PathBuf>,
+}
+
+impl MatchFiles {
+    /// Files in `record_dir` named after the current time, or the files given with --record-replay and --stats,
+    /// numbered from the second match on.
+    pub fn new(
+        record_dir: Option<&Path>,
+        replay_path: Option<&str>,
+        stats_path: Option<&str>,
+        match_number: usize,
+    ) -> Self {
+        if let Some(dir) = record_dir {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            let name = format!("match-{}-{}", timestamp, match_number);
+            return Self {
+                replay: Some(dir.join(format!("{}.replay", name))),
+                stats: Some(dir.join(format!("{}.json", name))),
+            };
+        }
+        Self {
+            replay: replay_path.map(|path| numbered_path(path, match_number)),
+            stats: stats_path.map(|path| numbered_path(path, match_number)),
+        }
+    }
+}
+
+/// `path` for the first match, with the match number appended to the file name for later ones.
+fn numbered_path(path: &str, match_number: usize) -> PathBuf {
+    let path = PathBuf::from(path);
+    if match_number == 1 {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, match_number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, match_number),
+    };
+    path.with_file_name(name)
+}
+
+/// Starts recording the match `game` is about to simulate, `names` has one entry per player.
+fn start_replay(
+    path: &Path,
+    names: &[String],
+    local_players: usize,
+    game: &mut Simulation,
+) -> anyhow::Result<replay::ReplayWriter> {
+    game.enable_frame_log();
+    let header = replay::ReplayHeader::new(
+        names.to_vec(),
+        names.len(),
+        local_players,
+        box_game::INPUT_SIZE * local_players,
+        game.map_hash(),
+        None,
+    );
+    replay::ReplayWriter::create(path, &header)
+}
+
+/// Where matches are recorded to, see `MatchFiles::new`. Nothing is recorded if all of them are `None`.
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    pub record_dir: Option<PathBuf>,
+    pub replay_path: Option<String>,
+    pub stats_path: Option<String>,
+}
+
+/// What happened to the connection with the host, for the binary to report.
+#[derive(Debug)]
+pub enum LinkEvent {
+    /// a new session with the host was started, the match starts over at the first frame
+    Reconnected,
+    /// starting a new session failed
+    ReconnectFailed {
+        error: anyhow::Error,
+        retry_in: Duration,
+    },
+    /// the last attempt did not synchronize with the host in time
+    HostSilent { retry_in: Duration },
+    /// the host disconnected, so the match is over and was written to its files
+    Disconnected { retry_in: Duration },
+}
+
+/// What a tick of the spectator session did.
+pub struct Tick {
+    pub advance: Advance,
+    /// the events of the session, which were already logged
+    pub events: Vec<GGRSEvent>,
+    /// the hits of the advanced frame, which a spectator never has to roll back
+    pub hits: Vec<HitEvent>,
+}
+
+/// A spectator of a host whose matches come and go: ticks the session, records every match into its own files, and
+/// when the host disconnects or does not answer, drops the session and starts a new one after a backoff delay.
+pub struct SpectatedHost {
+    builder: SessionBuilder,
+    port: u16,
+    host_addr: SocketAddr,
+    /// `None` while waiting to reconnect
+    sess: Option<P2PSpectatorSession>,
+    backoff: Backoff,
+    reconnect_at: Option<Instant>,
+    connected_at: Instant,
+    game: Simulation,
+    game_loop: GameLoop,
+    local_players: usize,
+    /// one per player, used for the files of the next match
+    names: Vec<String>,
+    recording: Recording,
+    match_number: usize,
+    files: MatchFiles,
+    replay_writer: Option<replay::ReplayWriter>,
+    match_stats: Option<MatchStats>,
+    /// the tuning changes the host announced for the current match
+    tuning_changes: Vec<(Frame, Tuning)>,
+    link_events: Vec<LinkEvent>,
+}
+
+impl SpectatedHost {
+    /// Binds `port` and starts spectating the host at `host_addr`, whose players control `local_players` boxes each.
+    pub fn connect(
+        port: u16,
+        host_addr: SocketAddr,
+        local_players: usize,
+        names: Vec<String>,
+        recording: Recording,
+        now: Instant,
+    ) -> anyhow::Result<Self> {
+        let builder = SessionBuilder::new(names.len(), box_game::INPUT_SIZE * local_players);
+        let sess = builder.start_spectator(port, host_addr)?;
+        let mut host = Self {
+            builder,
+            port,
+            host_addr,
+            sess: Some(sess),
+            backoff: Backoff::new(RECONNECT_DELAY, MAX_RECONNECT_DELAY),
+            reconnect_at: None,
+            connected_at: now,
+            game: Simulation::new(names.len(), local_players),
+            game_loop: GameLoop::default(),
+            local_players,
+            names,
+            recording,
+            match_number: 1,
+            files: MatchFiles {
+                replay: None,
+                stats: None,
+            },
+            replay_writer: None,
+            match_stats: None,
+            tuning_changes: Vec::new(),
+            link_events: Vec::new(),
+        };
+        host.start_match(now)?;
+        Ok(host)
+    }
+
+    /// Opens the files of the match that is about to start.
+    fn start_match(&mut self, now: Instant) -> anyhow::Result<()> {
+        self.files = MatchFiles::new(
+            self.recording.record_dir.as_deref(),
+            self.recording.replay_path.as_deref(),
+            self.recording.stats_path.as_deref(),
+            self.match_number,
+        );
+        self.replay_writer = match &self.files.replay {
+            Some(path) => Some(start_replay(
+                path,
+                &self.names,
+                self.local_players,
+                &mut self.game,
+            )?),
+            None => None,
+        };
+        self.match_stats = self
+            .files
+            .stats
+            .as_ref()
+            .map(|_| MatchStats::new(self.names.clone(), now));
+        Ok(())
+    }
+
+    /// Writes what is left of the replay and the stats of the current match, if it was not written yet.
+    pub fn finish_match(&mut self, now: Instant) -> anyhow::Result<()> {
+        // spectators only ever see confirmed inputs, so the frames not written yet are final as well
+        if let Some(mut writer) = self.replay_writer.take() {
+            writer.write_frames(&self.game.take_all_frames())?;
+            writer.flush()?;
+        }
+        if let (Some(stats), Some(path)) = (self.match_stats.take(), &self.files.stats) {
+            stats.summary(&self.game, now).write(path)?;
+            tracing::info!("Wrote the match stats to {}.", path.display());
+        }
+        Ok(())
+    }
+
+    /// Renames a player in the stats of the current match and the files of later ones.
+    pub fn rename(&mut self, handle: usize, name: String) {
+        if handle >= self.names.len() {
+            return;
+        }
+        if let Some(stats) = self.match_stats.as_mut() {
+            stats.rename(handle, &name);
+        }
+        self.names[handle] = name;
+    }
+
+    /// Simulates `frame` and all later frames with `tuning`, as announced by the host. The host repeats its
+    /// announcements, only the first one counts. The change is recorded into the replay along with its frame.
+    pub fn schedule_tuning(&mut self, frame: Frame, tuning: Tuning) {
+        if self.tuning_changes.contains(&(frame, tuning)) {
+            return;
+        }
+        if !self.game.schedule_tuning(frame, tuning) {
+            tracing::warn!(
+                "The tuning for frame {} arrived after simulating frame {}, the spectator will desync",
+                frame,
+                self.game.current_frame()
+            );
+        }
+        self.tuning_changes.push((frame, tuning));
+    }
+
+    /// Starts the next attempt to connect once its delay is over, and gives up on an attempt the host did not answer.
+    pub fn update(&mut self, now: Instant) -> anyhow::Result<()> {
+        if self.reconnect_at.is_some_and(|at| now >= at) {
+            self.reconnect_at = None;
+            match self.builder.start_spectator(self.port, self.host_addr) {
+                Ok(sess) => {
+                    tracing::info!("Reconnecting to host {}.", self.host_addr);
+                    // the new session starts over at the first frame, into new files
+                    self.sess = Some(sess);
+                    self.connected_at = now;
+                    self.game = Simulation::new(self.names.len(), self.local_players);
+                    self.game_loop = GameLoop::default();
+                    self.tuning_changes.clear();
+                    self.start_match(now)?;
+                    self.link_events.push(LinkEvent::Reconnected);
+                }
+                Err(error) => {
+                    let retry_in = self.retry_later(now);
+                    self.link_events
+                        .push(LinkEvent::ReconnectFailed { error, retry_in });
+                }
+            }
+        }
+        if self.sess.as_ref().is_some_and(|sess| {
+            sess.current_state() != SessionState::Running
+                && now.saturating_duration_since(self.connected_at) >= SYNC_TIMEOUT
+        }) {
+            // the host did not answer, the socket is released before the next attempt binds the port again
+            self.sess = None;
+            let retry_in = self.retry_later(now);
+            self.link_events.push(LinkEvent::HostSilent { retry_in });
+        }
+        Ok(())
+    }
+
+    fn retry_later(&mut self, now: Instant) -> Duration {
+        let delay = self.backoff.next_delay();
+        self.reconnect_at = Some(now + delay);
+        delay
+    }
+
+    /// Runs one tick of the session, `None` while there is none. Advanced frames are recorded, and when the host
+    /// disconnects, the match is finished and a reconnect is scheduled.
+    pub fn tick(&mut self, now: Instant) -> anyhow::Result<Option<Tick>> {
+        let sess = match self.sess.as_mut() {
+            Some(sess) => sess,
+            None => return Ok(None),
+        };
+        let (advance, events) = self
+            .game_loop
+            .tick(sess, &mut self.game, |sess| sess.advance_frame())?;
+        let mut hits = Vec::new();
+        if advance == Advance::Advanced {
+            self.backoff.reset();
+            if let Some(writer) = self.replay_writer.as_mut() {
+                writer.write_frames(&self.game.take_confirmed_frames())?;
+            }
+            hits = self.game.take_confirmed_hits();
+            if let Some(stats) = self.match_stats.as_mut() {
+                stats.record(&self.game, &hits);
+            }
+        }
+        if events
+            .iter()
+            .any(|event| matches!(event, GGRSEvent::Disconnected { .. }))
+        {
+            // the match is over, it starts over after reconnecting
+            self.finish_match(now)?;
+            self.match_number += 1;
+            self.sess = None;
+            let retry_in = self.retry_later(now);
+            self.link_events.push(LinkEvent::Disconnected { retry_in });
+        }
+        Ok(Some(Tick {
+            advance,
+            events,
+            hits,
+        }))
+    }
+
+    /// Processes incoming packets, call it whenever the event loop is idle.
+    pub fn poll(&mut self) {
+        if let Some(sess) = self.sess.as_mut() {
+            self.game_loop.poll(sess);
+        }
+    }
+
+    /// Removes and returns what happened to the connection since the last call.
+    pub fn take_link_events(&mut self) -> Vec<LinkEvent> {
+        std::mem::take(&mut self.link_events)
+    }
+
+    /// The simulation of the current match.
+    pub fn game(&self) -> &Simulation {
+        &self.game
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The session with the host, `None` while waiting to reconnect.
+    pub fn session(&self) -> Option<&P2PSpectatorSession> {
+        self.sess.as_ref()
+    }
+
+    /// When the next attempt to connect starts, if one is scheduled.
+    pub fn reconnect_at(&self) -> Option<Instant> {
+        self.reconnect_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_matches_are_numbered() {
+        let first = MatchFiles::new(None, Some("out/match.replay"), Some("stats"), 1);
+        assert_eq!(first.replay, Some(PathBuf::from("out/match.replay")));
+        assert_eq!(first.stats, Some(PathBuf::from("stats")));
+        let third = MatchFiles::new(None, Some("out/match.replay"), None, 3);
+        assert_eq!(third.replay, Some(PathBuf::from("out/match-3.replay")));
+        assert_eq!(third.stats, None);
+        let recorded = MatchFiles::new(Some(Path::new("archive")), None, None, 2);
+        let replay = recorded.replay.unwrap();
+        assert_eq!(replay.parent(), Some(Path::new("archive")));
+        assert!(replay.to_string_lossy().ends_with("-2.replay"));
+    }
+}
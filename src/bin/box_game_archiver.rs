@@ -0,0 +1,145 @@
+use boxgame_net::side_channel::{self, SideChannel, SideMessage};
+use ggrs_test_game::archive::{LinkEvent, Recording, SpectatedHost};
+use ggrs_test_game::{box_game, cli, timestep, trace};
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FPS: u64 = 60;
+const NUM_PLAYERS: usize = 2;
+/// Packets are processed at least this often, between ticks as well.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Spectates a host and records every match, like `box_game_spectator --headless --record`, but without a window.
+/// Needs no display libraries: cargo build --release --no-default-features --bin box_game_archiver
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --trace <file>, a chrome tracing file of all spans is written
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    cli::handle_interrupts();
+    // pass --two-local when the players control two boxes each
+    let two_local = cli::take_flag(&mut args, "--two-local");
+    let local_players = if two_local { 2 } else { 1 };
+    // with --record <dir>, every match is archived into that directory as a replay and a stats file named after the
+    // time it started. --record-replay <file> and --stats <file> name the files instead, numbered from the second
+    // match on.
+    let record_dir = cli::take_flag_value(&mut args, "--record").map(PathBuf::from);
+    if let Some(dir) = &record_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let replay_path = cli::take_flag_value(&mut args, "--record-replay");
+    let stats_path = cli::take_flag_value(&mut args, "--stats");
+    if record_dir.is_none() && replay_path.is_none() && stats_path.is_none() {
+        anyhow::bail!(
+            "nothing to record, pass --record <dir>, --record-replay <file> or --stats <file>"
+        );
+    }
+    // with --player-names <a,b>, the players are called that in the stats and the recorded replay. Otherwise they
+    // go by the names the players send, which the replay of a match only gets from the next one on.
+    let fixed_names = cli::take_flag_value(&mut args, "--player-names");
+    let names: Vec<String> = match &fixed_names {
+        Some(names) => names.split(',').map(str::to_string).collect(),
+        None => (0..NUM_PLAYERS)
+            .map(|handle| format!("P{}", handle))
+            .collect(),
+    };
+    if names.len() != NUM_PLAYERS {
+        anyhow::bail!("--player-names needs {} names", NUM_PLAYERS);
+    }
+    // with --side-port <port> and --host-side-port <port>, the side channels of the archiver and the host are not
+    // on the default ports, `side_channel::PORT_OFFSET` above the GGRS ports
+    let side_port = cli::take_flag_value(&mut args, "--side-port");
+    let host_side_port = cli::take_flag_value(&mut args, "--host-side-port");
+    if args.len() != 3 {
+        anyhow::bail!(
+            "usage: box_game_archiver [--two-local] [--record <dir>] [--record-replay <file>] [--stats <file>] ... <port> <host address>"
+        );
+    }
+
+    let port: u16 = args[1].parse()?;
+    let host_addr: SocketAddr = args[2].parse()?;
+
+    let recording = Recording {
+        record_dir,
+        replay_path,
+        stats_path,
+    };
+    let mut host = SpectatedHost::connect(
+        port,
+        host_addr,
+        local_players,
+        names,
+        recording,
+        Instant::now(),
+    )?;
+    // the host sends the player names and its tuning changes to the archiver's side channel
+    let side_port = match side_port {
+        Some(side_port) => side_port.parse()?,
+        None => side_channel::default_port(port),
+    };
+    let host_side_port = match host_side_port {
+        Some(host_side_port) => host_side_port.parse()?,
+        None => side_channel::default_port(host_addr.port()),
+    };
+    let mut side_channel = SideChannel::bind(side_port)?;
+    side_channel.add_peer(SocketAddr::new(host_addr.ip(), host_side_port));
+
+    let mut timestep = timestep::FixedTimestep::new(FPS, Instant::now());
+    tracing::info!("Recording the matches of host {}.", host_addr);
+
+    while !cli::interrupted() {
+        let now = Instant::now();
+        for message in side_channel.receive() {
+            match message {
+                SideMessage::PlayerName { handle, name } if fixed_names.is_none() => {
+                    host.rename(handle, name)
+                }
+                SideMessage::Tuning { frame, tuning } => {
+                    match box_game::encoding::deserialize(&tuning) {
+                        Ok(tuning) => host.schedule_tuning(frame, tuning),
+                        Err(e) => tracing::warn!("Invalid tuning from the host: {}", e),
+                    }
+                }
+                _ => (),
+            }
+        }
+        host.update(now)?;
+        for _ in 0..timestep.advance(now) {
+            if host.tick(now)?.is_none() {
+                break;
+            }
+        }
+        for event in host.take_link_events() {
+            match event {
+                LinkEvent::Reconnected => (),
+                LinkEvent::ReconnectFailed { error, retry_in } => tracing::warn!(
+                    "Could not reconnect: {}, retrying in {} s",
+                    error,
+                    retry_in.as_secs()
+                ),
+                LinkEvent::HostSilent { retry_in } => {
+                    tracing::warn!("Host did not answer, retrying in {} s", retry_in.as_secs())
+                }
+                LinkEvent::Disconnected { retry_in } => tracing::info!(
+                    "Disconnected from host, reconnecting in {} s.",
+                    retry_in.as_secs()
+                ),
+            }
+        }
+
+        host.poll();
+        thread::sleep(timestep.until_next_tick(Instant::now()).min(POLL_INTERVAL));
+    }
+
+    // the last match, a match that ended with the host disconnecting has nothing left to write
+    host.finish_match(Instant::now())?;
+    Ok(())
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -0,0 +1,209 @@
+use boxgame_net::{desync, side_channel};
+use ggrs::{Frame, GGRSEvent, PlayerHandle};
+use ggrs_test_game::{box_game, cli, config, crash, game_loop, input_recording, timestep, trace};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FPS: u64 = 60;
+const NUM_PLAYERS: usize = 2;
+const CHECKSUM_PERIOD: i32 = 100;
+/// Packets are processed at least this often, between ticks as well.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Plays one side of a P2P match without a window, mashing turbo inputs or replaying an input recording. It exchanges
+/// names and checksums with the other peer like `box_game_p2p` does, so a dedicated bot on a server doubles as a
+/// desync check. Needs no display libraries: cargo build --release --no-default-features --bin box_game_bot
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --trace <file>, a chrome tracing file of all spans is written
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    cli::handle_interrupts();
+    // a panic writes the last state and the recent inputs and checksums to a crash-<timestamp> directory
+    crash::install_hook();
+    // with --play-inputs <file>, the bot plays a recording instead of mashing random directions
+    let input_playback = match cli::take_flag_value(&mut args, "--play-inputs") {
+        Some(path) => Some(input_recording::InputPlayback::load(Path::new(&path))?),
+        None => None,
+    };
+    // with --frames <n>, the bot exits once frame n is confirmed
+    let stop_frame: Option<Frame> = match cli::take_flag_value(&mut args, "--frames") {
+        Some(frames) => Some(frames.parse()?),
+        None => None,
+    };
+    // with --print-checksum, the checksum of the last confirmed frame is printed at exit
+    let print_checksum = cli::take_flag(&mut args, "--print-checksum");
+    // with --fast-forward, the bot does not wait for its ticks but runs them back to back on a manual clock, e.g. for
+    // two bots that should play a long match quickly. GGRS still holds it back when it predicts too far ahead.
+    let clock: Box<dyn timestep::Clock> = if cli::take_flag(&mut args, "--fast-forward") {
+        Box::new(timestep::ManualClock::new(Instant::now()))
+    } else {
+        Box::new(timestep::SystemClock)
+    };
+    // with --metrics <file>, runtime metrics are written to that JSON file at exit
+    let metrics_path = cli::take_flag_value(&mut args, "--metrics").map(PathBuf::from);
+    // the same session settings as box_game_p2p: box_game.toml or --config <file>, BOXGAME_* variables and
+    // `<port> <handle> <remote> [spectator]` with --two-local, --name and --frame-delay
+    let config = config::load(&mut args)?;
+    let local_handle = config.local_handle;
+    let remote_handle: PlayerHandle = 1 - local_handle;
+    let local_players = config.local_players;
+
+    let mut player_names: Vec<Option<String>> = vec![None; NUM_PLAYERS];
+    player_names[local_handle] = Some(
+        config
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Bot {}", local_handle)),
+    );
+
+    let input_size = box_game::INPUT_SIZE * local_players;
+    let mut sess = config.session_builder().start_p2p(config.port)?;
+
+    let mut side_channel = side_channel::SideChannel::bind(config.side_port)?;
+    side_channel.add_peer(config.remote_side_addr());
+    let mut desync_detector = desync::DesyncDetector::default();
+
+    let mut game = box_game::Simulation::new(NUM_PLAYERS, local_players);
+    game.enable_frame_log();
+    // the bot has no keyboard, only the turbo mode of the controls is used
+    let mut controls = box_game::Presentation::new(local_players);
+    controls.turbo = true;
+    let mut game_loop = game_loop::GameLoop::default();
+    let mut timestep = timestep::FixedTimestep::new(FPS, clock.now());
+    let mut last_confirmed: Option<(Frame, u64)> = None;
+    let mut reached_stop_frame = false;
+    // the last tuning change of the other peer, which announces it repeatedly
+    let mut tuning_change: Option<(Frame, box_game::Tuning)> = None;
+    tracing::info!(
+        "{} is playing as player {}.",
+        player_names[local_handle].as_ref().unwrap(),
+        local_handle
+    );
+    let mut name_announcement = side_channel::NameAnnouncement::new(
+        local_handle,
+        player_names[local_handle].clone().unwrap(),
+    );
+
+    'session: while !cli::interrupted() {
+        for _ in 0..timestep.advance(clock.now()) {
+            let frame = game.current_frame();
+            let input = match &input_playback {
+                // frames missing from the recording (e.g. after it ended) send no buttons
+                Some(playback) => playback
+                    .input(frame)
+                    .map(|input| input.to_vec())
+                    .unwrap_or_else(|| vec![0; input_size]),
+                None => controls.local_input(frame),
+            };
+            let (_, events) = game_loop.tick(&mut sess, &mut game, |sess| {
+                sess.advance_frame(local_handle, &input)
+            })?;
+            // this GGRS version cannot reconnect, a disconnect ends the match for good
+            if events
+                .iter()
+                .any(|event| matches!(event, GGRSEvent::Disconnected { .. }))
+            {
+                break 'session;
+            }
+
+            for record in game.take_confirmed_frames() {
+                if stop_frame.is_none_or(|stop_frame| record.frame <= stop_frame) {
+                    last_confirmed = Some((record.frame, record.checksum));
+                }
+                if record.frame % CHECKSUM_PERIOD == 0 {
+                    side_channel.send(&side_channel::SideMessage::Checksum {
+                        frame: record.frame,
+                        checksum: record.checksum,
+                    });
+                    if let Some(frame) = desync_detector.add_local(record.frame, record.checksum) {
+                        anyhow::bail!("desync at frame {}", frame);
+                    }
+                }
+            }
+            if stop_frame.is_some() && last_confirmed.map(|(frame, _)| frame) == stop_frame {
+                reached_stop_frame = true;
+                break 'session;
+            }
+        }
+
+        game_loop.poll(&mut sess);
+        // the session only advances once both peers are connected
+        if game.current_frame() > 0 {
+            name_announcement.poll(&side_channel, clock.now());
+        }
+        for message in side_channel.receive() {
+            match message {
+                side_channel::SideMessage::Chat(text) => tracing::info!("{}", text),
+                side_channel::SideMessage::PlayerName { handle, name } => {
+                    if handle == remote_handle {
+                        side_channel.send(&side_channel::SideMessage::NameReceived { handle });
+                        if player_names[handle].is_none() {
+                            tracing::info!("Playing against {}.", name);
+                            player_names[handle] = Some(name);
+                        }
+                    }
+                }
+                side_channel::SideMessage::NameReceived { handle } => {
+                    name_announcement.answered(handle)
+                }
+                side_channel::SideMessage::Leaving { handle } if handle == remote_handle => {
+                    tracing::info!("The other player left the match.");
+                    break 'session;
+                }
+                side_channel::SideMessage::Leaving { .. } => (),
+                side_channel::SideMessage::Tuning { frame, tuning } => {
+                    match box_game::encoding::deserialize::<box_game::Tuning>(&tuning) {
+                        Ok(tuning) if tuning_change != Some((frame, tuning)) => {
+                            if !game.schedule_tuning(frame, tuning) {
+                                tracing::warn!(
+                                    "The tuning for frame {} arrived after simulating frame {}, the peers will desync",
+                                    frame,
+                                    game.current_frame()
+                                );
+                            }
+                            tuning_change = Some((frame, tuning));
+                        }
+                        Ok(_) => (),
+                        Err(e) => tracing::warn!("Invalid tuning from the other player: {}", e),
+                    }
+                }
+                side_channel::SideMessage::Checksum { frame, checksum } => {
+                    if let Some(frame) = desync_detector.add_remote(frame, checksum) {
+                        anyhow::bail!("desync at frame {}", frame);
+                    }
+                }
+            }
+        }
+        clock.sleep(timestep.until_next_tick(clock.now()).min(POLL_INTERVAL));
+    }
+
+    if reached_stop_frame {
+        // keep answering for a moment, so the other peer receives our last inputs and can confirm its frames too
+        let linger_start = Instant::now();
+        while linger_start.elapsed() < Duration::from_secs(1) {
+            sess.poll_remote_clients();
+            thread::sleep(POLL_INTERVAL);
+        }
+    } else {
+        side_channel.send(&side_channel::SideMessage::Leaving {
+            handle: local_handle,
+        });
+    }
+    if let Some(path) = &metrics_path {
+        game_loop.metrics.write_json(path)?;
+    }
+    if print_checksum {
+        match last_confirmed {
+            Some((_, checksum)) => println!("{}", checksum),
+            None => anyhow::bail!("no frame was confirmed"),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -0,0 +1,105 @@
+use ggrs_test_game::{box_game, cli, scenario};
+use std::env;
+use std::path::Path;
+
+/// The result of `determinism_check`, printed as JSON so runs on different platforms can be compared by a script.
+#[derive(serde::Serialize)]
+struct DeterminismResult {
+    seed: u32,
+    frames: ggrs::Frame,
+    players: usize,
+    checksum: u64,
+    os: &'static str,
+    arch: &'static str,
+}
+
+/// Simulates `frames` frames of seeded scripted inputs and returns the final checksum.
+/// The same seed has to produce the same checksum on every platform, anything else is float divergence.
+fn determinism_check(
+    seed: u32,
+    frames: ggrs::Frame,
+    players: usize,
+) -> Result<DeterminismResult, box_game::GameError> {
+    let mut game = box_game::Simulation::new(players, 1);
+    for frame in 0..frames {
+        let inputs = (0..players)
+            .map(|player| {
+                let input = box_game::scripted_input(seed, frame, player);
+                Some(box_game::input::encode(input).to_vec())
+            })
+            .collect();
+        game.simulate_frame(inputs)?;
+    }
+    Ok(DeterminismResult {
+        seed,
+        frames,
+        players,
+        checksum: game.last_checksum().1,
+        os: env::consts::OS,
+        arch: env::consts::ARCH,
+    })
+}
+
+/// Checks the simulation without a window, for CI:
+///
+/// - `determinism_check [--seed <seed>] [--frames <n>] [--players <n>]` simulates scripted inputs and prints the
+///   final checksum as JSON, to compare builds for different platforms
+/// - `scenario <file>...` plays scripted scenarios and checks their assertions, see `Scenario`
+///
+/// Needs no display libraries: cargo build --release --no-default-features --bin box_game_check
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("determinism_check") => {
+            let seed = match cli::take_flag_value(&mut args, "--seed") {
+                Some(seed) => seed.parse()?,
+                None => 0,
+            };
+            let frames = match cli::take_flag_value(&mut args, "--frames") {
+                Some(frames) => frames.parse()?,
+                None => 3600,
+            };
+            let players = match cli::take_flag_value(&mut args, "--players") {
+                Some(players) => players.parse()?,
+                None => 2,
+            };
+            if args.len() != 2 {
+                anyhow::bail!(
+                    "usage: box_game_check determinism_check [--seed <seed>] [--frames <n>] [--players <n>]"
+                );
+            }
+            let result = determinism_check(seed, frames, players)?;
+            println!("{}", serde_json::to_string(&result)?);
+            Ok(())
+        }
+        Some("scenario") => {
+            let mut failed = false;
+            for path in &args[2..] {
+                let scenario =
+                    scenario::Scenario::load(Path::new(path)).map_err(anyhow::Error::msg)?;
+                let failures = scenario.run();
+                if failures.is_empty() {
+                    println!("{}: ok", path);
+                } else {
+                    failed = true;
+                    println!("{}: {} failed assertions", path, failures.len());
+                    for failure in failures {
+                        println!("  {}", failure);
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        _ => anyhow::bail!(
+            "usage: box_game_check determinism_check [--seed <seed>] [--frames <n>] [--players <n>], or box_game_check scenario <file>..."
+        ),
+    }
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -0,0 +1,456 @@
+use anyhow::Context;
+use boxgame_net::session::SessionBuilder;
+use boxgame_net::{lossy_link, metrics};
+use ggrs::{Frame, GGRSError, GGRSRequest, PlayerHandle, SessionState};
+use ggrs_test_game::{alloc_counter, box_game, cli, trace};
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NUM_PLAYERS: usize = 2;
+/// How many frames GGRS may predict ahead, frames further back are confirmed.
+/// GGRS 0.2 does not allow changing it, so sweeps vary the frame delay or the latency instead.
+const MAX_PREDICTION_FRAMES: i32 = 8;
+/// A peer that makes no progress for this long is considered stuck.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Frame delays tried by `--sweep`.
+const SWEEP_FRAME_DELAYS: [u32; 5] = [0, 1, 2, 3, 4];
+/// Round trip times in milliseconds tried by `--latency-sweep`.
+const SWEEP_ROUND_TRIPS_MS: [u64; 7] = [0, 50, 100, 150, 200, 250, 300];
+
+#[cfg(feature = "alloc-profile")]
+#[global_allocator]
+static ALLOCATOR: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
+
+/// Histograms of the allocations made per request and per local input, see `alloc_counter`.
+const ALLOCATION_HISTOGRAMS: [&str; 4] = [
+    "input_allocations",
+    "save_allocations",
+    "load_allocations",
+    "advance_allocations",
+];
+
+/// How to set up a match.
+#[derive(Clone, Copy)]
+struct MatchConfig {
+    frames: Frame,
+    /// the peers use this port and the one above it, the lossy relay the two above those and a spectator the next
+    port: u16,
+    spectator: bool,
+    frame_delay: u32,
+    /// `None` to let the peers talk directly
+    link: Option<lossy_link::LinkConditions>,
+    /// whether the peers save their states as deltas, see `Simulation::delta_saves`
+    delta_saves: bool,
+}
+
+/// What a session saw during a match.
+struct SessionRun {
+    /// checksum of every confirmed frame
+    checksums: BTreeMap<Frame, u64>,
+    metrics: metrics::Metrics,
+}
+
+/// Runs one peer of the match until `frames` frames are confirmed. The inputs come from the turbo debug mode, so they
+/// are scripted but busy, and the same in every run.
+fn run_peer(
+    local_handle: PlayerHandle,
+    port: u16,
+    remote_addr: SocketAddr,
+    spectator_addr: Option<SocketAddr>,
+    config: MatchConfig,
+) -> Result<SessionRun, String> {
+    let mut builder = SessionBuilder::new(NUM_PLAYERS, box_game::INPUT_SIZE)
+        .local_player(local_handle)
+        .remote_player(1 - local_handle, remote_addr)
+        .frame_delay(config.frame_delay);
+    if let Some(addr) = spectator_addr {
+        builder = builder.spectator(addr);
+    }
+    let mut sess = builder.start_p2p(port).map_err(|e| e.to_string())?;
+
+    let mut game = box_game::Simulation::new(NUM_PLAYERS, 1);
+    let mut controls = box_game::Presentation::new(1);
+    controls.turbo = true;
+    game.delta_saves = config.delta_saves;
+    game.enable_frame_log();
+    let mut checksums = BTreeMap::new();
+    let mut metrics = metrics::Metrics::default();
+    let mut last_progress = Instant::now();
+
+    while game.current_frame() < config.frames + MAX_PREDICTION_FRAMES {
+        if last_progress.elapsed() > STALL_TIMEOUT {
+            return Err(format!(
+                "peer {} is stuck at frame {}",
+                local_handle,
+                game.current_frame()
+            ));
+        }
+        sess.poll_remote_clients();
+        if sess.current_state() != SessionState::Running {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let (input, input_allocations) =
+            alloc_counter::measure(|| controls.local_input(game.current_frame()));
+        match sess.advance_frame(local_handle, &input) {
+            Ok(requests) => {
+                metrics.record("input_allocations", input_allocations as f64);
+                metrics.record_requests(&requests);
+                let simulation_start = Instant::now();
+                for request in requests {
+                    let histogram = match request {
+                        GGRSRequest::SaveGameState { .. } => "save_allocations",
+                        GGRSRequest::LoadGameState { .. } => "load_allocations",
+                        GGRSRequest::AdvanceFrame { .. } => "advance_allocations",
+                    };
+                    let (result, allocations) =
+                        alloc_counter::measure(|| game.handle_request(request));
+                    result.map_err(|e| e.to_string())?;
+                    metrics.record(histogram, allocations as f64);
+                }
+                metrics.record(
+                    "simulation_time_ms",
+                    simulation_start.elapsed().as_secs_f64() * 1000.0,
+                );
+                for record in game.take_confirmed_frames() {
+                    if record.frame <= config.frames {
+                        checksums.insert(record.frame, record.checksum);
+                    }
+                }
+                last_progress = Instant::now();
+            }
+            // the other peer is behind, give it a moment
+            Err(GGRSError::PredictionThreshold) => {
+                metrics.increment("prediction_threshold_stalls", 1);
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+        for _ in sess.events() {}
+    }
+
+    // keep answering for a moment, so the other peer receives our last inputs
+    let linger_start = Instant::now();
+    while linger_start.elapsed() < Duration::from_secs(1) {
+        sess.poll_remote_clients();
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(SessionRun { checksums, metrics })
+}
+
+/// Spectates the match hosted by `host_addr` until `frames` frames are simulated.
+/// Spectators only ever simulate confirmed inputs, so all of their frames are final.
+fn run_spectator(port: u16, host_addr: SocketAddr, frames: Frame) -> Result<SessionRun, String> {
+    let mut sess = SessionBuilder::new(NUM_PLAYERS, box_game::INPUT_SIZE)
+        .start_spectator(port, host_addr)
+        .map_err(|e| e.to_string())?;
+
+    let mut game = box_game::Simulation::new(NUM_PLAYERS, 1);
+    game.enable_frame_log();
+    let mut checksums = BTreeMap::new();
+    let mut metrics = metrics::Metrics::default();
+    let mut last_progress = Instant::now();
+
+    while game.current_frame() < frames {
+        if last_progress.elapsed() > STALL_TIMEOUT {
+            return Err(format!(
+                "the spectator is stuck at frame {}",
+                game.current_frame()
+            ));
+        }
+        sess.poll_remote_clients();
+        if sess.current_state() != SessionState::Running {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        match sess.advance_frame() {
+            Ok(requests) => {
+                metrics.record_requests(&requests);
+                game.handle_requests(requests).map_err(|e| e.to_string())?;
+                for record in game.take_all_frames() {
+                    if record.frame <= frames {
+                        checksums.insert(record.frame, record.checksum);
+                    }
+                }
+                last_progress = Instant::now();
+            }
+            // waiting for inputs from the host
+            Err(GGRSError::PredictionThreshold) => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e.to_string()),
+        }
+        for _ in sess.events() {}
+    }
+    Ok(SessionRun { checksums, metrics })
+}
+
+/// The first frame the two runs disagree on, or a description of missing frames.
+fn compare(a: &BTreeMap<Frame, u64>, b: &BTreeMap<Frame, u64>, frames: Frame) -> Option<String> {
+    for frame in 1..=frames {
+        match (a.get(&frame), b.get(&frame)) {
+            (Some(checksum_a), Some(checksum_b)) if checksum_a == checksum_b => (),
+            (Some(checksum_a), Some(checksum_b)) => {
+                return Some(format!(
+                    "desync at frame {}: checksum {} vs {}",
+                    frame, checksum_a, checksum_b
+                ))
+            }
+            _ => return Some(format!("frame {} is missing", frame)),
+        }
+    }
+    None
+}
+
+/// Plays a match with each session in its own thread and checks that all sessions agree on every checksum.
+/// Returns the runs of the host, the client and the spectator, if there is one.
+fn run_match(config: MatchConfig) -> anyhow::Result<Vec<SessionRun>> {
+    let port = config.port;
+    let addrs: [SocketAddr; NUM_PLAYERS] = [
+        format!("127.0.0.1:{}", port).parse()?,
+        format!("127.0.0.1:{}", port + 1).parse()?,
+    ];
+    let mut link = None;
+    let mut remote_addrs = [addrs[1], addrs[0]];
+    if let Some(conditions) = config.link {
+        let relay =
+            lossy_link::LossyLink::bind([port + 2, port + 3], addrs, conditions, port as u64)?;
+        remote_addrs = [relay.addr_for(0)?, relay.addr_for(1)?];
+        link = Some(relay);
+    }
+    let spectator_addr: Option<SocketAddr> = match config.spectator {
+        true => Some(format!("127.0.0.1:{}", port + 4).parse()?),
+        false => None,
+    };
+
+    let mut threads: Vec<_> = (0..NUM_PLAYERS)
+        .map(|handle| {
+            let port = addrs[handle].port();
+            let remote_addr = remote_addrs[handle];
+            // only the host sends inputs to the spectator
+            let spectator_addr = spectator_addr.filter(|_| handle == 0);
+            thread::spawn(move || run_peer(handle, port, remote_addr, spectator_addr, config))
+        })
+        .collect();
+    if let Some(addr) = spectator_addr {
+        let host_addr = addrs[0];
+        threads.push(thread::spawn(move || {
+            run_spectator(addr.port(), host_addr, config.frames)
+        }));
+    }
+    if let Some(link) = link.as_mut() {
+        while !threads.iter().all(|thread| thread.is_finished()) {
+            link.pump();
+            thread::sleep(lossy_link::TICK);
+        }
+    }
+    let mut runs = Vec::new();
+    for thread in threads {
+        runs.push(
+            thread
+                .join()
+                .expect("session thread panicked")
+                .map_err(anyhow::Error::msg)?,
+        );
+    }
+
+    if let Some(difference) = compare(&runs[0].checksums, &runs[1].checksums, config.frames) {
+        anyhow::bail!("the peers disagree: {}", difference);
+    }
+    if config.spectator {
+        if let Some(difference) = compare(&runs[0].checksums, &runs[2].checksums, config.frames) {
+            anyhow::bail!("the spectator disagrees with the host: {}", difference);
+        }
+    }
+    Ok(runs)
+}
+
+/// Prints the average allocations per request and per `advance_frame` call of a peer.
+fn print_allocations(metrics: &metrics::Metrics) {
+    let mut total = 0.0;
+    for name in ALLOCATION_HISTOGRAMS.iter() {
+        if let Some(histogram) = metrics.histogram(name) {
+            println!(
+                "{}: {:.2} per call, {} at most",
+                name,
+                histogram.sum / histogram.count as f64,
+                histogram.max
+            );
+            total += histogram.sum;
+        }
+    }
+    println!(
+        "{:.2} allocations per advance_frame call on the host",
+        total / metrics.counter("frames").max(1) as f64
+    );
+}
+
+/// Plays one match per setting and prints a table row with the rollbacks, prediction stalls and timings of each.
+/// `label` names the setting that differs between the rows. Stops at the first match that fails.
+fn run_sweep(label: &str, settings: &[(String, MatchConfig)]) -> anyhow::Result<()> {
+    println!(
+        "{} | rollbacks | resimulated frames | deepest rollback | stalls | avg sim ms | wall s",
+        label
+    );
+    for (setting, config) in settings {
+        let start = Instant::now();
+        let runs = run_match(*config).with_context(|| format!("{} {}", label, setting))?;
+        // both peers together
+        let peers = &runs[..NUM_PLAYERS];
+        let counter = |name| {
+            peers
+                .iter()
+                .map(|run| run.metrics.counter(name))
+                .sum::<u64>()
+        };
+        let deepest = peers
+            .iter()
+            .filter_map(|run| run.metrics.histogram("rollback_length_frames"))
+            .map(|histogram| histogram.max)
+            .fold(0.0, f64::max);
+        let simulation: Vec<_> = peers
+            .iter()
+            .filter_map(|run| run.metrics.histogram("simulation_time_ms"))
+            .collect();
+        let average_simulation_ms = simulation
+            .iter()
+            .map(|histogram| histogram.sum)
+            .sum::<f64>()
+            / simulation
+                .iter()
+                .map(|histogram| histogram.count)
+                .sum::<u64>()
+                .max(1) as f64;
+        println!(
+            "{:>width$} | {:>9} | {:>18} | {:>16} | {:>6} | {:>10.3} | {:>6.1}",
+            setting,
+            counter("rollbacks"),
+            counter("resimulated_frames"),
+            deepest,
+            counter("prediction_threshold_stalls"),
+            average_simulation_ms,
+            start.elapsed().as_secs_f64(),
+            width = label.len()
+        );
+    }
+    Ok(())
+}
+
+/// Plays matches between two P2P sessions on loopback, each in its own thread, and checks that both peers computed
+/// the same checksum for every frame. With a spectator, its checksums have to match the host's as well.
+/// Exits with status 1 on a mismatch, so it can run as a regression test.
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --frames <n>, the match runs for n frames instead of 3000
+    let frames: Frame = match cli::take_flag_value(&mut args, "--frames") {
+        Some(frames) => frames.parse()?,
+        None => 3000,
+    };
+    // with --port <port>, the peers use that port and the one above it instead of 7100 and 7101
+    let port: u16 = match cli::take_flag_value(&mut args, "--port") {
+        Some(port) => port.parse()?,
+        None => 7100,
+    };
+    // with --spectator, a spectator joins the host (peer 0) from the port four above it
+    let spectator = cli::take_flag(&mut args, "--spectator");
+    // with --sweep, the same match is played once per frame delay in `SWEEP_FRAME_DELAYS` and the rollbacks and
+    // timings of each run are printed as a table
+    let sweep = cli::take_flag(&mut args, "--sweep");
+    // with --latency-sweep, the match is played once per round trip time in `SWEEP_ROUND_TRIPS_MS` instead.
+    // --jitter and --loss still apply, --lag is replaced by half the round trip time.
+    let latency_sweep = cli::take_flag(&mut args, "--latency-sweep");
+    // with --delta-saves, the peers save their states as deltas against recent full states
+    let delta_saves = cli::take_flag(&mut args, "--delta-saves");
+    // with --lag <ms>, --jitter <ms> or --loss <percent>, the peers talk through a lossy relay on the two ports
+    // above theirs, see `LossyLink`
+    let mut condition = |flag: &str| -> anyhow::Result<Option<u64>> {
+        match cli::take_flag_value(&mut args, flag) {
+            Some(value) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    };
+    let (lag, jitter, loss) = (
+        condition("--lag")?,
+        condition("--jitter")?,
+        condition("--loss")?,
+    );
+    // with --trace <file>, a chrome tracing file of all spans is written
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    if args.len() != 1 {
+        anyhow::bail!(
+            "unexpected argument {}, usage: box_game_loopback [--frames <n>] [--port <port>] [--spectator] [--sweep | --latency-sweep] [--delta-saves] [--lag <ms>] [--jitter <ms>] [--loss <percent>] [--trace <file>]",
+            args[1]
+        );
+    }
+
+    let link =
+        (lag.is_some() || jitter.is_some() || loss.is_some()).then(|| lossy_link::LinkConditions {
+            lag: Duration::from_millis(lag.unwrap_or(0)),
+            jitter: Duration::from_millis(jitter.unwrap_or(0)),
+            loss: loss.unwrap_or(0) as f64 / 100.0,
+            max_outage: None,
+        });
+    let config = MatchConfig {
+        frames,
+        port,
+        spectator,
+        frame_delay: 2,
+        link,
+        delta_saves,
+    };
+
+    if !sweep && !latency_sweep {
+        let runs = run_match(config)?;
+        println!(
+            "All sessions agree on the checksums of all {} frames.",
+            frames
+        );
+        if cfg!(feature = "alloc-profile") {
+            print_allocations(&runs[0].metrics);
+        }
+        return Ok(());
+    }
+
+    let settings: Vec<_> = if latency_sweep {
+        // a match played locally has no latency of its own, half the round trip is added in each direction
+        SWEEP_ROUND_TRIPS_MS
+            .iter()
+            .map(|&round_trip| {
+                let conditions = lossy_link::LinkConditions {
+                    lag: Duration::from_millis(round_trip / 2),
+                    ..link.unwrap_or_default()
+                };
+                let config = MatchConfig {
+                    link: Some(conditions),
+                    ..config
+                };
+                (round_trip.to_string(), config)
+            })
+            .collect()
+    } else {
+        SWEEP_FRAME_DELAYS
+            .iter()
+            .map(|&frame_delay| {
+                let config = MatchConfig {
+                    frame_delay,
+                    ..config
+                };
+                (frame_delay.to_string(), config)
+            })
+            .collect()
+    };
+    let label = if latency_sweep {
+        "rtt ms"
+    } else {
+        "frame delay"
+    };
+    run_sweep(label, &settings)
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
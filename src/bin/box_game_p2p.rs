@@ -0,0 +1,797 @@
+use boxgame_net::{desync, latency, lossy_link, metrics, side_channel};
+use ggrs::{Frame, PlayerHandle};
+use ggrs_test_game::notifications::{Notification, Toasts};
+#[cfg(feature = "discord")]
+use ggrs_test_game::presence;
+use ggrs_test_game::{
+    box_game, chat, cli, config, crash, game_loop, input_recording, inspector, replay, rumble,
+    sim_thread, text, trace, tuning_file, window,
+};
+use piston::input::{RenderEvent, TextEvent, UpdateEvent};
+use piston::{Button, ControllerAxisEvent, IdleEvent, Key, PressEvent, ReleaseEvent};
+use std::env;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FPS: u64 = 60;
+const NUM_PLAYERS: usize = 2;
+const CHECKSUM_PERIOD: i32 = 100;
+/// Tuning changes apply this many frames after the file was saved, so the other peer learns about them in time.
+const TUNING_LEAD: Frame = 2 * FPS as Frame;
+/// The tuning file is checked for changes this often.
+const TUNING_POLL_PERIOD: Duration = Duration::from_millis(500);
+/// Chance that a packet is dropped while packet loss is toggled on.
+const PACKET_LOSS: f64 = 0.1;
+/// The spectator is told the player names this often, it may join at any time.
+const LISTENER_NAMES_PERIOD: Duration = Duration::from_secs(1);
+
+const WINDOW_HEIGHT: u32 = 800;
+const WINDOW_WIDTH: u32 = 600;
+
+/// Maps a key to the local player slot and button index it controls.
+fn key_binding(key: Key, two_local: bool) -> Option<(usize, usize)> {
+    match key {
+        Key::W => Some((0, 0)),
+        Key::A => Some((0, 1)),
+        Key::S => Some((0, 2)),
+        Key::D => Some((0, 3)),
+        Key::Space => Some((0, 4)),
+        Key::Up if two_local => Some((1, 0)),
+        Key::Left if two_local => Some((1, 1)),
+        Key::Down if two_local => Some((1, 2)),
+        Key::Right if two_local => Some((1, 3)),
+        Key::RShift if two_local => Some((1, 4)),
+        _ => None,
+    }
+}
+
+/// Dumps the evidence of a desync into a diagnostic bundle.
+fn report_desync(
+    detector: &desync::DesyncDetector,
+    local_handle: PlayerHandle,
+    frame: Frame,
+    replay: Option<&str>,
+    replay_writer: Option<&mut replay::ReplayWriter>,
+    toasts: &mut Toasts,
+) {
+    let now = Instant::now();
+    toasts.push(
+        Notification::error(format!("Desync detected at frame {}!", frame)),
+        now,
+    );
+    if let Some(writer) = replay_writer {
+        if let Err(e) = writer.flush() {
+            toasts.push(
+                Notification::warning(format!("Could not flush the replay: {}", e)),
+                now,
+            );
+        }
+    }
+    if replay.is_none() {
+        tracing::warn!(
+            "No replay is being recorded, pass --record-replay to include one in the diagnostics."
+        );
+    }
+    match detector.write_bundle(local_handle, replay.map(Path::new)) {
+        Ok(dir) => toasts.push(
+            Notification::info(format!("Wrote desync diagnostics to {}", dir.display())),
+            now,
+        ),
+        Err(e) => toasts.push(
+            Notification::warning(format!("Could not write desync diagnostics: {}", e)),
+            now,
+        ),
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --trace <file>, spans of the session loop are written to a chrome tracing file for profiling
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    // Ctrl-C leaves the event loop below like closing the window does, so everything is flushed on the way out
+    cli::handle_interrupts();
+    // a panic writes the last state and the recent inputs and checksums to a crash-<timestamp> directory
+    crash::install_hook();
+    // with --record-inputs <file>, the local inputs are written to that file
+    let mut input_recorder = match cli::take_flag_value(&mut args, "--record-inputs") {
+        Some(path) => Some(input_recording::InputRecorder::create(Path::new(&path))?),
+        None => None,
+    };
+    // with --play-inputs <file>, the local inputs are read from a recording instead of the keyboard
+    let input_playback = match cli::take_flag_value(&mut args, "--play-inputs") {
+        Some(path) => Some(input_recording::InputPlayback::load(Path::new(&path))?),
+        None => None,
+    };
+    // with --toggle-thrust <slots>, UP toggles thrust for the given comma separated local players
+    let toggle_thrust = cli::take_flag_value(&mut args, "--toggle-thrust");
+    // with --rumble <intensity>, gamepads rumble with the given strength between 0 and 1 when a local box is hit
+    let rumble_intensity: f32 = match cli::take_flag_value(&mut args, "--rumble") {
+        Some(intensity) => intensity.parse()?,
+        None => 0.5,
+    };
+    // with --measure-latency, the time from key press to the rendered and to the confirmed frame is measured and
+    // reported
+    let measure_latency = cli::take_flag(&mut args, "--measure-latency");
+    // with --record-replay <file>, all confirmed frames are written to a replay file
+    let replay_path = cli::take_flag_value(&mut args, "--record-replay");
+    // with --resume <file>, the match continues from the end of a replay. Both players have to load the same file.
+    let resume_replay = match cli::take_flag_value(&mut args, "--resume") {
+        Some(path) => Some(replay::Replay::load(Path::new(&path))?),
+        None => None,
+    };
+    // with --diff-rollbacks, every rollback logs which fields of the state were mispredicted and by how much
+    let diff_rollbacks = cli::take_flag(&mut args, "--diff-rollbacks");
+    // with --double-simulate, every frame is simulated twice and the game stops with an error if the results differ
+    let double_simulate = cli::take_flag(&mut args, "--double-simulate");
+    // with --delta-saves, states are saved for GGRS as deltas against recent full states
+    let delta_saves = cli::take_flag(&mut args, "--delta-saves");
+    // with --metrics <file>, runtime metrics are written to that JSON file every minute and at exit
+    let metrics_path = cli::take_flag_value(&mut args, "--metrics");
+    // with --state-size-warning <bytes>, saved states larger than that log a warning instead of 64 KiB
+    let state_size_warning: usize = match cli::take_flag_value(&mut args, "--state-size-warning") {
+        Some(bytes) => bytes.parse()?,
+        None => box_game::DEFAULT_STATE_SIZE_WARNING,
+    };
+    // with --headless, no window is opened. Combine it with --play-inputs, there is no keyboard to play with.
+    let headless = cli::take_flag(&mut args, "--headless");
+    // with --frames <n>, the game exits once frame n is confirmed, e.g. for a scripted smoke test
+    let stop_frame: Option<Frame> = match cli::take_flag_value(&mut args, "--frames") {
+        Some(frames) => Some(frames.parse()?),
+        None => None,
+    };
+    // with --print-checksum, the checksum of the last confirmed frame is printed at exit
+    let print_checksum = cli::take_flag(&mut args, "--print-checksum");
+    // with --tuning <file>, the gameplay constants are read from that TOML file and reloaded whenever it is saved.
+    // Changes are sent to the other peer and both switch to them on the same frame, only one of them should tune.
+    let mut tuning_file = cli::take_flag_value(&mut args, "--tuning")
+        .map(|path| tuning_file::TuningFile::new(PathBuf::from(path)));
+    // with --lossy-link <port>, the session runs on <port> behind a relay on the session port, so packet loss can be
+    // toggled with F10. The relay talks to the session from <port>+1.
+    let lossy_link_port: Option<u16> = match cli::take_flag_value(&mut args, "--lossy-link") {
+        Some(port) => Some(port.parse()?),
+        None => None,
+    };
+    // with --discord-app <id>, the match is shown in the player's Discord presence, which needs the discord feature.
+    // With --public-addr <addr>, the address friends reach this peer at, it also offers them a secret for --join.
+    let discord_app = cli::take_flag_value(&mut args, "--discord-app");
+    let public_addr: Option<SocketAddr> = match cli::take_flag_value(&mut args, "--public-addr") {
+        Some(addr) => Some(addr.parse()?),
+        None => None,
+    };
+    #[cfg(not(feature = "discord"))]
+    {
+        if discord_app.is_some() || public_addr.is_some() {
+            anyhow::bail!("the Discord presence needs a build with the discord feature");
+        }
+    }
+    // the session settings are layered: defaults, box_game.toml or --config <file>, BOXGAME_* variables and the
+    // remaining arguments `<port> <handle> <remote> [spectator]` with --two-local, --name, --frame-delay and --join
+    let config = config::load(&mut args)?;
+    let port = config.port;
+    let local_handle = config.local_handle;
+    let remote_handle: PlayerHandle = 1 - local_handle;
+    let remote_addr = config.remote_addr;
+    let local_players = config.local_players;
+    let two_local = local_players == 2;
+
+    // player names are exchanged over the side channel, until then players go by their handle
+    let mut player_names: Vec<Option<String>> = vec![None; NUM_PLAYERS];
+    player_names[local_handle] = Some(
+        config
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("P{}", local_handle)),
+    );
+
+    // create and start a GGRS session with two players and the optional spectator
+    let input_size = box_game::INPUT_SIZE * local_players;
+    let mut frame_delay = config.frame_delay;
+    let (sess, link_conditions) = match lossy_link_port {
+        Some(session_port) => {
+            // the session sends to the spectator directly, from a port the spectator does not know
+            if config.spectator_addr.is_some() {
+                anyhow::bail!("--lossy-link cannot be used with a spectator");
+            }
+            let local = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+            let link = lossy_link::LossyLink::bind_addrs(
+                [
+                    local(session_port + 1),
+                    SocketAddr::from(([0, 0, 0, 0], port)),
+                ],
+                [local(session_port), remote_addr],
+                lossy_link::LinkConditions::default(),
+                port as u64,
+            )?;
+            let mut session_config = config.clone();
+            session_config.remote_addr = link.addr_for(0)?;
+            let sess = session_config.session_builder().start_p2p(session_port)?;
+            (sess, Some(spawn_lossy_link(link)))
+        }
+        None => (config.session_builder().start_p2p(port)?, None),
+    };
+
+    // chat and checksums are exchanged next to the session on their own socket
+    let mut side_channel = side_channel::SideChannel::bind(config.side_port)?;
+    side_channel.add_peer(config.remote_side_addr());
+    // the spectator is shown the chat of both players, but cannot write into it
+    if let Some(spectator_addr) = config.spectator_side_addr() {
+        side_channel.add_listener(spectator_addr);
+    }
+    let mut chat = chat::Chat::default();
+    // a missing Discord client is no reason not to play
+    #[cfg(feature = "discord")]
+    let mut discord = match &discord_app {
+        Some(app_id) => match presence::DiscordPresence::connect(app_id) {
+            Ok(discord) => Some(discord),
+            Err(e) => {
+                tracing::warn!("{:#}, playing without the Discord presence", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut desync_detector = desync::DesyncDetector::default();
+
+    let (mut window, mut gl) = window::open("Box Game", [WINDOW_WIDTH, WINDOW_HEIGHT], headless)?;
+
+    // load a font to render text
+    let mut text = text::Text::from_assets()?;
+
+    // Create a new box game
+    let mut game = box_game::Simulation::new(NUM_PLAYERS, local_players);
+    let mut presentation = box_game::Presentation::new(local_players);
+    game.diff_rollbacks = diff_rollbacks;
+    game.double_simulate = double_simulate;
+    game.delta_saves = delta_saves;
+    game.state_size_warning = state_size_warning;
+    if let Some(slots) = toggle_thrust {
+        for slot in slots.split(',') {
+            let slot: usize = slot.parse()?;
+            if slot >= local_players {
+                anyhow::bail!(
+                    "--toggle-thrust {}: the local players are 0{}",
+                    slot,
+                    if two_local {
+                        " and 1"
+                    } else {
+                        ", 1 needs --two-local"
+                    }
+                );
+            }
+            presentation.thrust_toggle[slot] = true;
+        }
+    }
+    let mut rumble = rumble::Rumble::new(rumble_intensity);
+    // confirmed frames feed the desync detection and the optional replay
+    if let Some(replay) = &resume_replay {
+        if let Err(reason) = replay.header.check_compatible(game.map_hash()) {
+            anyhow::bail!("cannot resume from this replay: {}", reason);
+        }
+        if replay.header.num_players != NUM_PLAYERS {
+            anyhow::bail!(
+                "cannot resume from this replay: it has {} players instead of {}",
+                replay.header.num_players,
+                NUM_PLAYERS
+            );
+        }
+        if replay.header.local_players != local_players {
+            anyhow::bail!("cannot resume from this replay: different number of local players");
+        }
+        // fast-forward through the replay, the new session then counts frames from 0 again. A replay of a match
+        // that was resumed itself starts from the state it was resumed with.
+        game.set_tuning(replay.tuning());
+        if let Some(keyframe) = &replay.header.start_keyframe {
+            game.load_keyframe(keyframe)?;
+        }
+        for record in &replay.frames {
+            game.simulate_frame(record.inputs.clone())?;
+        }
+        tracing::info!(
+            "Resuming after frame {} with checksum {}, both players should see the same values.",
+            game.last_checksum().0,
+            game.last_checksum().1
+        );
+        game.rebase_frame();
+    }
+    // replays of a resumed match start from the resumed state
+    let start_keyframe = match &resume_replay {
+        Some(_) => Some(game.save_keyframe()?),
+        None => None,
+    };
+    game.enable_frame_log();
+    // the replay is created with the first confirmed frames, so the player names are known by then
+    let mut replay_writer: Option<replay::ReplayWriter> = None;
+    let local_boxes = local_handle * local_players..(local_handle + 1) * local_players;
+
+    // the render thread draws a copy of the simulation, updated with the state of every advanced frame
+    let mut view = box_game::Simulation::new(NUM_PLAYERS, local_players);
+    view.load_state_copy(&game.copy_state()?)?;
+    let map_hash = game.map_hash();
+    // the session and the simulation tick on their own thread, so slow rendering cannot hold them up
+    let mut sim = sim_thread::SimThread::spawn(
+        sess,
+        game,
+        sim_thread::SimConfig {
+            fps: FPS,
+            local_handle,
+            remote_handle,
+            input_size,
+            playback: input_playback,
+            metrics_path: metrics_path.map(PathBuf::from),
+        },
+    );
+    let (mut last_save_size, mut held_state_bytes) = (0, 0);
+    let mut burst_window: Option<metrics::BurstWindow> = None;
+    // session events, non-fatal errors and news from the other peer, shown for a few seconds
+    let mut toasts = Toasts::default();
+
+    let mut events = game_loop::events(FPS);
+    // in frame-step mode, the simulation only advances one frame per press of the step key.
+    // The remote peer keeps predicting until it hits the prediction limit and then waits as well.
+    let mut frame_step = false;
+    // fake lag holds back polling, so incoming packets are only processed every 100ms
+    let mut fake_lag = false;
+    // packet loss needs the relay of --lossy-link, GGRS 0.2 owns its socket
+    let mut packet_loss = false;
+    let mut latency_probe = latency::LatencyProbe::default();
+    let mut inspector = inspector::Inspector::default();
+    // a copy of the game at the frame rendering was frozen on, the simulation itself keeps running
+    let mut frozen: Option<box_game::Simulation> = None;
+    // frame and checksum of the last confirmed frame, up to `stop_frame`
+    let mut last_confirmed: Option<(Frame, u64)> = None;
+    let mut reached_stop_frame = false;
+    // the last tuning change, announced to the other peer and the listeners until it applies
+    let mut tuning_change: Option<(Frame, box_game::Tuning)> = None;
+    let mut last_tuning_poll = Instant::now() - TUNING_POLL_PERIOD;
+    let mut last_listener_names = Instant::now() - LISTENER_NAMES_PERIOD;
+    let mut name_announcement = side_channel::NameAnnouncement::new(
+        local_handle,
+        player_names[local_handle].clone().unwrap(),
+    );
+
+    // event loop
+    'events: while let Some(e) = events.next(&mut window) {
+        if cli::interrupted() {
+            break;
+        }
+
+        // render
+        if let (Some(args), Some(gl)) = (e.render_args(), gl.as_mut()) {
+            presentation.debug_lines = vec![
+                format!("Frame delay: {} (F2/F3)", frame_delay),
+                format!("Fake lag: {} (F4)", fake_lag),
+                match &link_conditions {
+                    Some(_) => format!("Packet loss: {} (F10)", packet_loss),
+                    None => "Packet loss: F10 needs --lossy-link".to_string(),
+                },
+                format!("Turbo: {} (F1)", presentation.turbo),
+                "Stress save/load: F5".to_string(),
+                format!("Frame step: {} (F6, F7 to step)", frame_step),
+                format!("Inspector: {} (F8, F9 to freeze)", inspector.open),
+                format!(
+                    "Saved states: {} B, {} B held",
+                    last_save_size, held_state_bytes
+                ),
+            ];
+            if let Some(window) = &burst_window {
+                presentation.debug_lines.push(format!(
+                    "Worst burst/s: {:.2} ms ({} loads, {} saves, {} advances), rollbacks {:.2} ms/s",
+                    window.worst_ms,
+                    window.worst.loads,
+                    window.worst.saves,
+                    window.worst.advances,
+                    window.rollback_ms
+                ));
+            }
+            presentation.chat_lines = chat.lines();
+            presentation.toasts = toasts.visible(Instant::now());
+            if measure_latency {
+                latency_probe.frame_rendered(view.current_frame());
+                presentation.debug_lines.extend(latency_probe.reports());
+            }
+            let view = frozen.as_ref().unwrap_or(&view);
+            if inspector.open {
+                let lines = inspector.lines(&view.state_tree());
+                presentation.debug_lines.extend(lines);
+            }
+            presentation.render(gl, &mut text, &args, view)?;
+        }
+
+        // game update
+        if e.update_args().is_some() {
+            // the keys are sampled at the render thread's pace, the simulation thread sends the latest input it got.
+            // It latches the presses until a tick with them was accepted, so they are consumed here right away.
+            sim.send(sim_thread::Command::Input(
+                presentation.local_input(view.current_frame()),
+            ));
+            presentation.clear_input_edges();
+
+            // the thread only ends on its own if the session failed, `finish` reports why
+            let updates = match sim.updates() {
+                Some(updates) => updates,
+                None => break,
+            };
+            for notification in sim.notifications() {
+                toasts.push(notification, Instant::now());
+            }
+            for update in updates {
+                view.load_state_copy(&update.state)?;
+                last_save_size = update.last_save_size;
+                held_state_bytes = update.held_state_bytes;
+                burst_window = update.burst_window.or(burst_window);
+                let (frame, local_input) = &update.sent_input;
+                if let Some(recorder) = input_recorder.as_mut() {
+                    recorder.record(*frame, local_input)?;
+                }
+                // the input is used for frame + frame_delay, which produces the state one frame later
+                latency_probe.input_sent(frame + frame_delay as i32 + 1);
+                let confirmed_frames = update.confirmed_frames;
+                if let (Some(path), None) = (&replay_path, &replay_writer) {
+                    if !confirmed_frames.is_empty() {
+                        let names = (0..NUM_PLAYERS)
+                            .map(|handle| match &player_names[handle] {
+                                Some(name) => name.clone(),
+                                None => format!("P{}", handle),
+                            })
+                            .collect();
+                        let header = replay::ReplayHeader::new(
+                            names,
+                            NUM_PLAYERS,
+                            local_players,
+                            input_size,
+                            map_hash,
+                            start_keyframe.clone(),
+                        );
+                        replay_writer =
+                            Some(replay::ReplayWriter::create(Path::new(path), &header)?);
+                    }
+                }
+                if let Some(writer) = replay_writer.as_mut() {
+                    writer.write_frames(&confirmed_frames)?;
+                }
+                if let Some(record) = confirmed_frames.last() {
+                    latency_probe.frame_confirmed(record.frame);
+                }
+                for record in &confirmed_frames {
+                    if stop_frame.is_none_or(|stop_frame| record.frame <= stop_frame) {
+                        last_confirmed = Some((record.frame, record.checksum));
+                    }
+                }
+                if stop_frame.is_some() && last_confirmed.map(|(frame, _)| frame) == stop_frame {
+                    reached_stop_frame = true;
+                    break 'events;
+                }
+                for record in confirmed_frames
+                    .iter()
+                    .filter(|record| record.frame % CHECKSUM_PERIOD == 0)
+                {
+                    side_channel.send(&side_channel::SideMessage::Checksum {
+                        frame: record.frame,
+                        checksum: record.checksum,
+                    });
+                    if let Some(frame) = desync_detector.add_local(record.frame, record.checksum) {
+                        report_desync(
+                            &desync_detector,
+                            local_handle,
+                            frame,
+                            replay_path.as_deref(),
+                            replay_writer.as_mut(),
+                            &mut toasts,
+                        );
+                    }
+                }
+
+                // feedback for hits involving a local box
+                let local_hit = update.confirmed_hits.iter().any(|hit| {
+                    local_boxes.contains(&hit.players.0) || local_boxes.contains(&hit.players.1)
+                });
+                if let Some(rumble) = rumble.as_mut() {
+                    rumble.update();
+                    if local_hit {
+                        rumble.play();
+                    }
+                }
+            }
+        }
+
+        // idle
+        if let Some(_args) = e.idle_args() {
+            if let Some(file) = tuning_file.as_mut() {
+                if last_tuning_poll.elapsed() >= TUNING_POLL_PERIOD {
+                    last_tuning_poll = Instant::now();
+                    match file.poll() {
+                        Some(Ok(tuning)) => {
+                            let frame = view.current_frame() + TUNING_LEAD;
+                            toasts.push(
+                                Notification::info(format!(
+                                    "New tuning from frame {} on: {:?}",
+                                    frame, tuning
+                                )),
+                                Instant::now(),
+                            );
+                            sim.send(sim_thread::Command::ScheduleTuning(frame, tuning));
+                            tuning_change = Some((frame, tuning));
+                        }
+                        Some(Err(e)) => {
+                            toasts.push(Notification::warning(format!("{:#}", e)), Instant::now())
+                        }
+                        None => (),
+                    }
+                }
+            }
+            if let Some((frame, tuning)) = tuning_change {
+                // spectators simulate the change on the same frame as the peers
+                if view.current_frame() < frame {
+                    let message = side_channel::SideMessage::Tuning {
+                        frame,
+                        tuning: box_game::encoding::serialize(&tuning)?,
+                    };
+                    side_channel.send(&message);
+                    side_channel.send_to_listeners(&message);
+                }
+            }
+            // the session only advances once both peers are connected
+            if view.current_frame() > 0 {
+                name_announcement.poll(&side_channel, Instant::now());
+            }
+            if last_listener_names.elapsed() >= LISTENER_NAMES_PERIOD {
+                last_listener_names = Instant::now();
+                for (handle, name) in player_names.iter().enumerate() {
+                    if let Some(name) = name {
+                        side_channel.send_to_listeners(&side_channel::SideMessage::PlayerName {
+                            handle,
+                            name: name.clone(),
+                        });
+                    }
+                }
+            }
+            #[cfg(feature = "discord")]
+            {
+                if let Some(current) = discord.as_mut() {
+                    let status = presence::Status {
+                        frame: view.current_frame(),
+                        opponent: player_names[remote_handle].clone(),
+                        // the session only advances once both peers are synchronized
+                        opponent_synchronized: view.current_frame() > 0,
+                        join_secret: public_addr.map(|addr| presence::JoinSecret {
+                            addr,
+                            handle: local_handle,
+                        }),
+                    };
+                    if let Err(e) = current.update(&status, Instant::now()) {
+                        toasts.push(Notification::warning(format!("{:#}", e)), Instant::now());
+                        discord = None;
+                    }
+                }
+            }
+            for message in side_channel.receive() {
+                match message {
+                    side_channel::SideMessage::Chat(text) => {
+                        side_channel
+                            .send_to_listeners(&side_channel::SideMessage::Chat(text.clone()));
+                        chat.push_history(text);
+                    }
+                    side_channel::SideMessage::PlayerName { handle, name } => {
+                        if handle < NUM_PLAYERS && handle != local_handle {
+                            side_channel.send(&side_channel::SideMessage::NameReceived { handle });
+                            player_names[handle] = Some(name);
+                        }
+                    }
+                    side_channel::SideMessage::NameReceived { handle } => {
+                        name_announcement.answered(handle)
+                    }
+                    side_channel::SideMessage::Leaving { handle } if handle == remote_handle => {
+                        toasts.push(
+                            Notification::info(format!(
+                                "{} left the match",
+                                player_names[handle]
+                                    .as_deref()
+                                    .unwrap_or("The other player")
+                            )),
+                            Instant::now(),
+                        );
+                        sim.send(sim_thread::Command::Disconnect(handle));
+                    }
+                    side_channel::SideMessage::Leaving { .. } => (),
+                    side_channel::SideMessage::Tuning { frame, tuning } => {
+                        match box_game::encoding::deserialize::<box_game::Tuning>(&tuning) {
+                            // the change is announced repeatedly, only the first announcement counts
+                            Ok(tuning) if tuning_change != Some((frame, tuning)) => {
+                                toasts.push(
+                                    Notification::info(format!(
+                                        "The other player changed the tuning from frame {} on: {:?}",
+                                        frame, tuning
+                                    )),
+                                    Instant::now(),
+                                );
+                                sim.send(sim_thread::Command::ScheduleTuning(frame, tuning));
+                                tuning_change = Some((frame, tuning));
+                            }
+                            Ok(_) => (),
+                            Err(e) => toasts.push(
+                                Notification::warning(format!(
+                                    "Invalid tuning from the other player: {}",
+                                    e
+                                )),
+                                Instant::now(),
+                            ),
+                        }
+                    }
+                    side_channel::SideMessage::Checksum { frame, checksum } => {
+                        if let Some(frame) = desync_detector.add_remote(frame, checksum) {
+                            report_desync(
+                                &desync_detector,
+                                local_handle,
+                                frame,
+                                replay_path.as_deref(),
+                                replay_writer.as_mut(),
+                                &mut toasts,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // chat text entry, the game gets no keyboard input while it is open
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key {
+                Key::Return => {
+                    if let Some(text) = chat.toggle() {
+                        let message =
+                            format!("{}: {}", player_names[local_handle].as_ref().unwrap(), text);
+                        let message_to_send = side_channel::SideMessage::Chat(message.clone());
+                        side_channel.send(&message_to_send);
+                        side_channel.send_to_listeners(&message_to_send);
+                        chat.push_history(message);
+                    }
+                    presentation.release_all_keys();
+                }
+                Key::Backspace => chat.backspace(),
+                _ => (),
+            }
+        }
+        if let Some(text) = e.text_args() {
+            chat.type_text(&text);
+        }
+        if chat.open {
+            continue;
+        }
+
+        // netcode tuning hotkeys
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key {
+                Key::F1 => presentation.turbo = !presentation.turbo,
+                Key::F2 | Key::F3 => {
+                    frame_delay = match key {
+                        Key::F2 => frame_delay.saturating_sub(1),
+                        _ => frame_delay + 1,
+                    };
+                    sim.send(sim_thread::Command::SetFrameDelay(frame_delay));
+                }
+                Key::F4 => {
+                    fake_lag = !fake_lag;
+                    sim.send(sim_thread::Command::FakeLag(fake_lag));
+                }
+                Key::F5 => sim.send(sim_thread::Command::StressSaveLoad),
+                Key::F6 => {
+                    frame_step = !frame_step;
+                    sim.send(sim_thread::Command::FrameStep(frame_step));
+                }
+                Key::F7 => sim.send(sim_thread::Command::Step),
+                Key::F8 => inspector.toggle(),
+                Key::F9 => {
+                    frozen = match frozen {
+                        Some(_) => None,
+                        None => {
+                            let mut frozen_view =
+                                box_game::Simulation::new(NUM_PLAYERS, local_players);
+                            frozen_view.load_state_copy(&view.copy_state()?)?;
+                            Some(frozen_view)
+                        }
+                    }
+                }
+                Key::F10 => {
+                    if let Some(link_conditions) = &link_conditions {
+                        packet_loss = !packet_loss;
+                        let _ = link_conditions.send(lossy_link::LinkConditions {
+                            loss: if packet_loss { PACKET_LOSS } else { 0.0 },
+                            ..Default::default()
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        // the inspector takes over the arrow keys while it is open
+        if let Some(Button::Keyboard(key)) = e.press_args().filter(|_| inspector.open) {
+            let tree = frozen.as_ref().unwrap_or(&view).state_tree();
+            match key {
+                Key::Up => inspector.up(),
+                Key::Down => inspector.down(),
+                Key::Right => inspector.expand(&tree),
+                Key::Left => inspector.collapse(&tree),
+                _ => (),
+            }
+        }
+
+        // update key state
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            if let Some((slot, button)) = key_binding(key, two_local && !inspector.open) {
+                presentation.key_states[slot][button].press();
+                latency_probe.key_pressed();
+            }
+        }
+
+        // update key state
+        if let Some(Button::Keyboard(key)) = e.release_args() {
+            if let Some((slot, button)) = key_binding(key, two_local) {
+                presentation.key_states[slot][button].release();
+            }
+        }
+
+        // update analog stick state (axis 0 and 1 are the left stick)
+        if let Some(args) = e.controller_axis_args() {
+            match args.axis {
+                0 => presentation.stick_axes[0] = args.position,
+                1 => presentation.stick_axes[1] = args.position,
+                _ => (),
+            }
+        }
+    }
+
+    // the window was closed or Ctrl-C pressed: tell the peer, so it does not wait for us to time out. After
+    // --frames, the peer is about to stop as well and still needs our inputs to confirm its last frames.
+    if !reached_stop_frame {
+        side_channel.send(&side_channel::SideMessage::Leaving {
+            handle: local_handle,
+        });
+    }
+    if let Some(writer) = replay_writer.as_mut() {
+        writer.flush()?;
+    }
+    for report in latency_probe.reports() {
+        tracing::info!("{}", report);
+    }
+    #[cfg(feature = "discord")]
+    {
+        if let Some(discord) = discord {
+            discord.close();
+        }
+    }
+    // after --frames, keep answering for a moment, so the other peer receives our last inputs and can confirm its
+    // frames too. This also writes the final metrics.
+    sim.finish(stop_frame.is_some())?;
+    if print_checksum {
+        match last_confirmed {
+            Some((_, checksum)) => println!("{}", checksum),
+            None => anyhow::bail!("no frame was confirmed"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Pumps the relay of --lossy-link on its own thread, until the returned sender is dropped.
+fn spawn_lossy_link(mut link: lossy_link::LossyLink) -> mpsc::Sender<lossy_link::LinkConditions> {
+    let (sender, conditions) = mpsc::channel();
+    thread::spawn(move || loop {
+        match conditions.try_recv() {
+            Ok(new_conditions) => link.set_conditions(new_conditions),
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => break,
+        }
+        link.pump();
+        thread::sleep(lossy_link::TICK);
+    });
+    sender
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
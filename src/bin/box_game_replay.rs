@@ -0,0 +1,621 @@
+use boxgame_net::session::SessionBuilder;
+use boxgame_net::side_channel::{self, SideChannel, SideMessage};
+use ggrs::{GGRSError, SessionState};
+use ggrs_test_game::{
+    box_game::{self, GameError},
+    cli,
+    heatmap::Heatmap,
+    render, replay, text, timestep, trace, window,
+};
+use opengl_graphics::{gl as opengl, GlGraphics};
+use piston::event_loop::{EventSettings, Events};
+use piston::input::{RenderEvent, UpdateEvent};
+use piston::{Button, EventLoop, Key, PressEvent};
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const WINDOW_HEIGHT: u32 = 800;
+const WINDOW_WIDTH: u32 = 600;
+
+const SPEEDS: [f64; 6] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0];
+const THUMBNAIL_SCALE: f64 = 0.15;
+const BROWSER_ROW_HEIGHT: f64 = WINDOW_HEIGHT as f64 * THUMBNAIL_SCALE + 10.0;
+const BROWSER_TEXT_STYLE: text::Style = text::Style::new(16);
+/// Tuning changes are announced to the spectator of a broadcast this many frames ahead, as players announce them.
+const TUNING_LEAD: ggrs::Frame = 120;
+
+/// Re-simulates the replay without a window and compares every checksum with the recorded one.
+/// Returns the first frame that diverged, if any.
+fn verify(replay: &replay::Replay) -> Result<Option<ggrs::Frame>, GameError> {
+    let header = &replay.header;
+    let mut game = box_game::Simulation::new(header.num_players, header.local_players);
+    restart(&mut game, replay)?;
+    for record in &replay.frames {
+        game.simulate_frame(record.inputs.clone())?;
+        if game.last_checksum() != (record.frame, record.checksum) {
+            return Ok(Some(record.frame));
+        }
+    }
+    Ok(None)
+}
+
+/// Re-simulates the whole replay into a heatmap of where the players went and what they held.
+fn replay_heatmap(replay: &replay::Replay) -> Result<Heatmap, GameError> {
+    let header = &replay.header;
+    let mut game = box_game::Simulation::new(header.num_players, header.local_players);
+    restart(&mut game, replay)?;
+    let mut heatmap = Heatmap::new(header.num_players);
+    for record in &replay.frames {
+        game.simulate_frame(record.inputs.clone())?;
+        heatmap.record(&game, &record.inputs);
+    }
+    Ok(heatmap)
+}
+
+/// Resets the game to the state the replay starts from, with the tuning changes recorded in it.
+fn restart(game: &mut box_game::Simulation, replay: &replay::Replay) -> Result<(), GameError> {
+    game.restart();
+    game.set_tuning(replay.tuning());
+    if let Some(keyframe) = &replay.header.start_keyframe {
+        game.load_keyframe(keyframe)?;
+    }
+    Ok(())
+}
+
+/// Brings the game to the state after the given replay frame, starting from the closest keyframe before it.
+/// Returns the index of the next record to simulate.
+fn seek(
+    game: &mut box_game::Simulation,
+    replay: &replay::Replay,
+    target: usize,
+) -> Result<usize, GameError> {
+    let target = target.min(replay.frames.len());
+    // record i holds the inputs leading to state frame i + 1
+    let keyframe = replay.frames[..target]
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, record)| record.keyframe.as_ref().map(|keyframe| (i + 1, keyframe)));
+    let mut next_frame = match keyframe {
+        Some((next_frame, keyframe)) => {
+            game.load_keyframe(keyframe)?;
+            // the changes before the keyframe may have been pruned while playing past them
+            game.set_tuning(replay.tuning());
+            next_frame
+        }
+        None => {
+            restart(game, replay)?;
+            0
+        }
+    };
+    while next_frame < target {
+        game.simulate_frame(replay.frames[next_frame].inputs.clone())?;
+        next_frame += 1;
+    }
+    Ok(next_frame)
+}
+
+/// Reads back what was just drawn to the window and saves it as a PNG.
+fn save_frame(path: &Path, width: u32, height: u32) -> anyhow::Result<()> {
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    unsafe {
+        opengl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            opengl::RGBA,
+            opengl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+    }
+    let image = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow::anyhow!("invalid frame size"))?;
+    // OpenGL rows start at the bottom
+    image::imageops::flip_vertical(&image).save(path)?;
+    Ok(())
+}
+
+/// Plays the replay through two loopback P2P sessions, one per recorded peer, so a spectator connected to the first
+/// one receives the match like a live one. Only matches between two peers can be broadcast.
+///
+/// GGRS 0.2 sends spectators blank inputs for disconnected peers, which the game takes for an idle player, while the
+/// replay simulates disconnected peers as such. The broadcast therefore ends before the first frame with a
+/// disconnected peer.
+fn broadcast(replay: &replay::Replay, port: u16, spectator_addr: SocketAddr) -> anyhow::Result<()> {
+    let header = &replay.header;
+    let ghost_port = port + 2;
+    let host_addr: SocketAddr = format!("127.0.0.1:{}", port).parse()?;
+    let ghost_addr: SocketAddr = format!("127.0.0.1:{}", ghost_port).parse()?;
+
+    let mut host = SessionBuilder::new(header.num_players, header.input_size)
+        .local_player(0)
+        .remote_player(1, ghost_addr)
+        .spectator(spectator_addr)
+        .start_p2p(port)?;
+
+    let mut ghost = SessionBuilder::new(header.num_players, header.input_size)
+        .remote_player(0, host_addr)
+        .local_player(1)
+        .start_p2p(ghost_port)?;
+
+    // each session needs its own game to answer save and load requests during rollbacks
+    let mut games = [
+        box_game::Simulation::new(header.num_players, header.local_players),
+        box_game::Simulation::new(header.num_players, header.local_players),
+    ];
+    for game in games.iter_mut() {
+        restart(game, replay)?;
+    }
+    // the spectator learns about tuning changes from the host's side channel, as it would from a live host
+    let mut side_channel = SideChannel::bind(side_channel::default_port(port))?;
+    side_channel.add_listener(SocketAddr::new(
+        spectator_addr.ip(),
+        side_channel::default_port(spectator_addr.port()),
+    ));
+    let mut tuning_changes = Vec::new();
+    for record in &replay.frames {
+        if let Some(tuning) = record.tuning {
+            tuning_changes.push((record.frame, box_game::encoding::serialize(&tuning)?));
+        }
+    }
+    let frames = replay
+        .frames
+        .iter()
+        .take_while(|record| record.inputs.iter().all(Option::is_some))
+        .count();
+    if frames < replay.frames.len() {
+        tracing::warn!(
+            "A peer disconnected in frame {}, the spectator cannot be shown that, so the broadcast ends there",
+            replay.frames[frames].frame
+        );
+    }
+    let frames = &replay.frames[..frames];
+    let mut next_frames = [0; 2];
+    let frame_time = Duration::from_secs_f64(1.0 / header.fps as f64);
+    let mut timestep = timestep::FixedTimestep::new(header.fps, Instant::now());
+    tracing::info!("Waiting for the spectator at {}...", spectator_addr);
+
+    while next_frames
+        .iter()
+        .any(|&next_frame| next_frame < frames.len())
+    {
+        for _ in 0..timestep.advance(Instant::now()) {
+            host.poll_remote_clients();
+            ghost.poll_remote_clients();
+
+            if host.current_state() == SessionState::Running
+                && ghost.current_state() == SessionState::Running
+            {
+                // announced ahead of their frame until the host simulated it, like the players announce them
+                if let Some(host_frame) = frames.get(next_frames[0]).map(|record| record.frame) {
+                    for (frame, tuning) in tuning_changes
+                        .iter()
+                        .filter(|(frame, _)| (host_frame..host_frame + TUNING_LEAD).contains(frame))
+                    {
+                        side_channel.send_to_listeners(&SideMessage::Tuning {
+                            frame: *frame,
+                            tuning: tuning.clone(),
+                        });
+                    }
+                }
+                for handle in 0..2 {
+                    let input = match frames.get(next_frames[handle]) {
+                        Some(record) => record.inputs[handle].as_deref().unwrap_or_default(),
+                        None => continue,
+                    };
+                    let session = if handle == 0 { &mut host } else { &mut ghost };
+                    match session.advance_frame(handle, input) {
+                        Ok(requests) => {
+                            games[handle].handle_requests(requests)?;
+                            next_frames[handle] += 1;
+                        }
+                        Err(GGRSError::PredictionThreshold) => (),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+
+        std::thread::sleep(timestep.until_next_tick(Instant::now()));
+    }
+
+    // keep the sessions alive for a moment, so the last inputs reach the spectator
+    let linger_start = Instant::now();
+    while linger_start.elapsed() < Duration::from_secs(2) {
+        host.poll_remote_clients();
+        ghost.poll_remote_clients();
+        std::thread::sleep(frame_time);
+    }
+    println!("Broadcast {} frames.", frames.len());
+    Ok(())
+}
+
+/// The window and everything needed to draw into it. Headless frontends have no renderer.
+struct Frontend {
+    window: window::GameWindow,
+    gl: Option<GlGraphics>,
+    text: text::Text,
+}
+
+fn open_window(headless: bool) -> anyhow::Result<Frontend> {
+    let (window, gl) = window::open("Box Game Replay", [WINDOW_WIDTH, WINDOW_HEIGHT], headless)?;
+
+    // load a font to render text
+    let text = text::Text::from_assets()?;
+
+    Ok(Frontend { window, gl, text })
+}
+
+/// A replay listed in the browser, with a game showing its thumbnail.
+struct BrowserEntry {
+    path: PathBuf,
+    description: String,
+    thumbnail: box_game::Simulation,
+}
+
+/// Loads all replays in `dir`, skipping files that are not replays this build can play back.
+fn load_browser_entries(dir: &Path) -> anyhow::Result<Vec<BrowserEntry>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let replay = match replay::Replay::load(&path) {
+            Ok(replay) => replay,
+            Err(_) => continue,
+        };
+        let header = &replay.header;
+        let mut thumbnail = box_game::Simulation::new(header.num_players, header.local_players);
+        if header.check_compatible(thumbnail.map_hash()).is_err() {
+            continue;
+        }
+        // the thumbnail shows the keyframe closest to the middle of the match
+        restart(&mut thumbnail, &replay)?;
+        let middle = replay.frames.len() / 2;
+        if let Some(keyframe) = replay.frames[..middle]
+            .iter()
+            .rev()
+            .find_map(|record| record.keyframe.as_ref())
+        {
+            thumbnail.load_keyframe(keyframe)?;
+        }
+        let description = format!(
+            "{} - {} - {}, {}s",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            header.player_names.join(" vs "),
+            header.formatted_date(),
+            replay.frames.len() as u64 / header.fps
+        );
+        entries.push(BrowserEntry {
+            path,
+            description,
+            thumbnail,
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Shows the replays in `dir` and lets the user pick one with the arrow keys and Enter.
+/// Returns `None` if the window was closed instead.
+fn browse(dir: &Path, frontend: &mut Frontend) -> anyhow::Result<Option<PathBuf>> {
+    use graphics::*;
+
+    let gl = frontend
+        .gl
+        .as_mut()
+        .ok_or_else(|| anyhow::anyhow!("the replay browser needs a window"))?;
+    let entries = load_browser_entries(dir)?;
+    if entries.is_empty() {
+        anyhow::bail!("no playable replays in {}", dir.display());
+    }
+    for entry in &entries {
+        frontend
+            .text
+            .prepare(&entry.description, BROWSER_TEXT_STYLE.size)?;
+    }
+    let text = &frontend.text;
+    let rows_per_page = (WINDOW_HEIGHT as f64 / BROWSER_ROW_HEIGHT) as usize;
+
+    let mut selected = 0;
+    let mut events = Events::new(EventSettings::new());
+    while let Some(e) = events.next(&mut frontend.window) {
+        if let Some(args) = e.render_args() {
+            let first_row = selected / rows_per_page * rows_per_page;
+            gl.draw(args.viewport(), |c, gl| {
+                clear([0.0, 0.0, 0.0, 1.0], gl);
+                let rows = entries.iter().enumerate();
+                for (i, entry) in rows.skip(first_row).take(rows_per_page) {
+                    let y = 5.0 + (i - first_row) as f64 * BROWSER_ROW_HEIGHT;
+                    let arena = [
+                        0.0,
+                        0.0,
+                        WINDOW_WIDTH as f64 * THUMBNAIL_SCALE,
+                        WINDOW_HEIGHT as f64 * THUMBNAIL_SCALE,
+                    ];
+                    let border = if i == selected {
+                        [1.0, 1.0, 1.0, 1.0]
+                    } else {
+                        [0.4, 0.4, 0.4, 1.0]
+                    };
+                    let thumbnail = c.transform.trans(5.0, y);
+                    Rectangle::new_border(border, 1.0).draw(arena, &c.draw_state, thumbnail, gl);
+                    render::draw_boxes(
+                        &entry.thumbnail,
+                        &c.draw_state,
+                        thumbnail.scale(THUMBNAIL_SCALE, THUMBNAIL_SCALE),
+                        gl,
+                    );
+                    let position = [arena[2] + 10.0, y + 20.0];
+                    text.draw(&entry.description, BROWSER_TEXT_STYLE, position, &c, gl);
+                }
+            });
+        }
+
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key {
+                Key::Down => selected = (selected + 1).min(entries.len() - 1),
+                Key::Up => selected = selected.saturating_sub(1),
+                Key::Return => return Ok(Some(entries[selected].path.clone())),
+                _ => (),
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --trace <file>, spans of the simulation are written to a chrome tracing file for profiling
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    // with --verify, the replay is only re-simulated and checked against the recorded checksums
+    let verify_only = cli::take_flag(&mut args, "--verify");
+    // with --export <file>, the inputs and the resulting box states are written to a CSV or JSON file instead of
+    // playing the replay
+    let export_path = cli::take_flag_value(&mut args, "--export");
+    // with --export-frames <dir>, every frame is rendered to a numbered PNG in that directory as fast as possible.
+    // Turn them into a video with e.g. `ffmpeg -framerate 60 -i <dir>/frame_%06d.png match.mp4`
+    let frames_dir = cli::take_flag_value(&mut args, "--export-frames");
+    // with --diff <other replay>, the replay is compared to another recording of the same match
+    let diff_path = cli::take_flag_value(&mut args, "--diff");
+    // with --broadcast <spectator address>, the replay is sent to a spectator client like a live match,
+    // from --port <port> (default 7000) and the port two above it
+    let broadcast_addr = cli::take_flag_value(&mut args, "--broadcast");
+    let port: u16 = match cli::take_flag_value(&mut args, "--port") {
+        Some(port) => port.parse()?,
+        None => 7000,
+    };
+    // with --headless, the replay plays in real time without a window and the final checksum is printed
+    let headless = cli::take_flag(&mut args, "--headless");
+    // with --frames <n>, playback ends after n frames instead of at the end of the replay
+    let stop_frames: Option<usize> = match cli::take_flag_value(&mut args, "--frames") {
+        Some(frames) => Some(frames.parse()?),
+        None => None,
+    };
+    // with --print-checksum, only the final checksum is printed when playback ends, for scripts to compare
+    let print_checksum = cli::take_flag(&mut args, "--print-checksum");
+    if args.len() != 2 {
+        anyhow::bail!(
+            "usage: box_game_replay [--verify | --export <file> | --export-frames <dir> | --diff <replay> | --broadcast <addr>] [--headless] [--frames <n>] [--print-checksum] <replay or directory>"
+        );
+    }
+
+    // passing a directory opens the replay browser
+    let mut frontend = None;
+    let replay_path = if Path::new(&args[1]).is_dir() {
+        let mut browser_frontend = open_window(headless)?;
+        match browse(Path::new(&args[1]), &mut browser_frontend)? {
+            Some(path) => {
+                frontend = Some(browser_frontend);
+                path
+            }
+            None => return Ok(()),
+        }
+    } else {
+        PathBuf::from(&args[1])
+    };
+
+    let replay = replay::Replay::load(&replay_path)?;
+    let header = &replay.header;
+    let map_hash = box_game::Simulation::new(header.num_players, header.local_players).map_hash();
+    if let Err(reason) = header.check_compatible(map_hash) {
+        anyhow::bail!("cannot play back this replay: {}", reason);
+    }
+
+    if let Some(addr) = broadcast_addr {
+        if header.num_players != 2 {
+            anyhow::bail!("only replays of two peers can be broadcast");
+        }
+        return broadcast(&replay, port, addr.parse()?);
+    }
+
+    if let Some(path) = diff_path {
+        let other = replay::Replay::load(Path::new(&path))?;
+        match replay::first_difference(&replay, &other) {
+            None => println!("The replays agree on all frames they have in common."),
+            Some(replay::Difference::Input { frame, peer }) => {
+                println!(
+                    "Input divergence: the input of peer {} differs at frame {}.",
+                    peer, frame
+                );
+                std::process::exit(1);
+            }
+            Some(replay::Difference::Simulation { frame }) => {
+                println!(
+                    "Simulation divergence: same inputs but different checksums at frame {}.",
+                    frame
+                );
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = export_path {
+        replay::export_inputs(&replay, Path::new(&path))?;
+        println!("Exported {} frames to {}", replay.frames.len(), path);
+        return Ok(());
+    }
+
+    if verify_only {
+        match verify(&replay)? {
+            None => {
+                println!(
+                    "All {} frames match the recorded checksums.",
+                    replay.frames.len()
+                );
+                return Ok(());
+            }
+            Some(frame) => {
+                println!(
+                    "Desync: frame {} does not match the recorded checksum.",
+                    frame
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let Frontend {
+        mut window,
+        mut gl,
+        mut text,
+    } = match frontend {
+        Some(frontend) => frontend,
+        None => open_window(headless)?,
+    };
+    if headless && frames_dir.is_some() {
+        anyhow::bail!("exporting frames needs a window");
+    }
+
+    // Create a new box game, no session needed since all inputs come from the replay
+    let mut game = box_game::Simulation::new(header.num_players, header.local_players);
+    let mut presentation = box_game::Presentation::new(header.local_players);
+    restart(&mut game, &replay)?;
+
+    // event settings
+    let seek_frames = 5 * header.fps as usize;
+    let mut event_settings = EventSettings::new();
+    event_settings.set_ups(header.fps);
+    event_settings.set_ups_reset(timestep::MAX_TICKS_PER_FRAME as u64);
+    event_settings.set_max_fps(header.fps);
+    if let Some(dir) = &frames_dir {
+        std::fs::create_dir_all(dir)?;
+        // no need to wait for real time, every render event produces the next frame
+        event_settings.bench_mode = true;
+    }
+    let mut events = Events::new(event_settings);
+
+    let mut next_frame = 0;
+    let mut paused = false;
+    let mut speed_index = 3;
+    // fractional frames owed at slow or fast speeds
+    let mut pending_frames = 0.0;
+
+    // event loop
+    while let Some(e) = events.next(&mut window) {
+        // render
+        if let (Some(args), Some(gl)) = (e.render_args(), gl.as_mut()) {
+            presentation.debug_lines = vec![
+                header.player_names.join(" vs "),
+                format!("Replay frame {} / {}", next_frame, replay.frames.len()),
+                format!("Speed: x{} (Up/Down)", SPEEDS[speed_index]),
+                format!("Paused: {} (Space, Right to step)", paused),
+                "Seek: Left/Right 5 seconds, Home to restart".to_string(),
+                "Heatmap of the whole match: H".to_string(),
+            ];
+            if let Some(heatmap) = &presentation.heatmap {
+                for (handle, name) in header.player_names.iter().enumerate() {
+                    let held = heatmap.held_line(handle, header.local_players);
+                    presentation
+                        .debug_lines
+                        .push(format!("{} held: {}", name, held));
+                }
+            }
+            if let Some(dir) = &frames_dir {
+                presentation.debug_lines = vec![header.player_names.join(" vs ")];
+                presentation.render(gl, &mut text, &args, &game)?;
+                let path = Path::new(dir).join(format!("frame_{:06}.png", next_frame));
+                save_frame(&path, args.draw_size[0], args.draw_size[1])?;
+                match replay.frames.get(next_frame) {
+                    Some(record) => {
+                        game.simulate_frame(record.inputs.clone())?;
+                        next_frame += 1;
+                    }
+                    None => {
+                        println!("Exported {} frames to {}", next_frame + 1, dir);
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+            presentation.render(gl, &mut text, &args, &game)?;
+        }
+
+        // game update
+        let mut frames_to_play = 0;
+        if e.update_args().is_some() {
+            if !paused {
+                pending_frames += SPEEDS[speed_index];
+                frames_to_play = pending_frames as usize;
+                pending_frames -= frames_to_play as f64;
+            }
+        }
+
+        // playback controls
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key {
+                Key::Space => paused = !paused,
+                Key::Right if paused => frames_to_play = 1,
+                Key::Right => next_frame = seek(&mut game, &replay, next_frame + seek_frames)?,
+                Key::Left => {
+                    next_frame = seek(&mut game, &replay, next_frame.saturating_sub(seek_frames))?
+                }
+                Key::Home => next_frame = seek(&mut game, &replay, 0)?,
+                Key::Up => speed_index = (speed_index + 1).min(SPEEDS.len() - 1),
+                Key::Down => speed_index = speed_index.saturating_sub(1),
+                Key::H => {
+                    presentation.heatmap = match presentation.heatmap.take() {
+                        Some(_) => None,
+                        None => Some(replay_heatmap(&replay)?),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for _ in 0..frames_to_play {
+            if stop_frames.is_some_and(|frames| next_frame >= frames) {
+                break;
+            }
+            if let Some(record) = replay.frames.get(next_frame) {
+                game.simulate_frame(record.inputs.clone())?;
+                next_frame += 1;
+            }
+        }
+        let end = stop_frames.map_or(replay.frames.len(), |frames| {
+            frames.min(replay.frames.len())
+        });
+        if (headless || stop_frames.is_some()) && next_frame >= end {
+            let (frame, checksum) = game.last_checksum();
+            if print_checksum {
+                println!("{}", checksum);
+            } else {
+                println!(
+                    "Played {} frames, checksum {} at frame {}.",
+                    next_frame, checksum, frame
+                );
+            }
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -0,0 +1,339 @@
+use boxgame_net::session::SessionBuilder;
+use boxgame_net::{lossy_link, metrics};
+use ggrs::{Frame, GGRSError, GGRSEvent, GGRSRequest, PlayerHandle, SessionState};
+use ggrs_test_game::{box_game, cli, timestep, trace};
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FPS: u64 = 60;
+const NUM_PLAYERS: usize = 2;
+const CHECKSUM_PERIOD: i32 = 100;
+const STATS_PERIOD: Duration = Duration::from_secs(60);
+
+/// What a peer reports to the main thread.
+enum Report {
+    Checksum {
+        handle: PlayerHandle,
+        frame: Frame,
+        checksum: u64,
+    },
+    Stats {
+        handle: PlayerHandle,
+        stats: PeerStats,
+    },
+}
+
+/// Rollback statistics of one peer since its last report.
+#[derive(Default)]
+struct PeerStats {
+    frames: u64,
+    rollbacks: u64,
+    resimulated_frames: u64,
+    longest_rollback: usize,
+    skipped_frames: u64,
+    interruptions: u64,
+    resumptions: u64,
+    /// size in bytes of the largest saved state
+    largest_save: usize,
+    /// bytes held in saved states at the time of the report
+    held_state_bytes: usize,
+    /// the longest time spent handling the requests of a single frame
+    longest_burst: Duration,
+}
+
+/// Plays as a bot in real time until `stop` is set, reporting confirmed checksums and rollback statistics.
+fn run_peer(
+    local_handle: PlayerHandle,
+    port: u16,
+    remote_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    reports: mpsc::Sender<Report>,
+    metrics_path: Option<PathBuf>,
+) -> Result<(), String> {
+    let mut sess = SessionBuilder::new(NUM_PLAYERS, box_game::INPUT_SIZE)
+        .local_player(local_handle)
+        .remote_player(1 - local_handle, remote_addr)
+        .frame_delay(2)
+        .start_p2p(port)
+        .map_err(|e| e.to_string())?;
+
+    let mut game = box_game::Simulation::new(NUM_PLAYERS, 1);
+    let mut controls = box_game::Presentation::new(1);
+    controls.turbo = true;
+    game.enable_frame_log();
+    let mut stats = PeerStats::default();
+    let mut metrics = metrics::Metrics::default();
+    let mut last_stats = Instant::now();
+    let mut frames_to_skip = 0;
+    let mut timestep = timestep::FixedTimestep::new(FPS, Instant::now());
+
+    while !stop.load(Ordering::Relaxed) {
+        for _ in 0..timestep.advance(Instant::now()) {
+            sess.poll_remote_clients();
+
+            if frames_to_skip > 0 {
+                frames_to_skip -= 1;
+                stats.skipped_frames += 1;
+            } else if sess.current_state() == SessionState::Running {
+                match sess.advance_frame(local_handle, &controls.local_input(game.current_frame()))
+                {
+                    Ok(requests) => {
+                        if requests
+                            .iter()
+                            .any(|request| matches!(request, GGRSRequest::LoadGameState { .. }))
+                        {
+                            // all but the newest frame are simulated again
+                            let resimulated = requests
+                                .iter()
+                                .filter(|request| {
+                                    matches!(request, GGRSRequest::AdvanceFrame { .. })
+                                })
+                                .count()
+                                - 1;
+                            stats.rollbacks += 1;
+                            stats.resimulated_frames += resimulated as u64;
+                            stats.longest_rollback = stats.longest_rollback.max(resimulated);
+                        }
+                        metrics.record_requests(&requests);
+                        let burst = metrics::Burst::of(&requests);
+                        let simulation_start = Instant::now();
+                        game.handle_requests(requests).map_err(|e| e.to_string())?;
+                        let simulation_time = simulation_start.elapsed();
+                        metrics
+                            .record("simulation_time_ms", simulation_time.as_secs_f64() * 1000.0);
+                        metrics.record_burst(burst, simulation_time, Instant::now());
+                        stats.longest_burst = stats.longest_burst.max(simulation_time);
+                        metrics.record("save_state_bytes", game.last_save_size() as f64);
+                        metrics.record("held_state_bytes", game.held_state_bytes() as f64);
+                        stats.largest_save = stats.largest_save.max(game.last_save_size());
+                        stats.frames += 1;
+                        for record in game.take_confirmed_frames() {
+                            if record.frame % CHECKSUM_PERIOD == 0 {
+                                let _ = reports.send(Report::Checksum {
+                                    handle: local_handle,
+                                    frame: record.frame,
+                                    checksum: record.checksum,
+                                });
+                            }
+                        }
+                    }
+                    Err(GGRSError::PredictionThreshold) => {
+                        stats.skipped_frames += 1;
+                        metrics.increment("prediction_threshold_stalls", 1);
+                    }
+                    Err(e) => return Err(e.to_string()),
+                }
+                for event in sess.events() {
+                    match event {
+                        GGRSEvent::WaitRecommendation { skip_frames } => {
+                            frames_to_skip += skip_frames
+                        }
+                        GGRSEvent::NetworkInterrupted { .. } => {
+                            stats.interruptions += 1;
+                            metrics.increment("network_interruptions", 1);
+                        }
+                        GGRSEvent::NetworkResumed { .. } => {
+                            stats.resumptions += 1;
+                            metrics.increment("network_resumptions", 1);
+                        }
+                        // this GGRS version cannot reconnect, a disconnect ends the match for good
+                        GGRSEvent::Disconnected { .. } => {
+                            return Err(format!("peer {} lost its connection", local_handle))
+                        }
+                        _ => (),
+                    }
+                }
+                if let Ok(network) = sess.network_stats(1 - local_handle) {
+                    metrics.record("ping_ms", network.ping as f64);
+                    metrics.record("kbps_sent", network.kbps_sent as f64);
+                    metrics.record("send_queue_len", network.send_queue_len as f64);
+                }
+            }
+        }
+
+        if last_stats.elapsed() >= STATS_PERIOD {
+            stats.held_state_bytes = game.held_state_bytes();
+            let stats = std::mem::take(&mut stats);
+            let _ = reports.send(Report::Stats {
+                handle: local_handle,
+                stats,
+            });
+            last_stats = Instant::now();
+            if let Some(path) = &metrics_path {
+                metrics.write_json(path).map_err(|e| e.to_string())?;
+            }
+        }
+        thread::sleep(timestep.until_next_tick(Instant::now()));
+    }
+    if let Some(path) = &metrics_path {
+        metrics.write_json(path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Plays two bots against each other over a lossy loopback link for a long time, logging rollback statistics every
+/// minute and exiting with status 1 as soon as the peers disagree on a checksum. Meant for overnight stability runs.
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    let mut flag = |name: &str, default: u64| -> anyhow::Result<u64> {
+        match cli::take_flag_value(&mut args, name) {
+            Some(value) => Ok(value.parse()?),
+            None => Ok(default),
+        }
+    };
+    // --minutes <n> to run (default 8 hours), --lag <ms> and --jitter <ms> added to every packet,
+    // --loss <percent> of packets dropped, --port <port> to use it and the three ports above it (default 7200)
+    let minutes = flag("--minutes", 8 * 60)?;
+    let lag = Duration::from_millis(flag("--lag", 50)?);
+    let jitter = Duration::from_millis(flag("--jitter", 20)?);
+    let loss = flag("--loss", 5)? as f64 / 100.0;
+    let port = flag("--port", 7200)? as u16;
+    // with --chaos <ms>, the link goes down completely every 30 seconds on average, for up to that long. Outages
+    // shorter than the GGRS disconnect timeout have to be survived without a desync.
+    let max_outage = match flag("--chaos", 0)? {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    // with --metrics <prefix>, each peer writes its metrics to <prefix>-peer<handle>.json every minute and at exit
+    let metrics_prefix = cli::take_flag_value(&mut args, "--metrics");
+    // with --trace <file>, a chrome tracing file of all spans is written
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    if args.len() != 1 {
+        anyhow::bail!(
+            "unexpected argument {}, usage: box_game_soak [--minutes <n>] [--lag <ms>] [--jitter <ms>] [--loss <percent>] [--port <port>] [--chaos <ms>] [--metrics <prefix>] [--trace <file>]",
+            args[1]
+        );
+    }
+
+    let peer_ports = [port, port + 1];
+    let peer_addrs: [SocketAddr; NUM_PLAYERS] = [
+        format!("127.0.0.1:{}", peer_ports[0]).parse()?,
+        format!("127.0.0.1:{}", peer_ports[1]).parse()?,
+    ];
+    let conditions = lossy_link::LinkConditions {
+        lag,
+        jitter,
+        loss,
+        max_outage,
+    };
+    let mut link =
+        lossy_link::LossyLink::bind([port + 2, port + 3], peer_addrs, conditions, port as u64)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (report_sender, reports) = mpsc::channel();
+    let mut peers = Vec::new();
+    for (handle, &peer_port) in peer_ports.iter().enumerate() {
+        let remote_addr = link.addr_for(handle)?;
+        let stop = stop.clone();
+        let report_sender = report_sender.clone();
+        let metrics_path = metrics_prefix
+            .as_ref()
+            .map(|prefix| PathBuf::from(format!("{}-peer{}.json", prefix, handle)));
+        peers.push(thread::spawn(move || {
+            run_peer(
+                handle,
+                peer_port,
+                remote_addr,
+                stop,
+                report_sender,
+                metrics_path,
+            )
+        }));
+    }
+    // Ctrl-C ends the soak early, with the statistics so far
+    cli::handle_interrupts();
+    tracing::info!(
+        "Soaking for {} minutes with {:?} lag, {:?} jitter and {}% loss.",
+        minutes,
+        lag,
+        jitter,
+        loss * 100.0
+    );
+
+    // checksums only one peer has confirmed so far
+    let mut pending: HashMap<Frame, (PlayerHandle, u64)> = HashMap::new();
+    let mut compared = 0;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(minutes * 60) && !cli::interrupted() {
+        if let Some(outage) = link.pump() {
+            tracing::info!(
+                target: "soak::chaos",
+                "[{:>5}s] the link is down for {:?}",
+                start.elapsed().as_secs(),
+                outage
+            );
+        }
+        for report in reports.try_iter() {
+            match report {
+                Report::Checksum {
+                    handle,
+                    frame,
+                    checksum,
+                } => match pending.remove(&frame) {
+                    Some((other, other_checksum)) if other != handle => {
+                        if checksum != other_checksum {
+                            anyhow::bail!(
+                                "desync at frame {}: checksum {} vs {}",
+                                frame,
+                                checksum,
+                                other_checksum
+                            );
+                        }
+                        compared += 1;
+                    }
+                    _ => {
+                        pending.insert(frame, (handle, checksum));
+                    }
+                },
+                Report::Stats { handle, stats } => tracing::info!(
+                    target: "soak::stats",
+                    "[{:>5}s] peer {}: {} frames, {} rollbacks, {} resimulated frames (longest {}), {} skipped, \
+                     {} interruptions, {} resumptions, largest state {} B, {} B held, longest burst {:?}, \
+                     {} checksums agree",
+                    start.elapsed().as_secs(),
+                    handle,
+                    stats.frames,
+                    stats.rollbacks,
+                    stats.resimulated_frames,
+                    stats.longest_rollback,
+                    stats.skipped_frames,
+                    stats.interruptions,
+                    stats.resumptions,
+                    stats.largest_save,
+                    stats.held_state_bytes,
+                    stats.longest_burst,
+                    compared
+                ),
+            }
+        }
+        if peers.iter().any(|peer| peer.is_finished()) {
+            break;
+        }
+        thread::sleep(lossy_link::TICK);
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    for peer in peers {
+        peer.join()
+            .expect("peer thread panicked")
+            .map_err(anyhow::Error::msg)?;
+    }
+    println!(
+        "Soak finished after {}s, {} checksums agree.",
+        start.elapsed().as_secs(),
+        compared
+    );
+    Ok(())
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -0,0 +1,496 @@
+use boxgame_net::feed;
+use boxgame_net::side_channel::{self, SideChannel, SideMessage};
+use ggrs::GGRSEvent;
+use ggrs_test_game::archive::{LinkEvent, Recording, SpectatedHost};
+use ggrs_test_game::camera::{Inset, Preset};
+use ggrs_test_game::game_loop::Advance;
+use ggrs_test_game::heatmap::Heatmap;
+use ggrs_test_game::notifications::{Notification, Toasts};
+use ggrs_test_game::overlay::{Link, OverlayFeed};
+use ggrs_test_game::rewind::{Playback, RewindBuffer, SPEEDS};
+use ggrs_test_game::{box_game, chat, cli, game_loop, text, trace, window};
+use piston::input::{RenderEvent, UpdateEvent};
+use piston::{Button, IdleEvent, Key, PressEvent};
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const FPS: u64 = 60;
+const NUM_PLAYERS: usize = 2;
+
+const WINDOW_HEIGHT: u32 = 800;
+const WINDOW_WIDTH: u32 = 600;
+/// How far back the local playback can go, in seconds.
+const REWIND_SECONDS: u64 = 10;
+/// How far Left rewinds, in seconds.
+const SEEK_SECONDS: u64 = 3;
+/// Further behind the host than this, the spectator shows that it is catching up.
+const CATCH_UP_FRAMES: i32 = 30;
+/// Session ticks per update while fast-forwarding through a backlog.
+const FAST_FORWARD_TICKS: usize = 4;
+/// How long the lag graph goes back, in seconds.
+const LAG_GRAPH_SECONDS: u64 = 5;
+
+fn run() -> anyhow::Result<()> {
+    // read cmd line arguments very clumsily
+    let mut args: Vec<String> = env::args().collect();
+    // with --trace <file>, spans of the session loop are written to a chrome tracing file for profiling
+    let _trace_guard = trace::init(cli::take_flag_value(&mut args, "--trace").as_deref());
+    cli::handle_interrupts();
+    // pass --two-local when the players control two boxes each
+    let two_local = cli::take_flag(&mut args, "--two-local");
+    let local_players = if two_local { 2 } else { 1 };
+    // with --record-replay <file>, the spectated match is written to a replay file
+    let replay_path = cli::take_flag_value(&mut args, "--record-replay");
+    // with --headless, no window is opened. To record matches on a server without display libraries, build
+    // box_game_archiver instead.
+    let headless = cli::take_flag(&mut args, "--headless");
+    // with --record <dir>, every match is archived into that directory as a replay and a stats file named after the
+    // time it started. Together with --headless, this makes the spectator an unattended match archiver.
+    let record_dir = cli::take_flag_value(&mut args, "--record").map(PathBuf::from);
+    if let Some(dir) = &record_dir {
+        fs::create_dir_all(dir)?;
+    }
+    // with --delay-frames <n>, the match is shown n frames behind the host, so a public stream of this view cannot
+    // be used by the players to peek at each other
+    let delay_frames: ggrs::Frame = match cli::take_flag_value(&mut args, "--delay-frames") {
+        Some(frames) => frames.parse()?,
+        None => 0,
+    };
+    // with --overlay <file>, live match data for stream overlays is written to that JSON file every frame
+    let overlay_path = cli::take_flag_value(&mut args, "--overlay");
+    // with --stats <file>, a summary of every match is written to that JSON file once the match is over, numbered
+    // from the second match on
+    let stats_path = cli::take_flag_value(&mut args, "--stats");
+    // with --player-names <a,b>, the players are called that in the overlay, the stats and the recorded replay.
+    // Otherwise they go by the names the players send, which the replay of a match only gets from the next one on.
+    let fixed_names = cli::take_flag_value(&mut args, "--player-names");
+    let names: Vec<String> = match &fixed_names {
+        Some(names) => names.split(',').map(str::to_string).collect(),
+        None => (0..NUM_PLAYERS)
+            .map(|handle| format!("P{}", handle))
+            .collect(),
+    };
+    if names.len() != NUM_PLAYERS {
+        anyhow::bail!("--player-names needs {} names", NUM_PLAYERS);
+    }
+    // with --side-port <port> and --host-side-port <port>, the side channels of the spectator and the host are not
+    // on the default ports, `side_channel::PORT_OFFSET` above the GGRS ports
+    let side_port = cli::take_flag_value(&mut args, "--side-port");
+    let host_side_port = cli::take_flag_value(&mut args, "--host-side-port");
+    if args.len() != 3 {
+        anyhow::bail!(
+            "usage: box_game_spectator [--two-local] [--headless] [--record <dir>] [--record-replay <file>] ... <port> <host address>"
+        );
+    }
+
+    let port: u16 = args[1].parse()?;
+    let host_addr: SocketAddr = args[2].parse()?;
+
+    let mut overlay = overlay_path.map(|path| OverlayFeed::new(Path::new(&path), names.clone()));
+    // create a GGRS session for a spectator. When the host disconnects, the session is dropped and started again
+    // after a delay, until the host is back. Every match is recorded into its own files.
+    let recording = Recording {
+        record_dir,
+        replay_path,
+        stats_path,
+    };
+    let mut host = SpectatedHost::connect(
+        port,
+        host_addr,
+        local_players,
+        names,
+        recording,
+        Instant::now(),
+    )?;
+    // the host forwards its chat and the player names to the spectator's side channel. The spectator never sends
+    // on it, and the host drops what it would send anyway, so it cannot write into the chat.
+    let side_port = match side_port {
+        Some(side_port) => side_port.parse()?,
+        None => side_channel::default_port(port),
+    };
+    let host_side_port = match host_side_port {
+        Some(host_side_port) => host_side_port.parse()?,
+        None => side_channel::default_port(host_addr.port()),
+    };
+    let mut side_channel = SideChannel::bind(side_port)?;
+    side_channel.add_peer(SocketAddr::new(host_addr.ip(), host_side_port));
+    let mut chat = chat::Chat::default();
+
+    let (mut window, mut gl) = window::open(
+        "Box Game Spectator",
+        [WINDOW_WIDTH, WINDOW_HEIGHT],
+        headless,
+    )?;
+
+    // load a font to render text, there is none to render without a window
+    let mut text = match gl {
+        Some(_) => Some(text::Text::from_assets()?),
+        None => None,
+    };
+
+    let mut presentation = box_game::Presentation::new(local_players);
+    let mut events = game_loop::events(FPS);
+    let mut toasts = Toasts::default();
+    // how steadily the host's inputs arrive, for the HUD
+    let mut feed = feed::FeedMonitor::default();
+    // how far the shown frame trails the host's latest one, for the lag graph
+    let mut lag = feed::LagHistory::new((LAG_GRAPH_SECONDS * FPS) as usize);
+    // the recent frames for local playback, and what is shown while playing them back
+    let mut rewind = RewindBuffer::new((REWIND_SECONDS * FPS) as usize, delay_frames);
+    let mut playback: Option<Playback> = None;
+    let mut view = box_game::Simulation::new(NUM_PLAYERS, local_players);
+    // H shows where the players spent their time this match and which buttons they held
+    let mut heatmap = Heatmap::new(NUM_PLAYERS);
+    let mut show_heatmap = false;
+    // with a broadcast delay, the last frame recorded into the heatmap and the state it is loaded into
+    let mut heatmap_frame: ggrs::Frame = 0;
+    let mut heatmap_view = box_game::Simulation::new(NUM_PLAYERS, local_players);
+    // F toggles fast-forwarding through a backlog of frames, faster than GGRS catches up on its own
+    let mut fast_forward = false;
+
+    // event loop, until the window is closed or Ctrl-C pressed
+    while let Some(e) = events.next(&mut window) {
+        if cli::interrupted() {
+            break;
+        }
+
+        // render
+        if let (Some(args), Some(gl)) = (e.render_args(), gl.as_mut()) {
+            // the same view as the players get, with the spectator's own HUD lines
+            let now = Instant::now();
+            let last_frame = match feed.since_last_frame(now) {
+                Some(since) => format!("{} ms ago", since.as_millis()),
+                None => "never".to_string(),
+            };
+            let host_line = match host.reconnect_at() {
+                Some(at) => format!(
+                    "Host: {} (reconnecting in {} s)",
+                    host_addr,
+                    at.saturating_duration_since(now).as_secs()
+                ),
+                None => format!("Host: {}", host_addr),
+            };
+            let frames_behind_host = host.session().map_or(0, |sess| sess.frames_behind_host());
+            presentation.debug_lines = vec![
+                host_line,
+                format!("Frames behind host: {}", frames_behind_host),
+                format!(
+                    "Shown frame behind host: {} (max {} in {} s)",
+                    lag.samples().last().unwrap_or(0),
+                    lag.max(),
+                    LAG_GRAPH_SECONDS
+                ),
+                format!("Frames received/s: {}", feed.frames_per_second()),
+                format!(
+                    "Last frame received: {}{}",
+                    last_frame,
+                    if feed.stalled(now) { " (stalled)" } else { "" }
+                ),
+            ];
+            if delay_frames > 0 {
+                presentation
+                    .debug_lines
+                    .push(format!("Broadcast delay: {} frames", delay_frames));
+            }
+            presentation.debug_lines.push(format!(
+                "Camera: {} (1 arena, 2/3 follow a player, 4 director, 5 player insets, H heatmap)",
+                camera_name(presentation.camera.preset)
+            ));
+            match &playback {
+                Some(current) => presentation.debug_lines.extend(vec![
+                    format!(
+                        "Playback: {} frames behind live (End to go live)",
+                        rewind.live().unwrap_or(current.frame) - current.frame
+                    ),
+                    format!("Speed: x{} (Up/Down)", SPEEDS[current.speed_index]),
+                    format!("Paused: {} (Space, Right to step)", current.paused),
+                ]),
+                None => presentation
+                    .debug_lines
+                    .push("Live (Left to rewind, Space to pause)".to_string()),
+            }
+            presentation.toasts = toasts.visible(Instant::now());
+            presentation.chat_lines = chat.lines();
+            presentation.player_names.clear();
+            presentation.player_names.extend_from_slice(host.names());
+            presentation.heatmap = show_heatmap.then(|| heatmap.clone());
+            if show_heatmap {
+                for (handle, name) in host.names().iter().enumerate() {
+                    let held = heatmap.held_line(handle, local_players);
+                    presentation
+                        .debug_lines
+                        .push(format!("{} held: {}", name, held));
+                }
+            }
+            presentation.lag_graph.clear();
+            presentation.lag_graph.extend(lag.samples());
+            presentation.banner = (frames_behind_host > CATCH_UP_FRAMES).then(|| {
+                let speed = feed.frames_per_second() as f64 / FPS as f64;
+                format!(
+                    "Catching up x{:.1}, {} frames behind{}",
+                    speed,
+                    frames_behind_host,
+                    if fast_forward {
+                        ""
+                    } else {
+                        " (F to fast-forward)"
+                    }
+                )
+            });
+            let game = host.game();
+            let (shown, shown_inputs) = if playback.is_some() || delay_frames > 0 {
+                let inputs = rewind.inputs(view.current_frame()).unwrap_or(&[]);
+                (&view, inputs)
+            } else {
+                (game, game.last_inputs())
+            };
+            presentation.camera.update(shown);
+            for inset in presentation.insets.iter_mut() {
+                inset.update(shown, shown_inputs);
+            }
+            if let Some(text) = text.as_mut() {
+                presentation.render(gl, text, &args, shown)?;
+            }
+        }
+
+        // game update
+        if e.update_args().is_some() {
+            let now = Instant::now();
+            for message in side_channel.receive() {
+                match message {
+                    SideMessage::Chat(text) => chat.push_history(text),
+                    SideMessage::PlayerName { handle, name }
+                        if handle < NUM_PLAYERS && fixed_names.is_none() =>
+                    {
+                        if let Some(overlay) = overlay.as_mut() {
+                            overlay.rename(handle, &name);
+                        }
+                        host.rename(handle, name);
+                    }
+                    SideMessage::Tuning { frame, tuning } => {
+                        match box_game::encoding::deserialize(&tuning) {
+                            Ok(tuning) => host.schedule_tuning(frame, tuning),
+                            Err(e) => tracing::warn!("Invalid tuning from the host: {}", e),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            host.update(now)?;
+
+            // tell GGRS it is time to advance the frame and handle the requests, several times while fast-forwarding
+            let ticks = match fast_forward
+                && host
+                    .session()
+                    .is_some_and(|sess| sess.frames_behind_host() > CATCH_UP_FRAMES)
+            {
+                true => FAST_FORWARD_TICKS,
+                false => 1,
+            };
+            let mut session_events = Vec::new();
+            for _ in 0..ticks {
+                let tick = match host.tick(now)? {
+                    Some(tick) => tick,
+                    None => break,
+                };
+                session_events.extend(tick.events);
+                if tick.advance == Advance::Advanced {
+                    let game = host.game();
+                    let mut saved = Ok(());
+                    rewind.push(game.current_frame(), game.last_inputs(), |keyframe| {
+                        saved = game.save_keyframe_into(keyframe)
+                    });
+                    saved?;
+                    // with a broadcast delay, the heatmap follows the delayed live frame instead, so it does not show the future
+                    if delay_frames == 0 {
+                        heatmap.record(game, game.last_inputs());
+                    }
+                    if let Some(overlay) = overlay.as_mut() {
+                        overlay.record(tick.hits, game.last_checksum());
+                    }
+                }
+                // a disconnect ended the session
+                if tick.advance != Advance::Advanced || host.session().is_none() {
+                    break;
+                }
+            }
+
+            for event in host.take_link_events() {
+                let notification = match event {
+                    LinkEvent::Reconnected => {
+                        // the new session starts over at the first frame
+                        rewind = RewindBuffer::new((REWIND_SECONDS * FPS) as usize, delay_frames);
+                        view = box_game::Simulation::new(NUM_PLAYERS, local_players);
+                        playback = None;
+                        lag.clear();
+                        heatmap = Heatmap::new(NUM_PLAYERS);
+                        heatmap_frame = 0;
+                        if let Some(overlay) = overlay.as_mut() {
+                            overlay.reset();
+                        }
+                        continue;
+                    }
+                    LinkEvent::ReconnectFailed { error, retry_in } => {
+                        Notification::warning(format!(
+                            "Could not reconnect: {}, retrying in {} s",
+                            error,
+                            retry_in.as_secs()
+                        ))
+                    }
+                    LinkEvent::HostSilent { retry_in } => Notification::warning(format!(
+                        "Host did not answer, retrying in {} s",
+                        retry_in.as_secs()
+                    )),
+                    LinkEvent::Disconnected { retry_in } => {
+                        tracing::info!("Disconnected from host.");
+                        Notification::error(format!(
+                            "Disconnected from host, reconnecting in {} s",
+                            retry_in.as_secs()
+                        ))
+                    }
+                };
+                toasts.push(notification, now);
+            }
+            // handle GGRS events, the disconnect was reported above
+            for event in session_events
+                .iter()
+                .filter(|event| !matches!(event, GGRSEvent::Disconnected { .. }))
+            {
+                if let Some(notification) = Notification::from_event(event) {
+                    toasts.push(notification, now);
+                }
+            }
+            let current_sess = match host.session() {
+                Some(current_sess) => current_sess,
+                None => continue,
+            };
+            let game = host.game();
+            feed.update(game.current_frame(), Instant::now());
+            // the match goes on underneath the local playback, which ends once it caught up
+            if let Some(current) = playback.as_mut() {
+                match current.tick(&rewind) {
+                    true => show_frame(&mut view, &rewind, current.frame)?,
+                    false => playback = None,
+                }
+            }
+            // with a broadcast delay, even the live view is played back from the buffer
+            if let (None, Some(live)) = (&playback, rewind.live()) {
+                if delay_frames > 0 {
+                    show_frame(&mut view, &rewind, live)?;
+                }
+            }
+            // the delay a viewer sees: how far the session trails the host, plus what playback and broadcast delay add
+            let shown_frame = match playback.is_some() || delay_frames > 0 {
+                true => view.current_frame(),
+                false => game.current_frame(),
+            };
+            lag.push(current_sess.frames_behind_host() + game.current_frame() - shown_frame);
+            // the delayed heatmap follows the live frame, also while a local playback shows another one
+            if let Some(live) = rewind
+                .live()
+                .filter(|&live| delay_frames > 0 && live > heatmap_frame)
+            {
+                show_frame(&mut heatmap_view, &rewind, live)?;
+                heatmap.record(&heatmap_view, rewind.inputs(live).unwrap_or(&[]));
+                heatmap_frame = live;
+            }
+            // the overlay shows the public view, local playback does not change it
+            if let (Some(overlay), Some(live)) = (overlay.as_mut(), rewind.live()) {
+                let link = Link {
+                    host: host_addr.to_string(),
+                    frames_behind_host: current_sess.frames_behind_host(),
+                    stalled: feed.stalled(now),
+                };
+                let data = overlay.data(live, local_players, link);
+                overlay.write(&data)?;
+            }
+        }
+
+        // camera presets
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            let preset = match key {
+                Key::D1 => Some(Preset::Arena),
+                Key::D2 => Some(Preset::Follow(0)),
+                Key::D3 => Some(Preset::Follow(1)),
+                Key::D4 => Some(Preset::Director),
+                _ => None,
+            };
+            if let Some(preset) = preset {
+                presentation.camera.preset = preset;
+            }
+            if key == Key::F {
+                fast_forward = !fast_forward;
+            }
+            if key == Key::H {
+                show_heatmap = !show_heatmap;
+            }
+            // a zoomed view of every player with their inputs, next to the main view
+            if key == Key::D5 {
+                presentation.insets = match presentation.insets.is_empty() {
+                    true => (0..NUM_PLAYERS).map(Inset::new).collect(),
+                    false => Vec::new(),
+                };
+            }
+        }
+
+        // local playback controls, they never affect the session
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            let seek_frames = (SEEK_SECONDS * FPS) as ggrs::Frame;
+            match (key, playback.as_mut()) {
+                (Key::Left, Some(current)) => current.seek_back(&rewind, seek_frames),
+                (Key::Left, None) => playback = Playback::rewind(&rewind, seek_frames),
+                (Key::Space, Some(current)) => current.paused = !current.paused,
+                (Key::Space, None) => {
+                    playback = Playback::rewind(&rewind, 0).map(|mut current| {
+                        current.paused = true;
+                        current
+                    })
+                }
+                (Key::Right, Some(current)) if current.paused => current.step(),
+                (Key::Up, Some(current)) => current.faster(),
+                (Key::Down, Some(current)) => current.slower(),
+                (Key::End, _) => playback = None,
+                _ => (),
+            }
+            if let Some(current) = &playback {
+                show_frame(&mut view, &rewind, current.frame)?;
+            }
+        }
+
+        // idle
+        if let Some(_args) = e.idle_args() {
+            host.poll();
+        }
+    }
+
+    // the last match, a match that ended with the host disconnecting has nothing left to write
+    host.finish_match(Instant::now())?;
+    Ok(())
+}
+
+fn camera_name(preset: Preset) -> String {
+    match preset {
+        Preset::Arena => "arena".to_string(),
+        Preset::Follow(handle) => format!("following player {}", handle + 1),
+        Preset::Director => "director".to_string(),
+    }
+}
+
+/// Loads the buffered state of `frame` into the simulation the local playback is shown with.
+fn show_frame(
+    view: &mut box_game::Simulation,
+    rewind: &RewindBuffer,
+    frame: ggrs::Frame,
+) -> anyhow::Result<()> {
+    if let Some(keyframe) = rewind.get(frame) {
+        view.load_keyframe(keyframe)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    cli::exit_on_error(run());
+}
@@ -1,293 +1,1153 @@
-extern crate freetype as ft;
-
-use ft::Library;
-use ggrs::{Frame, GGRSRequest, GameInput, GameState, GameStateCell, NULL_FRAME};
-use graphics::{Context, Graphics, ImageSize};
-use opengl_graphics::{GlGraphics, Texture, TextureSettings};
-use piston::input::RenderArgs;
+use crate::camera::{Camera, Inset};
+use crate::crash;
+use crate::heatmap::Heatmap;
+use crate::notifications::Notification;
+use boxgame_net::metrics::Burst;
+use boxgame_sim::snapshot::Snapshot;
+use boxgame_sim::versioned;
+use boxgame_sim::{delta, fletcher16, hash32, new_contacts, BoxGameState, Checksum, PerPlayer};
+pub use boxgame_sim::{
+    encoding, input, quantize_axis, scripted_input, tuning, Input, InputFlags, PlayerState, Tuning,
+    FPS, INPUT_SIZE,
+};
+use ggrs::{Frame, GGRSError, GGRSRequest, GameInput, GameState, GameStateCell, NULL_FRAME};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
 
-const FPS: u64 = 60;
-const NUM_PLAYERS: usize = 2;
 const CHECKSUM_PERIOD: i32 = 100;
+/// Every this many frames, logged frames carry a full copy of the game state, so replays can seek.
+const KEYFRAME_PERIOD: i32 = 300;
+
+/// Number of buttons per local player: up, left, down, right, dash
+pub const NUM_BUTTONS: usize = 5;
+
+/// How many frames GGRS may predict ahead. Frames further back than this can no longer be rolled back.
+const MAX_PREDICTION_FRAMES: i32 = 8;
+/// GGRS keeps the states of this many frames in its `GameStateCell`s, a new save overwrites the oldest one.
+const SAVED_STATE_SLOTS: usize = MAX_PREDICTION_FRAMES as usize + 2;
+/// Confirmed hits wait this many frames for `Simulation::take_confirmed_hits` and are dropped after that, so games
+/// that never take them, like replays, do not collect them for the whole match.
+const CONFIRMED_HIT_FRAMES: Frame = 60;
+/// A saved state larger than this many bytes logs a warning, unless `Simulation::state_size_warning` is changed.
+pub const DEFAULT_STATE_SIZE_WARNING: usize = 64 * 1024;
+/// With delta saves, every state of a frame divisible by this is saved in full and later saves are deltas against
+/// it. Longer than the prediction window, so a rollback never needs a base older than the previous one.
+const DELTA_BASE_PERIOD: Frame = 2 * MAX_PREDICTION_FRAMES;
+/// Number of full states kept as bases for delta saves.
+const DELTA_BASES: usize = 2;
+/// The first byte of every state saved for GGRS: what follows is either the encoded state...
+const FULL_SAVE: u8 = 0;
+/// ...or the frame of the base as a little endian `i32`, followed by a `boxgame_sim::delta` against it...
+const DELTA_SAVE: u8 = 1;
+/// ...or the bytes of a `Snapshot`, for games with up to `MAX_INLINE_PLAYERS` boxes.
+const SNAPSHOT_SAVE: u8 = 2;
+
+/// Generates mashed inputs for the turbo debug mode: a pseudo-random direction that changes every few frames,
+/// plus a dash on every other frame. Only used on the input generation side, so it needs no real randomness.
+fn turbo_buttons(frame: Frame, slot: usize) -> InputFlags {
+    // hash of the current direction period
+    let x = hash32((frame / 8) as u32 ^ (slot as u32).wrapping_mul(0x9e37_79b9));
+
+    let mut buttons = InputFlags::from_bits_truncate((x & 0b1111) as u8);
+    if frame % 2 == 0 {
+        buttons |= InputFlags::DASH;
+    }
+    buttons
+}
+
+/// State of a single button, fed by press/release events from the render loop and sampled once per simulation tick.
+/// Presses are latched until the next tick, so a tap that starts and ends between two ticks is still seen by exactly
+/// one tick, no matter how fast or slow the render loop runs compared to the simulation.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ButtonState {
+    held: bool,
+    pressed: bool,
+    released: bool,
+}
 
-const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-const BLUE: [f32; 4] = [0.0, 0.35, 0.78, 1.0];
-const ORANGE: [f32; 4] = [0.78, 0.59, 0.2, 1.0];
-const PLAYER_COLORS: [[f32; 4]; 2] = [BLUE, ORANGE];
+impl ButtonState {
+    pub fn press(&mut self) {
+        // ignore key repeat
+        if !self.held {
+            self.pressed = true;
+        }
+        self.held = true;
+    }
 
-const PLAYER_SIZE: f64 = 50.0;
-const WINDOW_HEIGHT: u32 = 800;
-const WINDOW_WIDTH: u32 = 600;
+    pub fn release(&mut self) {
+        if self.held {
+            self.released = true;
+        }
+        self.held = false;
+    }
 
-const INPUT_UP: u8 = 1 << 0;
-const INPUT_DOWN: u8 = 1 << 1;
-const INPUT_LEFT: u8 = 1 << 2;
-const INPUT_RIGHT: u8 = 1 << 3;
+    /// Whether the button counts as held down for the current tick, including taps since the last tick.
+    pub fn is_down(&self) -> bool {
+        self.held || self.pressed
+    }
 
-const MOVEMENT_SPEED: f64 = 15.0 / FPS as f64;
-const ROTATION_SPEED: f64 = 2.5 / FPS as f64;
-const MAX_SPEED: f64 = 7.0;
-const FRICTION: f64 = 0.98;
+    /// Whether the button went down since the last tick.
+    pub fn just_pressed(&self) -> bool {
+        self.pressed
+    }
 
-/// Computes the fletcher16 checksum, copied from wikipedia: <https://en.wikipedia.org/wiki/Fletcher%27s_checksum>
-fn fletcher16(data: &[u8]) -> u16 {
-    let mut sum1: u16 = 0;
-    let mut sum2: u16 = 0;
+    /// Whether the button went up since the last tick.
+    pub fn just_released(&self) -> bool {
+        self.released
+    }
 
-    for index in 0..data.len() {
-        sum1 = (sum1 + data[index] as u16) % 255;
-        sum2 = (sum2 + sum1) % 255;
+    /// Clears the latched edges, call once the tick that sampled them has been accepted.
+    pub fn clear_edges(&mut self) {
+        self.pressed = false;
+        self.released = false;
     }
+}
 
-    (sum2 << 8) | sum1
+/// Two boxes starting to touch, recorded by the simulation for feedback like rumble. Not part of the game state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HitEvent {
+    pub frame: Frame,
+    pub players: (usize, usize),
 }
 
-fn glyphs(face: &mut ft::Face, text: &str) -> Vec<(Texture, [f64; 2])> {
-    let mut x = 10;
-    let mut y = 0;
-    let mut res = vec![];
-    for ch in text.chars() {
-        face.load_char(ch as usize, ft::face::LoadFlag::RENDER)
-            .unwrap();
-        let g = face.glyph();
-
-        let bitmap = g.bitmap();
-        let texture = Texture::from_memory_alpha(
-            bitmap.buffer(),
-            bitmap.width() as u32,
-            bitmap.rows() as u32,
-            &TextureSettings::new(),
-        )
-        .unwrap();
-        res.push((
-            texture,
-            [(x + g.bitmap_left()) as f64, (y - g.bitmap_top()) as f64],
-        ));
+/// A node of the game state as shown in the state inspector, see `Simulation::state_tree`.
+pub struct TreeNode {
+    pub label: String,
+    pub children: Vec<TreeNode>,
+}
 
-        x += (g.advance().x >> 6) as i32;
-        y += (g.advance().y >> 6) as i32;
+impl TreeNode {
+    fn leaf(label: String) -> Self {
+        Self {
+            label,
+            children: Vec::new(),
+        }
     }
-    res
 }
 
-fn render_text<G, T>(glyphs: &[(T, [f64; 2])], c: &Context, gl: &mut G)
-where
-    G: Graphics<Texture = T>,
-    T: ImageSize,
-{
-    for &(ref texture, [x, y]) in glyphs {
-        use graphics::*;
+/// Why the game could not advance or load a state.
+#[derive(Debug, thiserror::Error)]
+pub enum GameError {
+    #[error(transparent)]
+    Session(#[from] GGRSError),
+    #[error("GGRS asked to load the state of frame {0}, which was never saved")]
+    MissingState(Frame),
+    #[error("cannot decode the saved game state: {0}")]
+    CorruptState(#[from] bincode::Error),
+    #[error("the saved state of frame {0} is a delta against frame {1}, which is no longer held")]
+    MissingDeltaBase(Frame, Frame),
+    #[error("the saved state of frame {0} is malformed")]
+    MalformedSave(Frame),
+    #[error("cannot encode the game state: {0}")]
+    EncodeState(bincode::Error),
+    #[error("GGRS asked to save frame {requested}, but the game is at frame {current}")]
+    UnexpectedSaveFrame { requested: Frame, current: Frame },
+    #[error("simulating frame {0} twice from the same state and inputs gave different results")]
+    Nondeterministic(Frame),
+    #[cfg(feature = "render")]
+    #[error("cannot render text: {0}")]
+    Font(#[from] freetype::Error),
+    #[cfg(feature = "render")]
+    #[error("cannot create a texture: {0}")]
+    Texture(String),
+}
+
+/// The encoded inputs of every peer for one frame, `None` for disconnected peers.
+pub(crate) type FrameInputs = Vec<Option<Vec<u8>>>;
+
+/// Input buffers of frames that are gone, reused for the inputs of the next frames. Every `AdvanceFrame` request
+/// needs its inputs as owned buffers for the frame log, so without reuse a rollback allocates a list and a buffer per
+/// peer for every resimulated frame.
+#[derive(Default)]
+struct InputPool {
+    lists: Vec<FrameInputs>,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl InputPool {
+    /// Enough for a rollback through the whole prediction window, more are dropped instead of kept around.
+    const MAX_LISTS: usize = SAVED_STATE_SLOTS;
+    const MAX_BUFFERS: usize = 4 * SAVED_STATE_SLOTS;
+
+    /// Copies the inputs of a frame into recycled buffers.
+    fn copy<'a>(&mut self, inputs: impl Iterator<Item = Option<&'a [u8]>>) -> FrameInputs {
+        let mut list = self.lists.pop().unwrap_or_default();
+        list.extend(inputs.map(|input| {
+            input.map(|input| {
+                let mut buffer = self.buffers.pop().unwrap_or_default();
+                buffer.extend_from_slice(input);
+                buffer
+            })
+        }));
+        list
+    }
 
-        Image::new_color(color::WHITE).draw(texture, &c.draw_state, c.transform.trans(x, y), gl);
+    fn recycle(&mut self, mut list: FrameInputs) {
+        for mut buffer in list.drain(..).flatten() {
+            if self.buffers.len() < Self::MAX_BUFFERS {
+                buffer.clear();
+                self.buffers.push(buffer);
+            }
+        }
+        if self.lists.len() < Self::MAX_LISTS {
+            self.lists.push(list);
+        }
     }
 }
 
-pub struct BoxGame {
-    game_state: BoxGameState,
-    pub key_states: [bool; 4],
-    font: PathBuf,
+/// The inputs and resulting checksum of a single simulated frame, as stored in replays.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+    pub frame: Frame,
+    /// the encoded input of every peer, `None` for disconnected peers
+    pub inputs: Vec<Option<Vec<u8>>>,
+    /// checksum of the game state after simulating the frame
+    pub checksum: u64,
+    /// the game state after simulating the frame, for every `KEYFRAME_PERIOD`th frame, encoded by `versioned`
+    pub keyframe: Option<Vec<u8>>,
+    /// the gameplay constants used from this frame on, if they changed with it
+    pub tuning: Option<Tuning>,
+}
+
+/// A copy of the current state for another `Simulation`, e.g. for the render thread's copy. Games that fit a
+/// `Snapshot` are copied as one, without serializing or allocating.
+// boxing the snapshot would bring back the allocation per copy
+#[allow(clippy::large_enum_variant)]
+pub enum StateCopy {
+    Snapshot(Snapshot),
+    /// encoded by `versioned`, for games with too many boxes for a snapshot
+    Keyframe(Vec<u8>),
+}
+
+/// The rolled back part of the game: the game state, and how GGRS requests advance, save and load it. Only what
+/// is derived from the state lives here, local controls and everything rendered belong to `Presentation`.
+pub struct Simulation {
+    pub(crate) game_state: BoxGameState,
+    /// number of boxes controlled by each peer (i.e. each GGRS player handle)
+    pub(crate) local_players: usize,
     last_checksum: (Frame, u64),
+    /// size in bytes of the last state saved for GGRS
+    last_save_size: usize,
+    /// buffers of loaded states, reused by the next saves. GGRS keeps the buffers it is given and drops them when
+    /// their cell is overwritten, so loads during rollbacks are the only way to get one back.
+    spare_buffers: Vec<Vec<u8>>,
+    /// the positions before the frame being simulated, kept to avoid allocating a copy every frame
+    old_positions: PerPlayer<(f64, f64)>,
+    /// save states as deltas against a recent full state, for large states that change little per frame
+    pub delta_saves: bool,
+    /// the encoded full states delta saves refer to, by frame
+    delta_bases: Vec<(Frame, Vec<u8>)>,
+    /// scratch buffer for encoding and decoding delta saves
+    encoded_state: Vec<u8>,
+    /// size in bytes of the state in each of GGRS' cells, indexed by frame modulo `SAVED_STATE_SLOTS`
+    saved_sizes: [usize; SAVED_STATE_SLOTS],
+    /// saved states larger than this many bytes log a warning
+    pub state_size_warning: usize,
+    /// the next warning is only logged once a state is at least this large, so a growing state does not spam the log
+    next_state_size_warning: usize,
     periodic_checksum: (Frame, u64),
+    /// hits of frames that might still be rolled back
+    hit_events: Vec<HitEvent>,
+    /// simulated frames that might still be rolled back, only kept if the frame log is enabled
+    frame_log: Option<Vec<FrameRecord>>,
+    /// debug mode: after a rollback, log how the resimulated state differs from the mispredicted one
+    pub diff_rollbacks: bool,
+    /// paranoid debug mode: simulate every frame twice and fail with `GameError::Nondeterministic` if the results are
+    /// not identical
+    pub double_simulate: bool,
+    /// the state before the last rollback, until resimulation has caught up with it
+    mispredicted: Option<BoxGameState>,
+    /// the gameplay constants of every frame, see `schedule_tuning`
+    tuning: tuning::TuningSchedule,
+    /// input buffers of rolled back or unlogged frames
+    input_pool: InputPool,
+    /// the inputs of the last simulated frame, for input displays
+    last_inputs: FrameInputs,
 }
 
-impl BoxGame {
-    pub fn new(font: PathBuf) -> Self {
+impl Simulation {
+    /// Creates a game for `num_peers` GGRS players, each controlling `local_players` boxes.
+    pub fn new(num_peers: usize, local_players: usize) -> Self {
         Self {
-            game_state: BoxGameState::new(),
-            key_states: [false; 4],
-            font,
+            game_state: BoxGameState::new(num_peers * local_players),
+            local_players,
             last_checksum: (NULL_FRAME, 0),
+            last_save_size: 0,
+            spare_buffers: Vec::new(),
+            old_positions: PerPlayer::new(),
+            delta_saves: false,
+            delta_bases: Vec::new(),
+            encoded_state: Vec::new(),
+            saved_sizes: [0; SAVED_STATE_SLOTS],
+            state_size_warning: DEFAULT_STATE_SIZE_WARNING,
+            next_state_size_warning: 0,
             periodic_checksum: (NULL_FRAME, 0),
+            hit_events: Vec::new(),
+            frame_log: None,
+            diff_rollbacks: false,
+            double_simulate: false,
+            mispredicted: None,
+            tuning: tuning::TuningSchedule::default(),
+            input_pool: InputPool::default(),
+            last_inputs: Vec::new(),
         }
     }
 
-    pub fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
+    pub fn handle_requests(&mut self, requests: Vec<GGRSRequest>) -> Result<(), GameError> {
+        let burst = Burst::of(&requests);
+        let _span = tracing::debug_span!(
+            "handle_requests",
+            loads = burst.loads,
+            saves = burst.saves,
+            advances = burst.advances
+        )
+        .entered();
         for request in requests {
-            match request {
-                GGRSRequest::LoadGameState { cell } => self.load_game_state(cell),
-                GGRSRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
-                GGRSRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
-            }
+            self.handle_request(request)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a single request, for callers that measure each request on its own.
+    pub fn handle_request(&mut self, request: GGRSRequest) -> Result<(), GameError> {
+        match request {
+            GGRSRequest::LoadGameState { cell } => self.load_game_state(cell)?,
+            GGRSRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame)?,
+            GGRSRequest::AdvanceFrame { inputs } => self.advance_frame(inputs)?,
         }
+        Ok(())
     }
 
-    fn save_game_state(&mut self, cell: GameStateCell, frame: Frame) {
-        assert_eq!(self.game_state.frame, frame);
-        let buffer = bincode::serialize(&self.game_state).unwrap();
-        let checksum = fletcher16(&buffer) as u64;
+    fn save_game_state(&mut self, cell: GameStateCell, frame: Frame) -> Result<(), GameError> {
+        let _span = tracing::debug_span!("save_game_state", frame).entered();
+        if self.game_state.frame != frame {
+            return Err(GameError::UnexpectedSaveFrame {
+                requested: frame,
+                current: self.game_state.frame,
+            });
+        }
+        let mut buffer = self
+            .spare_buffers
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.last_save_size));
+        buffer.clear();
+        if self.delta_saves {
+            self.encode_delta_save(frame, &mut buffer)?;
+        } else if let Some(snapshot) = Snapshot::capture(&self.game_state) {
+            buffer.push(SNAPSHOT_SAVE);
+            buffer.extend_from_slice(snapshot.as_bytes());
+        } else {
+            buffer.push(FULL_SAVE);
+            encoding::serialize_into(&mut buffer, &self.game_state)
+                .map_err(GameError::EncodeState)?;
+        }
+        let checksum = self.game_state.checksum();
+        self.last_save_size = buffer.len();
+        self.saved_sizes[frame as usize % SAVED_STATE_SLOTS] = buffer.len();
+        if buffer.len() > self.state_size_warning && buffer.len() >= self.next_state_size_warning {
+            tracing::warn!(
+                "The state of frame {} is {} bytes, {} bytes are held in saved states",
+                frame,
+                buffer.len(),
+                self.held_state_bytes()
+            );
+            self.next_state_size_warning = buffer.len() * 2;
+        }
 
         cell.save(GameState::new(frame, Some(buffer), Some(checksum)));
+        Ok(())
     }
 
-    fn load_game_state(&mut self, cell: GameStateCell) {
+    /// Writes the current state to `buffer` as a delta against the latest base before it, or in full if there is
+    /// no such base, the frame is due to become one, or the delta would not be smaller.
+    fn encode_delta_save(&mut self, frame: Frame, buffer: &mut Vec<u8>) -> Result<(), GameError> {
+        self.encoded_state.clear();
+        encoding::serialize_into(&mut self.encoded_state, &self.game_state)
+            .map_err(GameError::EncodeState)?;
+        let base = self
+            .delta_bases
+            .iter()
+            .filter(|(base_frame, _)| *base_frame < frame)
+            .max_by_key(|(base_frame, _)| *base_frame);
+        if frame % DELTA_BASE_PERIOD == 0 || base.is_none() {
+            // a resimulated frame replaces its mispredicted base, the deltas against that are resaved as well
+            self.delta_bases
+                .retain(|(base_frame, _)| *base_frame != frame);
+            if self.delta_bases.len() == DELTA_BASES {
+                let oldest = (0..DELTA_BASES)
+                    .min_by_key(|&i| self.delta_bases[i].0)
+                    .unwrap();
+                self.delta_bases.remove(oldest);
+            }
+            self.delta_bases.push((frame, self.encoded_state.clone()));
+        } else if let Some((base_frame, base)) = base {
+            buffer.push(DELTA_SAVE);
+            buffer.extend_from_slice(&base_frame.to_le_bytes());
+            delta::encode_into(base, &self.encoded_state, buffer);
+            if buffer.len() <= self.encoded_state.len() {
+                return Ok(());
+            }
+            buffer.clear();
+        }
+        buffer.push(FULL_SAVE);
+        buffer.extend_from_slice(&self.encoded_state);
+        Ok(())
+    }
+
+    /// Decodes a state saved by `save_game_state`.
+    fn decode_save(&mut self, frame: Frame, buffer: &[u8]) -> Result<BoxGameState, GameError> {
+        match buffer.split_first() {
+            Some((&FULL_SAVE, state)) => Ok(encoding::deserialize(state)?),
+            Some((&SNAPSHOT_SAVE, snapshot)) => Snapshot::from_bytes(snapshot)
+                .map(|snapshot| snapshot.to_state())
+                .ok_or(GameError::MalformedSave(frame)),
+            Some((&DELTA_SAVE, rest)) if rest.len() >= 4 => {
+                let base_frame = Frame::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                let (_, base) = self
+                    .delta_bases
+                    .iter()
+                    .find(|(f, _)| *f == base_frame)
+                    .ok_or(GameError::MissingDeltaBase(frame, base_frame))?;
+                delta::decode_into(base, &rest[4..], &mut self.encoded_state)
+                    .ok_or(GameError::MalformedSave(frame))?;
+                Ok(encoding::deserialize(&self.encoded_state)?)
+            }
+            _ => Err(GameError::MalformedSave(frame)),
+        }
+    }
+
+    fn load_game_state(&mut self, cell: GameStateCell) -> Result<(), GameError> {
+        let _span = tracing::debug_span!("load_game_state").entered();
         let state_to_load = cell.load();
-        self.game_state = bincode::deserialize(&state_to_load.buffer.unwrap()).unwrap();
+        let buffer = state_to_load
+            .buffer
+            .ok_or(GameError::MissingState(state_to_load.frame))?;
+        let loaded = self.decode_save(state_to_load.frame, &buffer)?;
+        if self.spare_buffers.len() < SAVED_STATE_SLOTS {
+            self.spare_buffers.push(buffer);
+        }
+        let mispredicted = std::mem::replace(&mut self.game_state, loaded);
+        if self.diff_rollbacks {
+            self.mispredicted = Some(mispredicted);
+        }
+        // hits after the loaded frame were mispredicted, they are recorded again during resimulation
+        let frame = self.game_state.frame;
+        self.hit_events.retain(|hit| hit.frame <= frame);
+        if let Some(frame_log) = self.frame_log.as_mut() {
+            // the log is in frame order
+            let kept = frame_log
+                .iter()
+                .take_while(|record| record.frame <= frame)
+                .count();
+            for record in frame_log.drain(kept..) {
+                self.input_pool.recycle(record.inputs);
+            }
+        }
+        Ok(())
+    }
+
+    fn advance_frame(&mut self, inputs: Vec<GameInput>) -> Result<(), GameError> {
+        let inputs = self.input_pool.copy(
+            inputs
+                .iter()
+                .map(|input| (input.frame != NULL_FRAME).then(|| input.input())),
+        );
+        self.simulate_frame(inputs)
     }
 
-    fn advance_frame(&mut self, inputs: Vec<GameInput>) {
-        // increase the frame counter
-        self.game_state.frame += 1;
+    /// Advances the game by one frame, given the encoded input of every peer and `None` for disconnected peers.
+    /// This is what GGRS drives through `AdvanceFrame` requests, and what replays drive directly.
+    pub fn simulate_frame(&mut self, inputs: FrameInputs) -> Result<(), GameError> {
+        let _span =
+            tracing::debug_span!("simulate_frame", frame = self.game_state.frame + 1).entered();
+        self.old_positions.clone_from(&self.game_state.positions);
+        // in double simulation mode, the frame is simulated a second time from a copy of the state
+        let saved = if self.double_simulate {
+            Some(self.save_keyframe()?)
+        } else {
+            None
+        };
+        let tuning = *self.tuning.at(self.game_state.frame + 1);
+        let tuning_change = self.tuning.change_at(self.game_state.frame + 1).copied();
+        self.game_state
+            .advance(&inputs, self.local_players, &tuning);
+        self.tuning
+            .prune(self.game_state.frame - MAX_PREDICTION_FRAMES);
+
+        // record boxes that started touching this frame
+        for players in new_contacts(&self.old_positions, &self.game_state.positions) {
+            self.hit_events.push(HitEvent {
+                frame: self.game_state.frame,
+                players,
+            });
+        }
+        let stale_frame = self.game_state.frame - MAX_PREDICTION_FRAMES - CONFIRMED_HIT_FRAMES;
+        if self
+            .hit_events
+            .first()
+            .is_some_and(|hit| hit.frame <= stale_frame)
+        {
+            self.hit_events.retain(|hit| hit.frame > stale_frame);
+        }
 
-        for i in 0..NUM_PLAYERS {
-            // get input of that player
-            let input;
-            // check if the player is disconnected (disconnected players might maybe do something different)
-            if inputs[i].frame == NULL_FRAME {
-                input = 4; // disconnected players spin
-            } else {
-                input = bincode::deserialize(inputs[i].input()).unwrap();
+        if let Some(saved) = saved {
+            let mut resimulated = versioned::decode(&saved)?;
+            resimulated.advance(&inputs, self.local_players, &tuning);
+            let resimulated = versioned::encode(&resimulated).map_err(GameError::EncodeState)?;
+            if resimulated != self.save_keyframe()? {
+                let error = GameError::Nondeterministic(self.game_state.frame);
+                crash::record_failure(&error.to_string());
+                return Err(error);
+            }
+        }
+        // remember checksum to render it later
+        let checksum = self.game_state.checksum();
+        self.last_checksum = (self.game_state.frame, checksum);
+        crash::record_frame(&self.game_state, &inputs, checksum);
+        self.last_inputs.clone_from(&inputs);
+        let keyframe = if self.frame_log.is_some() && self.game_state.frame % KEYFRAME_PERIOD == 0 {
+            Some(self.save_keyframe()?)
+        } else {
+            None
+        };
+        match self.frame_log.as_mut() {
+            Some(frame_log) => {
+                frame_log.push(FrameRecord {
+                    frame: self.game_state.frame,
+                    inputs,
+                    checksum,
+                    keyframe,
+                    tuning: tuning_change,
+                });
             }
+            None => self.input_pool.recycle(inputs),
+        }
+        if self.game_state.frame % CHECKSUM_PERIOD == 0 {
+            self.periodic_checksum = (self.game_state.frame, checksum);
+        }
+
+        #[cfg(feature = "fp-audit")]
+        self.game_state.audit_finite();
+
+        // resimulation caught up with the mispredicted state
+        if let Some(mispredicted) = &self.mispredicted {
+            if mispredicted.frame == self.game_state.frame {
+                let differences = mispredicted.diff(&self.game_state);
+                tracing::info!(
+                    "Rollback at frame {}: {} fields diverged from the prediction",
+                    self.game_state.frame,
+                    differences.len()
+                );
+                for difference in differences {
+                    tracing::info!("  {}", difference);
+                }
+                self.mispredicted = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Debug helper to stress the rollback path: saves the current state and immediately loads it back,
+    /// panicking if the round trip changed the state.
+    pub fn stress_save_load(&mut self) {
+        let buffer = encoding::serialize(&self.game_state).unwrap();
+        let checksum = self.game_state.checksum();
+        self.game_state = encoding::deserialize(&buffer).unwrap();
+        assert_eq!(
+            checksum,
+            self.game_state.checksum(),
+            "save/load round trip changed the game state"
+        );
+    }
+
+    /// Removes and returns the hits of frames that can no longer be rolled back,
+    /// so feedback is only triggered once and never for mispredicted hits.
+    pub fn take_confirmed_hits(&mut self) -> Vec<HitEvent> {
+        let confirmed_frame = self.game_state.frame - MAX_PREDICTION_FRAMES;
+        // the hits are in frame order, the confirmed ones come first
+        let confirmed = self
+            .hit_events
+            .iter()
+            .take_while(|hit| hit.frame <= confirmed_frame)
+            .count();
+        if confirmed == 0 {
+            return Vec::new();
+        }
+        let pending = self.hit_events.split_off(confirmed);
+        std::mem::replace(&mut self.hit_events, pending)
+    }
+
+    /// Starts keeping a log of simulated frames, see `take_confirmed_frames`.
+    pub fn enable_frame_log(&mut self) {
+        self.frame_log = Some(Vec::new());
+    }
+
+    /// Removes and returns the logged frames that can no longer be rolled back, in order.
+    pub fn take_confirmed_frames(&mut self) -> Vec<FrameRecord> {
+        let confirmed_frame = self.game_state.frame - MAX_PREDICTION_FRAMES;
+        match self.frame_log.as_mut() {
+            Some(frame_log) => {
+                let count = frame_log
+                    .iter()
+                    .take_while(|record| record.frame <= confirmed_frame)
+                    .count();
+                frame_log.drain(..count).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Removes and returns all logged frames, e.g. when the session ended and no more rollbacks can happen.
+    pub fn take_all_frames(&mut self) -> Vec<FrameRecord> {
+        match self.frame_log.as_mut() {
+            Some(frame_log) => std::mem::take(frame_log),
+            None => Vec::new(),
+        }
+    }
+
+    /// A hash of the starting arena, so replays can tell whether they start from the same initial state.
+    /// Still the fletcher16 of the encoded state, so replays recorded before `Checksum` existed remain playable.
+    pub fn map_hash(&self) -> u64 {
+        let arena = BoxGameState::new(self.game_state.positions.len());
+        fletcher16(&encoding::serialize(&arena).unwrap()) as u64
+    }
+
+    /// Resets the game to the starting state at frame 0.
+    pub fn restart(&mut self) {
+        self.game_state = BoxGameState::new(self.game_state.positions.len());
+        self.delta_bases.clear();
+        self.last_checksum = (NULL_FRAME, 0);
+        self.periodic_checksum = (NULL_FRAME, 0);
+    }
+
+    /// Makes the current state the new starting point at frame 0, e.g. to start a new session from it. The tuning the
+    /// next frame would have used applies from frame 1 on.
+    pub fn rebase_frame(&mut self) {
+        let tuning = *self.tuning.at(self.game_state.frame + 1);
+        self.tuning = tuning::TuningSchedule::default();
+        if tuning != Tuning::DEFAULT {
+            self.tuning.schedule(1, tuning);
+        }
+        self.game_state.frame = 0;
+        self.delta_bases.clear();
+        self.last_checksum = (NULL_FRAME, 0);
+        self.periodic_checksum = (NULL_FRAME, 0);
+        self.hit_events.clear();
+        if let Some(frame_log) = self.frame_log.as_mut() {
+            frame_log.clear();
+        }
+    }
+
+    /// Simulates `frame` and all later frames with `tuning`. Both peers have to schedule the same change before either
+    /// simulates that frame, so it has to be announced well ahead. Returns `false` without scheduling anything if the
+    /// frame was already simulated, the peers would disagree about it.
+    pub fn schedule_tuning(&mut self, frame: Frame, tuning: Tuning) -> bool {
+        if frame <= self.game_state.frame {
+            return false;
+        }
+        self.tuning.schedule(frame, tuning);
+        true
+    }
+
+    /// Replaces the gameplay constants of every frame, e.g. with the changes recorded in a replay. Unlike
+    /// `schedule_tuning`, this also changes frames that were already simulated, for when they are simulated again.
+    pub fn set_tuning(&mut self, schedule: tuning::TuningSchedule) {
+        self.tuning = schedule;
+    }
+
+    /// Serializes the current state in the same format as the keyframes in `FrameRecord`, see `versioned`.
+    pub fn save_keyframe(&self) -> Result<Vec<u8>, GameError> {
+        versioned::encode(&self.game_state).map_err(GameError::EncodeState)
+    }
+
+    /// Like `save_keyframe`, but reuses the allocation of `keyframe`.
+    pub fn save_keyframe_into(&self, keyframe: &mut Vec<u8>) -> Result<(), GameError> {
+        versioned::encode_into(&self.game_state, keyframe).map_err(GameError::EncodeState)
+    }
+
+    /// Loads a keyframe stored in a `FrameRecord`. Keyframes come from replay files, so they may be corrupt or of an
+    /// older state version, which is migrated.
+    pub fn load_keyframe(&mut self, keyframe: &[u8]) -> Result<(), GameError> {
+        self.replace_state(versioned::decode(keyframe)?);
+        Ok(())
+    }
+
+    /// Copies the current state, to be loaded into another simulation with `load_state_copy`.
+    pub fn copy_state(&self) -> Result<StateCopy, GameError> {
+        Ok(match Snapshot::capture(&self.game_state) {
+            Some(snapshot) => StateCopy::Snapshot(snapshot),
+            None => StateCopy::Keyframe(self.save_keyframe()?),
+        })
+    }
+
+    pub fn load_state_copy(&mut self, copy: &StateCopy) -> Result<(), GameError> {
+        match copy {
+            StateCopy::Snapshot(snapshot) => self.replace_state(snapshot.to_state()),
+            StateCopy::Keyframe(keyframe) => self.load_keyframe(keyframe)?,
+        }
+        Ok(())
+    }
+
+    fn replace_state(&mut self, state: BoxGameState) {
+        self.game_state = state;
+        self.delta_bases.clear();
+        self.last_inputs.clear();
+        self.last_checksum = (self.game_state.frame, self.game_state.checksum());
+    }
+
+    /// The inputs of the last simulated frame, one entry per GGRS player and `None` for disconnected ones. Empty
+    /// before the first frame and after loading a keyframe.
+    pub fn last_inputs(&self) -> &[Option<Vec<u8>>] {
+        &self.last_inputs
+    }
+
+    /// The frame and checksum of the last simulated frame.
+    pub fn last_checksum(&self) -> (Frame, u64) {
+        self.last_checksum
+    }
+
+    /// Size in bytes of the last state saved for GGRS.
+    pub fn last_save_size(&self) -> usize {
+        self.last_save_size
+    }
+
+    /// Bytes of serialized state currently held in GGRS' `GameStateCell`s.
+    pub fn held_state_bytes(&self) -> usize {
+        self.saved_sizes.iter().sum()
+    }
+
+    /// The current state of a box, `None` if there is no such player.
+    pub fn player_state(&self, player: usize) -> Option<PlayerState> {
+        let state = &self.game_state;
+        Some(PlayerState {
+            position: *state.positions.get(player)?,
+            velocity: state.velocities[player],
+            rotation: state.rotations[player],
+        })
+    }
+
+    /// The full game state as a tree, for the state inspector.
+    pub fn state_tree(&self) -> TreeNode {
+        let state = &self.game_state;
+        let mut children = vec![TreeNode::leaf(format!("frame: {}", state.frame))];
+        for i in 0..state.positions.len() {
+            let (x, y) = state.positions[i];
+            let (vel_x, vel_y) = state.velocities[i];
+            let rot = state.rotations[i];
+            children.push(TreeNode {
+                label: format!("player {}", i),
+                children: vec![
+                    TreeNode::leaf(format!("position: ({:.3}, {:.3})", x, y)),
+                    TreeNode::leaf(format!("velocity: ({:.3}, {:.3})", vel_x, vel_y)),
+                    TreeNode::leaf(format!(
+                        "rotation: {:.4} rad ({:.1} deg)",
+                        rot,
+                        rot.to_degrees()
+                    )),
+                ],
+            });
+        }
+        TreeNode {
+            label: "state".to_string(),
+            children,
+        }
+    }
+
+    /// The frame and checksum of the last simulated frame divisible by `CHECKSUM_PERIOD`.
+    pub fn periodic_checksum(&self) -> (Frame, u64) {
+        self.periodic_checksum
+    }
+
+    /// The frame the next call to `advance_frame` will simulate from.
+    pub fn current_frame(&self) -> Frame {
+        self.game_state.frame
+    }
+}
 
-            // old values
-            let (old_x, old_y) = self.game_state.positions[i];
-            let (old_vel_x, old_vel_y) = self.game_state.velocities[i];
-            let mut rot = self.game_state.rotations[i];
+/// The part of the game that is never rolled back: local controls, debug text and rendering. It only reads the
+/// `Simulation`, so nothing pressed or shown here can end up in the state GGRS saves.
+pub struct Presentation {
+    local_players: usize,
+    /// one set of button states per local player, see `NUM_BUTTONS` for the order
+    pub key_states: Vec<[ButtonState; NUM_BUTTONS]>,
+    /// per local player: whether pressing UP toggles continuous thrust instead of having to hold it
+    pub thrust_toggle: Vec<bool>,
+    /// per local player: whether toggled thrust is currently on
+    thrust_on: Vec<bool>,
+    /// raw left stick axes `[x, y]` as reported by the controller
+    pub stick_axes: [f64; 2],
+    /// extra lines of debug information rendered below the checksums
+    pub debug_lines: Vec<String>,
+    /// chat history and text entry, rendered at the bottom of the window
+    pub chat_lines: Vec<String>,
+    /// notifications rendered as toasts above the chat
+    pub toasts: Vec<Notification>,
+    /// which part of the arena is drawn, the whole of it unless a spectator picks another preset
+    pub camera: Camera,
+    /// small views of single players drawn over the main one, see `Inset`
+    pub insets: Vec<Inset>,
+    /// a status line drawn large in the middle of the window, e.g. while a spectator catches up
+    pub banner: Option<String>,
+    /// names drawn above the boxes, by GGRS player handle, none if empty
+    pub player_names: Vec<String>,
+    /// frames behind the host over the last seconds, oldest first, drawn as a graph below the debug lines if not
+    /// empty
+    pub lag_graph: Vec<i32>,
+    /// drawn over the arena below the boxes if set, see `Heatmap`
+    pub heatmap: Option<Heatmap>,
+    /// debug mode: local players mash random directions and dash every other frame, for unattended stress tests
+    pub turbo: bool,
+}
+
+impl Presentation {
+    /// Creates the presentation for a peer controlling `local_players` boxes.
+    pub fn new(local_players: usize) -> Self {
+        Self {
+            local_players,
+            key_states: vec![[ButtonState::default(); NUM_BUTTONS]; local_players],
+            thrust_toggle: vec![false; local_players],
+            thrust_on: vec![false; local_players],
+            stick_axes: [0.0; 2],
+            debug_lines: Vec::new(),
+            chat_lines: Vec::new(),
+            toasts: Vec::new(),
+            camera: Camera::default(),
+            insets: Vec::new(),
+            banner: None,
+            player_names: Vec::new(),
+            lag_graph: Vec::new(),
+            heatmap: None,
+            turbo: false,
+        }
+    }
 
-            // slow down
-            let mut vel_x = old_vel_x * FRICTION;
-            let mut vel_y = old_vel_y * FRICTION;
+    /// Encodes the inputs of all local players for the given frame back to back, `INPUT_SIZE` bytes each.
+    pub fn local_input(&self, frame: Frame) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(INPUT_SIZE * self.local_players);
+        for (slot, key_states) in self.key_states.iter().enumerate() {
+            // Create a set of pressed Keys.
+            let mut buttons = InputFlags::empty();
 
-            // thrust
-            if input & INPUT_UP != 0 && input & INPUT_DOWN == 0 {
-                vel_x += MOVEMENT_SPEED * rot.cos();
-                vel_y += MOVEMENT_SPEED * rot.sin();
+            // ugly, but it works...
+            if self.thrust_toggle[slot] {
+                // the toggle only flips for good in clear_input_edges, once this input was accepted
+                if self.thrust_on[slot] != key_states[0].just_pressed() {
+                    buttons |= InputFlags::UP;
+                }
+            } else if key_states[0].is_down() {
+                buttons |= InputFlags::UP;
+            }
+            if key_states[1].is_down() {
+                buttons |= InputFlags::LEFT;
             }
-            //break
-            if input & INPUT_UP == 0 && input & INPUT_DOWN != 0 {
-                vel_x -= MOVEMENT_SPEED * rot.cos();
-                vel_y -= MOVEMENT_SPEED * rot.sin();
+            if key_states[2].is_down() {
+                buttons |= InputFlags::DOWN;
             }
-            // turn left
-            if input & INPUT_LEFT != 0 && input & INPUT_RIGHT == 0 {
-                rot = (rot - ROTATION_SPEED).rem_euclid(2.0 * std::f64::consts::PI);
+            if key_states[3].is_down() {
+                buttons |= InputFlags::RIGHT;
             }
-            // turn right
-            if input & INPUT_LEFT == 0 && input & INPUT_RIGHT != 0 {
-                rot = (rot + ROTATION_SPEED).rem_euclid(2.0 * std::f64::consts::PI);
+            // dashing is a single-frame action, only the press itself counts
+            if key_states[4].just_pressed() {
+                buttons |= InputFlags::DASH;
+            }
+            if self.turbo {
+                buttons = turbo_buttons(frame, slot);
             }
 
-            // limit speed
-            let magnitude = (vel_x * vel_x + vel_y * vel_y).sqrt();
-            if magnitude > MAX_SPEED {
-                vel_x = (vel_x * MAX_SPEED) / magnitude;
-                vel_y = (vel_y * MAX_SPEED) / magnitude;
+            // the analog stick always belongs to the first local player
+            let mut input = Input {
+                buttons,
+                ..Default::default()
+            };
+            if slot == 0 {
+                input.stick_x = quantize_axis(self.stick_axes[0]);
+                input.stick_y = quantize_axis(self.stick_axes[1]);
             }
 
-            // compute new position
-            let mut x = old_x + vel_x;
-            let mut y = old_y + vel_y;
+            bytes.extend_from_slice(&input::encode(input));
+        }
+        bytes
+    }
 
-            //constrain boxes to canvas borders
-            x = x.max(0.0);
-            x = x.min(WINDOW_WIDTH as f64);
-            y = y.max(0.0);
-            y = y.min(WINDOW_HEIGHT as f64);
+    /// Releases all held buttons, e.g. when the keyboard is taken over by the chat.
+    pub fn release_all_keys(&mut self) {
+        for key_states in self.key_states.iter_mut() {
+            for state in key_states.iter_mut() {
+                state.release();
+            }
+        }
+    }
 
-            self.game_state.positions[i] = (x, y);
-            self.game_state.velocities[i] = (vel_x, vel_y);
-            self.game_state.rotations[i] = rot;
+    /// Consumes the button edges sampled by `local_input`. Only call this once the input was accepted by the session,
+    /// or handed to something that latches the presses until then, like the simulation thread. Otherwise a press
+    /// during a skipped frame would be lost.
+    pub fn clear_input_edges(&mut self) {
+        for (slot, key_states) in self.key_states.iter().enumerate() {
+            if self.thrust_toggle[slot] && key_states[0].just_pressed() {
+                self.thrust_on[slot] = !self.thrust_on[slot];
+            }
+        }
+        for key_states in self.key_states.iter_mut() {
+            for state in key_states.iter_mut() {
+                state.clear_edges();
+            }
         }
+    }
+}
 
-        // TODO: inefficient to serialize the gamestate here just for the checksum
-        // remember checksum to render it later
-        let buffer = bincode::serialize(&self.game_state).unwrap();
-        let checksum = fletcher16(&buffer) as u64;
-        self.last_checksum = (self.game_state.frame, checksum);
-        if self.game_state.frame % CHECKSUM_PERIOD == 0 {
-            self.periodic_checksum = (self.game_state.frame, checksum);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxgame_sim::MAX_INLINE_PLAYERS;
+    use proptest::collection::vec;
+    use proptest::num::f64::ANY;
+    use proptest::prelude::*;
+
+    fn game_states() -> impl Strategy<Value = BoxGameState> {
+        (0usize..8).prop_flat_map(|players| {
+            (
+                any::<i32>(),
+                vec((ANY, ANY), players),
+                vec((ANY, ANY), players),
+                vec(ANY, players),
+            )
+                .prop_map(|(frame, positions, velocities, rotations)| BoxGameState {
+                    frame,
+                    positions: positions.into(),
+                    velocities: velocities.into(),
+                    rotations: rotations.into(),
+                })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn keyframe_round_trip_preserves_checksum(state in game_states()) {
+            let mut game = Simulation::new(1, 1);
+            let buffer = versioned::encode(&state).unwrap();
+            game.load_keyframe(&buffer).unwrap();
+            prop_assert_eq!(game.save_keyframe().unwrap(), buffer.clone());
+            prop_assert_eq!(game.last_checksum(), (state.frame, state.checksum()));
         }
     }
 
-    pub fn render(&mut self, gl: &mut GlGraphics, freetype: &Library, args: &RenderArgs) {
-        use graphics::*;
+    /// Runs `frames` frames of seeded scripted inputs, with the peers in `disconnected` sending no input.
+    fn run_script(
+        seed: u32,
+        num_peers: usize,
+        local_players: usize,
+        frames: Frame,
+        disconnected: &[usize],
+    ) -> Vec<u8> {
+        let mut game = Simulation::new(num_peers, local_players);
+        for frame in 0..frames {
+            let inputs = (0..num_peers)
+                .map(|peer| {
+                    if disconnected.contains(&peer) {
+                        return None;
+                    }
+                    let mut bytes = Vec::new();
+                    for slot in 0..local_players {
+                        let input = scripted_input(seed, frame, peer * local_players + slot);
+                        bytes.extend_from_slice(&input::encode(input));
+                    }
+                    Some(bytes)
+                })
+                .collect();
+            game.simulate_frame(inputs).unwrap();
+        }
+        game.save_keyframe().unwrap()
+    }
 
-        let mut face = freetype.new_face(&self.font, 0).unwrap();
-        face.set_pixel_sizes(0, 40).unwrap();
-        let checksum_string = format!(
-            "Frame {}: Checksum {}",
-            self.last_checksum.0, self.last_checksum.1
-        );
-        let checksum_glyphs = glyphs(&mut face, &checksum_string);
-        let periodic_string = format!(
-            "Frame {}: Checksum {}",
-            self.periodic_checksum.0, self.periodic_checksum.1
-        );
-        let periodic_glyphs = glyphs(&mut face, &periodic_string);
-
-        gl.draw(args.viewport(), |c, gl| {
-            // Clear the screen.
-            clear(BLACK, gl);
-            render_text(&checksum_glyphs, &c.trans(0.0, 40.0), gl);
-            render_text(&periodic_glyphs, &c.trans(0.0, 80.0), gl);
-
-            // draw the player rectangles
-            for i in 0..NUM_PLAYERS {
-                let square = rectangle::square(0.0, 0.0, PLAYER_SIZE);
-                let (x, y) = self.game_state.positions[i];
-                let rotation = self.game_state.rotations[i];
-
-                let transform = c
-                    .transform
-                    .trans(x, y)
-                    .rot_rad(rotation)
-                    .trans(-PLAYER_SIZE / 2.0, -PLAYER_SIZE / 2.0);
-                rectangle(PLAYER_COLORS[i], square, transform, gl);
-            }
+    /// Compares the state with `tests/golden/<name>.bin`. Run the tests with `UPDATE_GOLDEN=1` to write the golden
+    /// files, for a new test or after an intended change to the simulation.
+    fn assert_golden(name: &str, state: &[u8]) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.bin", name));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, state).unwrap();
+            println!("Wrote golden state {}, commit it.", path.display());
+            return;
+        }
+        let golden = std::fs::read(&path).unwrap_or_else(|e| {
+            panic!(
+                "cannot read {}: {}, run the tests with UPDATE_GOLDEN=1 to write it",
+                path.display(),
+                e
+            )
         });
+        assert!(
+            golden == state,
+            "the state differs from {}, rerun with UPDATE_GOLDEN=1 if the change is intended",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn golden_two_players() {
+        assert_golden("two_players", &run_script(1, 2, 1, 600, &[]));
     }
 
-    #[allow(dead_code)]
-    pub fn local_input(&self) -> Vec<u8> {
-        // Create a set of pressed Keys.
-        let mut input: u8 = 0;
+    #[test]
+    fn golden_two_local_players() {
+        assert_golden("two_local_players", &run_script(2, 2, 2, 600, &[]));
+    }
+
+    #[test]
+    fn golden_disconnected_peer() {
+        assert_golden("disconnected_peer", &run_script(3, 2, 1, 600, &[1]));
+    }
 
-        // ugly, but it works...
-        if self.key_states[0] {
-            input |= INPUT_UP;
+    /// A cell as GGRS hands it out for saving `frame`, cells only take states of the frame they were handed out for.
+    fn cell_for(frame: Frame) -> GameStateCell {
+        let mut sess = ggrs::start_synctest_session(1, 1, 0).unwrap();
+        sess.add_player(ggrs::PlayerType::Local, 0).unwrap();
+        sess.start_session().unwrap();
+        for _ in 0..frame {
+            sess.advance_frame(0, &[0]).unwrap();
         }
-        if self.key_states[1] {
-            input |= INPUT_LEFT;
+        match sess.advance_frame(0, &[0]).unwrap().remove(0) {
+            GGRSRequest::SaveGameState { cell, .. } => cell,
+            _ => unreachable!("GGRS saves before it advances"),
         }
-        if self.key_states[2] {
-            input |= INPUT_DOWN;
+    }
+
+    #[test]
+    fn delta_saves_load_every_frame() {
+        let mut game = Simulation::new(2, 1);
+        game.delta_saves = true;
+        let mut saves = Vec::new();
+        for frame in 0..3 * DELTA_BASE_PERIOD {
+            let inputs = (0..2)
+                .map(|player| Some(input::encode(scripted_input(7, frame, player)).to_vec()))
+                .collect();
+            game.simulate_frame(inputs).unwrap();
+            let cell = cell_for(game.current_frame());
+            game.save_game_state(cell.clone(), game.current_frame())
+                .unwrap();
+            saves.push((cell, game.last_checksum()));
+        }
+        // a rollback only goes back as far as the prediction window
+        for (cell, checksum) in saves.iter().rev().take(MAX_PREDICTION_FRAMES as usize) {
+            game.load_game_state(cell.clone()).unwrap();
+            assert_eq!(game.game_state.checksum(), checksum.1);
         }
-        if self.key_states[3] {
-            input |= INPUT_RIGHT;
+    }
+
+    #[test]
+    fn untaken_hits_are_dropped() {
+        let mut game = Simulation::new(2, 1);
+        let hit = |frame| HitEvent {
+            frame,
+            players: (0, 1),
+        };
+        game.hit_events = vec![hit(1), hit(2)];
+        // the boxes stand still, so they start touching no one else
+        let inputs = || vec![Some(input::encode(Input::default()).to_vec()); 2];
+        for _ in 0..MAX_PREDICTION_FRAMES + CONFIRMED_HIT_FRAMES + 1 {
+            game.simulate_frame(inputs()).unwrap();
         }
+        assert_eq!(game.hit_events, vec![hit(2)]);
+        game.simulate_frame(inputs()).unwrap();
+        assert!(game.hit_events.is_empty());
 
-        bincode::serialize(&input).unwrap()
+        game.hit_events = vec![
+            hit(game.current_frame() - MAX_PREDICTION_FRAMES),
+            hit(game.current_frame()),
+        ];
+        assert_eq!(
+            game.take_confirmed_hits(),
+            vec![hit(game.current_frame() - MAX_PREDICTION_FRAMES)]
+        );
+        assert_eq!(game.hit_events, vec![hit(game.current_frame())]);
+        assert!(game.take_confirmed_hits().is_empty());
     }
-}
 
-// BoxGameState holds all relevant information about the game state
-#[derive(Serialize, Deserialize)]
-struct BoxGameState {
-    pub frame: i32,
-    pub positions: Vec<(f64, f64)>,
-    pub velocities: Vec<(f64, f64)>,
-    pub rotations: Vec<f64>,
-}
+    #[test]
+    fn saving_another_frame_is_an_error() {
+        let mut game = Simulation::new(2, 1);
+        assert!(matches!(
+            game.save_game_state(cell_for(1), 1),
+            Err(GameError::UnexpectedSaveFrame {
+                requested: 1,
+                current: 0
+            })
+        ));
+    }
 
-impl BoxGameState {
-    pub fn new() -> Self {
-        let mut positions = Vec::new();
-        let mut velocities = Vec::new();
-        let mut rotations = Vec::new();
-        for i in 0..NUM_PLAYERS as i32 {
-            let x = WINDOW_WIDTH as i32 / 2 + (2 * i - 1) * (WINDOW_WIDTH as i32 / 4);
-            let y = WINDOW_HEIGHT as i32 / 2;
-            positions.push((x as f64, y as f64));
-            velocities.push((0.0, 0.0));
-            rotations.push(0.0);
+    #[test]
+    fn snapshot_saves_load() {
+        // the second game has too many boxes for a snapshot and is saved in full
+        for &num_peers in &[2, MAX_INLINE_PLAYERS + 1] {
+            let mut game = Simulation::new(num_peers, 1);
+            let inputs = (0..num_peers)
+                .map(|player| Some(input::encode(scripted_input(3, 0, player)).to_vec()))
+                .collect();
+            game.simulate_frame(inputs).unwrap();
+            let cell = cell_for(game.current_frame());
+            game.save_game_state(cell.clone(), game.current_frame())
+                .unwrap();
+            let checksum = game.game_state.checksum();
+            game.restart();
+            game.load_game_state(cell).unwrap();
+            assert_eq!(game.game_state.checksum(), checksum);
         }
+    }
 
-        Self {
-            frame: 0,
-            positions,
-            velocities,
-            rotations,
+    #[test]
+    fn state_copies_load() {
+        // the second game has too many boxes for a snapshot and is copied as a keyframe
+        for &num_peers in &[2, MAX_INLINE_PLAYERS + 1] {
+            let mut game = Simulation::new(num_peers, 1);
+            let inputs = (0..num_peers)
+                .map(|player| Some(input::encode(scripted_input(4, 0, player)).to_vec()))
+                .collect();
+            game.simulate_frame(inputs).unwrap();
+            let copy = game.copy_state().unwrap();
+            assert_eq!(
+                matches!(copy, StateCopy::Snapshot(_)),
+                num_peers <= MAX_INLINE_PLAYERS
+            );
+            let mut view = Simulation::new(num_peers, 1);
+            view.load_state_copy(&copy).unwrap();
+            assert_eq!(view.last_checksum(), game.last_checksum());
         }
     }
+
+    #[test]
+    fn rolled_back_inputs_are_reused() {
+        let mut game = Simulation::new(2, 1);
+        game.enable_frame_log();
+        let cell = cell_for(game.current_frame());
+        game.save_game_state(cell.clone(), game.current_frame())
+            .unwrap();
+        for frame in 0..4 {
+            let inputs = (0..2)
+                .map(|player| Some(input::encode(scripted_input(5, frame, player)).to_vec()))
+                .collect();
+            game.simulate_frame(inputs).unwrap();
+        }
+        let mispredicted = game.frame_log.as_ref().unwrap()[0].inputs[0]
+            .as_ref()
+            .unwrap()
+            .as_ptr();
+        game.load_game_state(cell).unwrap();
+        assert!(game.frame_log.as_ref().unwrap().is_empty());
+        assert_eq!(game.input_pool.lists.len(), 4);
+
+        let recycled: Vec<_> = game
+            .input_pool
+            .buffers
+            .iter()
+            .map(|buffer| buffer.as_ptr())
+            .collect();
+        assert!(recycled.contains(&mispredicted));
+        let resimulated = game
+            .input_pool
+            .copy(vec![None, Some(&[1, 2, 3][..])].into_iter());
+        assert_eq!(resimulated, vec![None, Some(vec![1, 2, 3])]);
+        assert!(recycled.contains(&resimulated[1].as_ref().unwrap().as_ptr()));
+        assert_eq!(game.input_pool.lists.len(), 3);
+    }
 }
@@ -0,0 +1,204 @@
+use crate::box_game::{input, Input, Simulation, INPUT_SIZE};
+use boxgame_sim::{ARENA_HEIGHT, ARENA_WIDTH, PLAYER_SIZE};
+
+/// The camera closes this share of the distance to its target every frame, so cuts between presets are smooth.
+const SMOOTHING: f64 = 0.1;
+/// Zoom of the presets that follow a single box.
+const FOLLOW_ZOOM: f64 = 2.0;
+/// The director zooms in on the action at most this far.
+const MAX_DIRECTOR_ZOOM: f64 = 2.0;
+/// Boxes slower than this, in pixels per frame, are not part of the action.
+const ACTION_SPEED: f64 = 1.0;
+/// Room the director leaves around the boxes it frames.
+const DIRECTOR_MARGIN: f64 = 2.0 * PLAYER_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// the whole arena, as the players see it
+    Arena,
+    /// centered on the first box of a GGRS player handle
+    Follow(usize),
+    /// frames the boxes that are moving, or all of them when none is
+    Director,
+}
+
+/// What part of the arena is drawn. The window has the size of the arena, so at zoom 1 the whole arena is visible.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub preset: Preset,
+    /// the arena point in the middle of the window
+    pub center: (f64, f64),
+    pub zoom: f64,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            preset: Preset::Arena,
+            center: (ARENA_WIDTH as f64 / 2.0, ARENA_HEIGHT as f64 / 2.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Moves the camera one step towards where its preset wants it for the simulation's current state. Call once
+    /// per rendered frame.
+    pub fn update(&mut self, simulation: &Simulation) {
+        let (center, zoom) = self.target(simulation);
+        self.zoom += (zoom - self.zoom) * SMOOTHING;
+        self.center.0 += (center.0 - self.center.0) * SMOOTHING;
+        self.center.1 += (center.1 - self.center.1) * SMOOTHING;
+        self.center = clamp_center(self.center, self.zoom);
+    }
+
+    fn target(&self, simulation: &Simulation) -> ((f64, f64), f64) {
+        let state = &simulation.game_state;
+        let arena = Self::default();
+        match self.preset {
+            Preset::Arena => (arena.center, arena.zoom),
+            Preset::Follow(handle) => {
+                match state.positions.get(handle * simulation.local_players) {
+                    Some(&position) => (position, FOLLOW_ZOOM),
+                    None => (arena.center, arena.zoom),
+                }
+            }
+            Preset::Director => {
+                let moving: Vec<(f64, f64)> = (0..state.positions.len())
+                    .filter(|&i| {
+                        let (vx, vy) = state.velocities[i];
+                        vx.hypot(vy) >= ACTION_SPEED
+                    })
+                    .map(|i| state.positions[i])
+                    .collect();
+                let framed = match moving.is_empty() {
+                    true => state.positions.iter().copied().collect(),
+                    false => moving,
+                };
+                frame_points(&framed).unwrap_or((arena.center, arena.zoom))
+            }
+        }
+    }
+}
+
+/// A small extra view following one player, with the inputs of the boxes it controls. Spectators show one for every
+/// player next to the main view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inset {
+    pub handle: usize,
+    pub camera: Camera,
+    /// the inputs of the player's boxes in the shown frame, empty when unknown
+    pub inputs: Vec<Input>,
+}
+
+impl Inset {
+    pub fn new(handle: usize) -> Self {
+        Self {
+            handle,
+            camera: Camera {
+                preset: Preset::Follow(handle),
+                ..Camera::default()
+            },
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Follows the player in the simulation's current state, `frame_inputs` are the inputs that state was simulated
+    /// with, see `Simulation::last_inputs`. Call once per rendered frame.
+    pub fn update(&mut self, simulation: &Simulation, frame_inputs: &[Option<Vec<u8>>]) {
+        self.camera.update(simulation);
+        self.inputs.clear();
+        if let Some(Some(bytes)) = frame_inputs.get(self.handle) {
+            self.inputs.extend(
+                bytes
+                    .chunks(INPUT_SIZE)
+                    .take(simulation.local_players)
+                    .map(input::decode),
+            );
+        }
+    }
+}
+
+/// The center and zoom that fit all `points` with some margin, `None` without points.
+fn frame_points(points: &[(f64, f64)]) -> Option<((f64, f64), f64)> {
+    let (first, rest) = points.split_first()?;
+    let (mut min, mut max) = (*first, *first);
+    for &(x, y) in rest {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let width = max.0 - min.0 + 2.0 * DIRECTOR_MARGIN;
+    let height = max.1 - min.1 + 2.0 * DIRECTOR_MARGIN;
+    let zoom = (ARENA_WIDTH as f64 / width)
+        .min(ARENA_HEIGHT as f64 / height)
+        .clamp(1.0, MAX_DIRECTOR_ZOOM);
+    Some((((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0), zoom))
+}
+
+/// Keeps the view inside the arena, so zooming in never shows what is beyond its borders.
+fn clamp_center((x, y): (f64, f64), zoom: f64) -> (f64, f64) {
+    let half_width = ARENA_WIDTH as f64 / 2.0 / zoom;
+    let half_height = ARENA_HEIGHT as f64 / 2.0 / zoom;
+    (
+        x.max(half_width).min(ARENA_WIDTH as f64 - half_width),
+        y.max(half_height).min(ARENA_HEIGHT as f64 - half_height),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settle(camera: &mut Camera, simulation: &Simulation) {
+        for _ in 0..200 {
+            camera.update(simulation);
+        }
+    }
+
+    #[test]
+    fn presets_settle_on_their_targets() {
+        let mut simulation = Simulation::new(2, 1);
+        simulation.game_state.positions[0] = (100.0, 100.0);
+        simulation.game_state.positions[1] = (500.0, 200.0);
+        let mut camera = Camera {
+            preset: Preset::Follow(1),
+            ..Camera::default()
+        };
+        settle(&mut camera, &simulation);
+        assert!((camera.zoom - FOLLOW_ZOOM).abs() < 1e-3);
+        // the box is close to the border, the view stops at it
+        assert!((camera.center.0 - 450.0).abs() < 1e-3);
+        assert!((camera.center.1 - 200.0).abs() < 1e-3);
+
+        // only the moving box is framed
+        simulation.game_state.velocities[0] = (3.0, 0.0);
+        camera.preset = Preset::Director;
+        settle(&mut camera, &simulation);
+        assert!((camera.zoom - MAX_DIRECTOR_ZOOM).abs() < 1e-3);
+        assert!((camera.center.0 - 150.0).abs() < 1e-3);
+
+        camera.preset = Preset::Arena;
+        settle(&mut camera, &simulation);
+        let arena = Camera::default();
+        assert!((camera.zoom - arena.zoom).abs() < 1e-3);
+        assert!((camera.center.1 - arena.center.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn insets_show_the_inputs_of_their_player() {
+        let simulation = Simulation::new(2, 2);
+        let mut inset = Inset::new(1);
+        let dash = Input {
+            buttons: crate::box_game::InputFlags::DASH,
+            ..Input::default()
+        };
+        let second: Vec<u8> = [Input::default(), dash]
+            .iter()
+            .flat_map(|&input| input::encode(input).to_vec())
+            .collect();
+        inset.update(&simulation, &[None, Some(second)]);
+        assert_eq!(inset.inputs, vec![Input::default(), dash]);
+        inset.update(&simulation, &[Some(vec![0; 6]), None]);
+        assert!(inset.inputs.is_empty());
+    }
+}
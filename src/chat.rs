@@ -0,0 +1,55 @@
+/// Chat messages older than this are dropped from the history.
+const MAX_HISTORY: usize = 8;
+const MAX_MESSAGE_LEN: usize = 200;
+
+/// The chat text entry: opened with Enter, it swallows typed characters until Enter is pressed again.
+#[derive(Default)]
+pub struct Chat {
+    pub open: bool,
+    pub input: String,
+    pub history: Vec<String>,
+}
+
+impl Chat {
+    /// Toggles the text entry. Returns the typed message if the entry was closed with text in it.
+    pub fn toggle(&mut self) -> Option<String> {
+        self.open = !self.open;
+        if self.open || self.input.trim().is_empty() {
+            self.input.clear();
+            return None;
+        }
+        Some(std::mem::take(&mut self.input))
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        if self.open {
+            for ch in text.chars().filter(|ch| !ch.is_control()) {
+                if self.input.len() < MAX_MESSAGE_LEN {
+                    self.input.push(ch);
+                }
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.open {
+            self.input.pop();
+        }
+    }
+
+    pub fn push_history(&mut self, message: String) {
+        self.history.push(message);
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    /// The lines to render: the history, followed by the text entry if it is open.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = self.history.clone();
+        if self.open {
+            lines.push(format!("> {}_", self.input));
+        }
+        lines
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code after a shutdown by Ctrl-C, 128 + SIGINT like shells report it.
+const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Removes `flag` from the arguments and returns whether it was present.
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let present = args.iter().any(|arg| arg == flag);
+    args.retain(|arg| arg != flag);
+    present
+}
+
+/// Removes `flag` and the value following it from the arguments and returns the value.
+pub fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.remove(index);
+    if index < args.len() {
+        Some(args.remove(index))
+    } else {
+        None
+    }
+}
+
+/// Makes Ctrl-C (and SIGTERM) ask the binary to shut down instead of killing it mid-frame: `interrupted` turns
+/// true, and the main loop is expected to end, flush replays and metrics and tell its peers. A second Ctrl-C exits
+/// right away, in case the shutdown hangs.
+pub fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        tracing::info!("Interrupted, shutting down. Press Ctrl-C again to exit right away.");
+    });
+    if let Err(e) = result {
+        tracing::warn!(
+            "Cannot handle Ctrl-C, it will exit without cleaning up: {}",
+            e
+        );
+    }
+}
+
+/// Whether Ctrl-C was pressed since `handle_interrupts`.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Top-level error handler of the binaries: prints the error along with its causes and exits with status 1, or with
+/// 130 if the binary was interrupted. `run` has returned by then, so the session, the window and any replay being
+/// written were dropped and closed.
+pub fn exit_on_error(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+    if interrupted() {
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+}
@@ -0,0 +1,414 @@
+use anyhow::{bail, Context};
+use boxgame_net::session::SessionBuilder;
+use boxgame_net::side_channel;
+use ggrs::PlayerHandle;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Read from the working directory when no `--config <file>` is given, it is fine for it to be missing.
+pub const DEFAULT_CONFIG_FILE: &str = "box_game.toml";
+/// Prefix of the environment variables, e.g. `BOXGAME_FRAME_DELAY=3`.
+const ENV_PREFIX: &str = "BOXGAME_";
+/// A p2p session is always between two peers.
+const NUM_PLAYERS: usize = 2;
+/// There are two keyboard layouts, WASD and the arrow keys.
+const MAX_LOCAL_PLAYERS: usize = 2;
+/// Larger delays make the game unplayable, so they are most likely typos.
+const MAX_FRAME_DELAY: u32 = 8;
+
+/// The settings of a p2p session, validated and ready to create the session with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    pub port: u16,
+    pub local_handle: PlayerHandle,
+    pub remote_addr: SocketAddr,
+    pub spectator_addr: Option<SocketAddr>,
+    pub local_players: usize,
+    pub frame_delay: u32,
+    pub name: Option<String>,
+    /// the ports of the side channels, by default `side_channel::PORT_OFFSET` above the GGRS ports
+    pub side_port: u16,
+    pub remote_side_port: u16,
+    pub spectator_side_port: Option<u16>,
+}
+
+impl SessionConfig {
+    /// The session these settings describe, started with `start_p2p(self.port)`.
+    pub fn session_builder(&self) -> SessionBuilder {
+        let builder = SessionBuilder::new(
+            NUM_PLAYERS,
+            crate::box_game::INPUT_SIZE * self.local_players,
+        )
+        .local_player(self.local_handle)
+        .remote_player(1 - self.local_handle, self.remote_addr)
+        .frame_delay(self.frame_delay);
+        match self.spectator_addr {
+            Some(addr) => builder.spectator(addr),
+            None => builder,
+        }
+    }
+
+    /// The address of the remote peer's side channel.
+    pub fn remote_side_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.remote_addr.ip(), self.remote_side_port)
+    }
+
+    /// The address of the spectator's side channel, if there is a spectator.
+    pub fn spectator_side_addr(&self) -> Option<SocketAddr> {
+        let addr = self.spectator_addr?;
+        Some(SocketAddr::new(addr.ip(), self.spectator_side_port?))
+    }
+}
+
+/// One source of settings. Layers are merged over the defaults, from the config file and the environment to the
+/// command line, every layer overriding the values set by the ones before it.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    pub port: Option<u16>,
+    pub local_handle: Option<PlayerHandle>,
+    pub remote_addr: Option<SocketAddr>,
+    pub spectator_addr: Option<SocketAddr>,
+    pub local_players: Option<usize>,
+    pub frame_delay: Option<u32>,
+    pub name: Option<String>,
+    pub side_port: Option<u16>,
+    pub remote_side_port: Option<u16>,
+    pub spectator_side_port: Option<u16>,
+}
+
+impl ConfigLayer {
+    pub fn defaults() -> Self {
+        Self {
+            port: Some(7000),
+            local_handle: Some(0),
+            local_players: Some(1),
+            frame_delay: Some(2),
+            ..Self::default()
+        }
+    }
+
+    /// Parses a config file, e.g. `remote_addr = "127.0.0.1:7001"`. The keys are the field names.
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Reads `BOXGAME_` followed by the upper case field names, e.g. `BOXGAME_REMOTE_ADDR`. `var` looks up a
+    /// variable, `std::env::var(name).ok()` outside of tests.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        fn parse<T: FromStr>(
+            var: &impl Fn(&str) -> Option<String>,
+            field: &str,
+        ) -> anyhow::Result<Option<T>>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            let name = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+            match var(&name) {
+                Some(value) => Ok(Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid {}={}", name, value))?,
+                )),
+                None => Ok(None),
+            }
+        }
+        Ok(Self {
+            port: parse(&var, "port")?,
+            local_handle: parse(&var, "local_handle")?,
+            remote_addr: parse(&var, "remote_addr")?,
+            spectator_addr: parse(&var, "spectator_addr")?,
+            local_players: parse(&var, "local_players")?,
+            frame_delay: parse(&var, "frame_delay")?,
+            name: parse(&var, "name")?,
+            side_port: parse(&var, "side_port")?,
+            remote_side_port: parse(&var, "remote_side_port")?,
+            spectator_side_port: parse(&var, "spectator_side_port")?,
+        })
+    }
+
+    /// Takes the session flags and the positional `<port> <handle> <remote> [spectator]` from the arguments.
+    /// The other flags of the binary have to be taken before, anything left over is an error.
+    pub fn from_args(args: &mut Vec<String>) -> anyhow::Result<Self> {
+        let mut layer = Self {
+            name: crate::cli::take_flag_value(args, "--name"),
+            ..Self::default()
+        };
+        // with --two-local, this peer controls two boxes (WASD and arrow keys). Both peers have to pass it.
+        if crate::cli::take_flag(args, "--two-local") {
+            layer.local_players = Some(2);
+        }
+        if let Some(delay) = crate::cli::take_flag_value(args, "--frame-delay") {
+            layer.frame_delay = Some(delay.parse().context("invalid --frame-delay")?);
+        }
+        // with --side-port, --remote-side-port and --spectator-side-port, the side channels run on other ports than
+        // `side_channel::PORT_OFFSET` above the GGRS ports. Both sides have to agree on them.
+        for (flag, field) in [
+            ("--side-port", &mut layer.side_port),
+            ("--remote-side-port", &mut layer.remote_side_port),
+            ("--spectator-side-port", &mut layer.spectator_side_port),
+        ] {
+            if let Some(port) = crate::cli::take_flag_value(args, flag) {
+                *field = Some(port.parse().with_context(|| format!("invalid {}", flag))?);
+            }
+        }
+        // with --join <secret>, e.g. from a Discord invite, the host's address and the other player handle are taken
+        // from the host's join secret, see `presence::JoinSecret`
+        if let Some(secret) = crate::cli::take_flag_value(args, "--join") {
+            let secret = crate::presence::JoinSecret::parse(&secret)?;
+            if secret.handle >= NUM_PLAYERS {
+                bail!(
+                    "the join secret has the invalid player handle {}",
+                    secret.handle
+                );
+            }
+            layer.remote_addr = Some(secret.addr);
+            layer.local_handle = Some(1 - secret.handle);
+        }
+        let mut positional = args.drain(1..);
+        if let Some(port) = positional.next() {
+            layer.port = Some(
+                port.parse()
+                    .with_context(|| format!("invalid port {}", port))?,
+            );
+        }
+        if let Some(handle) = positional.next() {
+            layer.local_handle = Some(
+                handle
+                    .parse()
+                    .with_context(|| format!("invalid player handle {}", handle))?,
+            );
+        }
+        if let Some(addr) = positional.next() {
+            layer.remote_addr = Some(
+                addr.parse()
+                    .with_context(|| format!("invalid remote address {}", addr))?,
+            );
+        }
+        if let Some(addr) = positional.next() {
+            layer.spectator_addr = Some(
+                addr.parse()
+                    .with_context(|| format!("invalid spectator address {}", addr))?,
+            );
+        }
+        if let Some(unexpected) = positional.next() {
+            bail!("unexpected argument {}", unexpected);
+        }
+        Ok(layer)
+    }
+
+    /// The values of `self`, where set, over those of `base`.
+    pub fn over(self, base: Self) -> Self {
+        Self {
+            port: self.port.or(base.port),
+            local_handle: self.local_handle.or(base.local_handle),
+            remote_addr: self.remote_addr.or(base.remote_addr),
+            spectator_addr: self.spectator_addr.or(base.spectator_addr),
+            local_players: self.local_players.or(base.local_players),
+            frame_delay: self.frame_delay.or(base.frame_delay),
+            name: self.name.or(base.name),
+            side_port: self.side_port.or(base.side_port),
+            remote_side_port: self.remote_side_port.or(base.remote_side_port),
+            spectator_side_port: self.spectator_side_port.or(base.spectator_side_port),
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Merges the layers over the defaults, later ones taking precedence, and checks the result.
+    pub fn resolve(layers: Vec<ConfigLayer>) -> anyhow::Result<Self> {
+        let merged = layers
+            .into_iter()
+            .fold(ConfigLayer::defaults(), |base, layer| layer.over(base));
+        // everything but the addresses has a default
+        let port = merged.port.unwrap_or_default();
+        let remote_addr = match merged.remote_addr {
+            Some(addr) => addr,
+            None => bail!("no remote address, pass it after the player handle, as remote_addr in the config file or as BOXGAME_REMOTE_ADDR"),
+        };
+        let spectator_side_port = merged.spectator_side_port;
+        let config = Self {
+            port,
+            local_handle: merged.local_handle.unwrap_or_default(),
+            remote_addr,
+            spectator_addr: merged.spectator_addr,
+            local_players: merged.local_players.unwrap_or_default(),
+            frame_delay: merged.frame_delay.unwrap_or_default(),
+            name: merged.name,
+            side_port: merged
+                .side_port
+                .unwrap_or_else(|| side_channel::default_port(port)),
+            remote_side_port: merged
+                .remote_side_port
+                .unwrap_or_else(|| side_channel::default_port(remote_addr.port())),
+            spectator_side_port: merged.spectator_addr.map(|addr| {
+                spectator_side_port.unwrap_or_else(|| side_channel::default_port(addr.port()))
+            }),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.port == 0 {
+            bail!("port 0 is not allowed, the other peer has to know the port to send to");
+        }
+        if self.local_handle >= NUM_PLAYERS {
+            bail!(
+                "player handle {} does not exist, it has to be 0 or 1",
+                self.local_handle
+            );
+        }
+        if self.remote_addr.ip().is_loopback() && self.remote_addr.port() == self.port {
+            bail!(
+                "the remote address {} is this peer's own port",
+                self.remote_addr
+            );
+        }
+        // every socket on this machine needs a port of its own, other peers on it count as well
+        let mut ports = vec![("port", self.port), ("side port", self.side_port)];
+        if self.remote_addr.ip().is_loopback() {
+            ports.push(("remote port", self.remote_addr.port()));
+            ports.push(("remote side port", self.remote_side_port));
+        }
+        if let Some(addr) = self.spectator_addr.filter(|addr| addr.ip().is_loopback()) {
+            ports.push(("spectator port", addr.port()));
+            ports.extend(
+                self.spectator_side_port
+                    .map(|port| ("spectator side port", port)),
+            );
+        }
+        for (i, &(name, port)) in ports.iter().enumerate() {
+            if let Some(&(other, _)) = ports[..i].iter().find(|&&(_, used)| used == port) {
+                bail!("the {} {} is also the {}", name, port, other);
+            }
+        }
+        if self.spectator_addr == Some(self.remote_addr) {
+            bail!(
+                "the spectator address {} is the remote player's address",
+                self.remote_addr
+            );
+        }
+        if self.local_players == 0 || self.local_players > MAX_LOCAL_PLAYERS {
+            bail!(
+                "{} local players, there can be 1 or {}",
+                self.local_players,
+                MAX_LOCAL_PLAYERS
+            );
+        }
+        if self.frame_delay > MAX_FRAME_DELAY {
+            bail!(
+                "frame delay {} is more than the maximum of {}",
+                self.frame_delay,
+                MAX_FRAME_DELAY
+            );
+        }
+        if self.name.as_deref() == Some("") {
+            bail!("the player name is empty");
+        }
+        Ok(())
+    }
+}
+
+/// Builds the session config from the defaults, the config file (`--config <file>` or `box_game.toml`), the
+/// `BOXGAME_` environment variables and the command line, in increasing precedence.
+pub fn load(args: &mut Vec<String>) -> anyhow::Result<SessionConfig> {
+    let file = match crate::cli::take_flag_value(args, "--config") {
+        Some(path) => ConfigLayer::load(Path::new(&path))?,
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+            ConfigLayer::load(Path::new(DEFAULT_CONFIG_FILE))?
+        }
+        None => ConfigLayer::default(),
+    };
+    let cli = ConfigLayer::from_args(args)?;
+    let env = ConfigLayer::from_env(|name| std::env::var(name).ok())?;
+    SessionConfig::resolve(vec![file, env, cli])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let file = ConfigLayer::from_toml(
+            "port = 7100\nframe_delay = 4\nremote_addr = \"127.0.0.1:7101\"",
+        )
+        .unwrap();
+        let env = ConfigLayer::from_env(|name| match name {
+            "BOXGAME_FRAME_DELAY" => Some("3".to_string()),
+            "BOXGAME_NAME" => Some("env".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let cli = ConfigLayer::from_args(&mut args("box_game_p2p 7200 1 --name cli")).unwrap();
+        let config = SessionConfig::resolve(vec![file, env, cli]).unwrap();
+        assert_eq!(config.port, 7200);
+        assert_eq!(config.local_handle, 1);
+        assert_eq!(config.remote_addr, "127.0.0.1:7101".parse().unwrap());
+        assert_eq!(config.frame_delay, 3);
+        assert_eq!(config.name.as_deref(), Some("cli"));
+        assert_eq!(config.local_players, 1);
+        assert!(config.session_builder().validate().is_ok());
+    }
+
+    #[test]
+    fn side_ports_default_to_an_offset() {
+        let cli = ConfigLayer::from_args(&mut args("p2p 7000 0 127.0.0.1:7001 127.0.0.1:7002"));
+        let config = SessionConfig::resolve(vec![cli.unwrap()]).unwrap();
+        assert_eq!(config.side_port, 7100);
+        assert_eq!(config.remote_side_addr(), "127.0.0.1:7101".parse().unwrap());
+        assert_eq!(
+            config.spectator_side_addr(),
+            Some("127.0.0.1:7102".parse().unwrap())
+        );
+        let cli = ConfigLayer::from_args(&mut args(
+            "p2p 7000 0 127.0.0.1:7001 --side-port 8000 --remote-side-port 8001",
+        ));
+        let config = SessionConfig::resolve(vec![cli.unwrap()]).unwrap();
+        assert_eq!(config.side_port, 8000);
+        assert_eq!(config.remote_side_addr(), "127.0.0.1:8001".parse().unwrap());
+        assert_eq!(config.spectator_side_addr(), None);
+    }
+
+    #[test]
+    fn join_secrets_fill_in_the_host() {
+        let cli = ConfigLayer::from_args(&mut args("p2p 7001 --join boxgame1:0@127.0.0.1:7000"));
+        let config = SessionConfig::resolve(vec![cli.unwrap()]).unwrap();
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.local_handle, 1);
+        assert_eq!(config.remote_addr, "127.0.0.1:7000".parse().unwrap());
+        assert!(ConfigLayer::from_args(&mut args("p2p --join boxgame1:2@127.0.0.1:7000")).is_err());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let resolve = |line: &str| {
+            SessionConfig::resolve(vec![ConfigLayer::from_args(&mut args(line)).unwrap()])
+        };
+        assert!(resolve("p2p 7000 0 127.0.0.1:7001").is_ok());
+        assert!(resolve("p2p 7000 0").is_err());
+        assert!(resolve("p2p 7000 2 127.0.0.1:7001").is_err());
+        assert!(resolve("p2p 7000 0 127.0.0.1:7000").is_err());
+        assert!(resolve("p2p 0 0 127.0.0.1:7001").is_err());
+        assert!(resolve("p2p 7000 0 127.0.0.1:7001 127.0.0.1:7001").is_err());
+        assert!(resolve("p2p 7000 0 127.0.0.1:7001 --frame-delay 20").is_err());
+        // the side channel of this peer would take the remote peer's GGRS port
+        assert!(resolve("p2p 7000 0 127.0.0.1:7100").is_err());
+        assert!(resolve("p2p 7000 0 127.0.0.1:7001 --side-port 7001").is_err());
+        assert!(ConfigLayer::from_toml("frame_dleay = 2").is_err());
+        assert!(ConfigLayer::from_env(|_| Some("x".to_string())).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::box_game::{FrameInputs, FrameRecord};
+use boxgame_sim::{versioned, BoxGameState};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The inputs and checksums of this many frames end up in a crash bundle, ten seconds of play.
+const RECENT_FRAMES: usize = 600;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EVIDENCE: Mutex<Option<Evidence>> = Mutex::new(None);
+
+/// What the simulation last did, kept up to date once `install_hook` was called.
+#[derive(Default)]
+struct Evidence {
+    /// the state after the last simulated frame, encoded with `versioned`
+    state: Vec<u8>,
+    /// the last `RECENT_FRAMES` frames, without keyframes
+    frames: VecDeque<FrameRecord>,
+}
+
+impl Evidence {
+    fn record(&mut self, state: &BoxGameState, inputs: &FrameInputs, checksum: u64) {
+        // a state too large to encode leaves no state in the bundle, the frames are still worth having
+        if versioned::encode_into(state, &mut self.state).is_err() {
+            self.state.clear();
+        }
+        // rollbacks resimulate frames, their new results replace the mispredicted ones
+        while self
+            .frames
+            .back()
+            .is_some_and(|record| record.frame >= state.frame)
+        {
+            self.frames.pop_back();
+        }
+        // the oldest record is reused, so recording does not allocate once the history is full
+        let mut record = match self.frames.len() {
+            RECENT_FRAMES => self.frames.pop_front().unwrap(),
+            _ => FrameRecord {
+                frame: 0,
+                inputs: Vec::new(),
+                checksum: 0,
+                keyframe: None,
+                tuning: None,
+            },
+        };
+        record.frame = state.frame;
+        record.inputs.clone_from(inputs);
+        record.checksum = checksum;
+        self.frames.push_back(record);
+    }
+
+    /// Writes `panic.txt`, the last state as `state.bin` and the recent frames as `frames.json` into `dir`.
+    fn write(&self, dir: &Path, panic: &str) -> std::io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("panic.txt"), panic)?;
+        fs::write(dir.join("state.bin"), &self.state)?;
+        let mut frames = fs::File::create(dir.join("frames.json"))?;
+        serde_json::to_writer_pretty(&mut frames, &self.frames)?;
+        frames.flush()
+    }
+}
+
+fn evidence() -> MutexGuard<'static, Option<Evidence>> {
+    // a panic while recording poisons the lock, the evidence is still the best there is
+    EVIDENCE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes a panic write a crash bundle before the process aborts: the panic message with a backtrace, the last
+/// simulated state, and the inputs and checksums of the frames before it. Panics that only happen in one peer's
+/// timing, e.g. a failed `--double-simulate` check, cannot be reproduced later, so the bundle is all there is.
+/// From now on every simulated frame is recorded for it.
+pub fn install_hook() {
+    *evidence() = Some(Evidence::default());
+    ENABLED.store(true, Ordering::SeqCst);
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let panic = format!(
+            "thread '{}' {}\n\n{}",
+            std::thread::current().name().unwrap_or("<unnamed>"),
+            info,
+            std::backtrace::Backtrace::force_capture()
+        );
+        match write_bundle(&panic) {
+            Ok(dir) => eprintln!("Wrote crash diagnostics to {}", dir.display()),
+            Err(e) => eprintln!("Could not write crash diagnostics: {}", e),
+        }
+        std::process::abort();
+    }));
+}
+
+/// Records a simulated frame for the crash bundle, if the hook is installed.
+pub(crate) fn record_frame(state: &BoxGameState, inputs: &FrameInputs, checksum: u64) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(evidence) = evidence().as_mut() {
+        evidence.record(state, inputs, checksum);
+    }
+}
+
+/// Writes a crash bundle for an error the game cannot go on after, as a panic would, if the hook is installed. The
+/// game returns such errors instead of panicking in the middle of a GGRS request.
+pub(crate) fn record_failure(error: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    match write_bundle(error) {
+        Ok(dir) => tracing::error!("Wrote crash diagnostics to {}", dir.display()),
+        Err(e) => tracing::error!("Could not write crash diagnostics: {}", e),
+    }
+}
+
+fn write_bundle(panic: &str) -> std::io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let dir = PathBuf::from(format!("crash-{}", timestamp));
+    // the panicking thread may hold the lock while recording, waiting for it would never end
+    let evidence = match EVIDENCE.try_lock() {
+        Ok(evidence) => Some(evidence),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    };
+    match evidence.as_ref().and_then(|evidence| evidence.as_ref()) {
+        Some(evidence) => evidence.write(&dir, panic)?,
+        None => {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("panic.txt"), panic)?;
+        }
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxgame_sim::Checksum;
+
+    #[test]
+    fn bundle_holds_the_recent_frames() {
+        let mut evidence = Evidence::default();
+        let mut state = BoxGameState::new(2);
+        for frame in 1..=RECENT_FRAMES as i32 + 10 {
+            state.frame = frame;
+            evidence.record(&state, &vec![Some(vec![frame as u8]), None], frame as u64);
+        }
+        // a rollback to frame 5 before the end
+        state.frame = RECENT_FRAMES as i32 + 5;
+        evidence.record(&state, &vec![None, None], 1);
+        assert_eq!(evidence.frames.len(), RECENT_FRAMES - 5);
+        assert_eq!(evidence.frames.front().unwrap().frame, 11);
+        let last = evidence.frames.back().unwrap();
+        assert_eq!((last.frame, last.checksum), (state.frame, 1));
+
+        let dir = std::env::temp_dir().join(format!("crash-test-{}", std::process::id()));
+        evidence.write(&dir, "panicked at 'test'").unwrap();
+        let stored = versioned::decode(&fs::read(dir.join("state.bin")).unwrap()).unwrap();
+        assert_eq!(stored.checksum(), state.checksum());
+        let frames: Vec<FrameRecord> =
+            serde_json::from_slice(&fs::read(dir.join("frames.json")).unwrap()).unwrap();
+        assert_eq!(frames.len(), evidence.frames.len());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
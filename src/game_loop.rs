@@ -0,0 +1,146 @@
+use crate::box_game::{GameError, Simulation};
+use crate::timestep::MAX_TICKS_PER_FRAME;
+use boxgame_net::metrics::{Burst, Metrics};
+use ggrs::{GGRSError, GGRSEvent, GGRSRequest, P2PSession, P2PSpectatorSession, SessionState};
+use piston::event_loop::{EventSettings, Events};
+use piston::EventLoop;
+use std::time::Instant;
+
+/// Creates the event loop of a game simulating `fps` frames per second and rendering at most that often.
+/// Like `FixedTimestep`, it catches up on at most `MAX_TICKS_PER_FRAME` late updates and skips the rest.
+pub fn events(fps: u64) -> Events {
+    let mut event_settings = EventSettings::new();
+    event_settings.set_ups(fps);
+    event_settings.set_ups_reset(MAX_TICKS_PER_FRAME as u64);
+    event_settings.set_max_fps(fps);
+    Events::new(event_settings)
+}
+
+/// The parts of a GGRS session the game loop needs, for players and spectators alike.
+pub trait Session {
+    fn current_state(&self) -> SessionState;
+    fn poll_remote_clients(&mut self);
+    fn take_events(&mut self) -> Vec<GGRSEvent>;
+}
+
+impl Session for P2PSession {
+    fn current_state(&self) -> SessionState {
+        P2PSession::current_state(self)
+    }
+
+    fn poll_remote_clients(&mut self) {
+        P2PSession::poll_remote_clients(self)
+    }
+
+    fn take_events(&mut self) -> Vec<GGRSEvent> {
+        self.events().collect()
+    }
+}
+
+impl Session for P2PSpectatorSession {
+    fn current_state(&self) -> SessionState {
+        P2PSpectatorSession::current_state(self)
+    }
+
+    fn poll_remote_clients(&mut self) {
+        P2PSpectatorSession::poll_remote_clients(self)
+    }
+
+    fn take_events(&mut self) -> Vec<GGRSEvent> {
+        self.events().collect()
+    }
+}
+
+/// What a single tick of the game loop did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advance {
+    /// the session is not running yet
+    NotRunning,
+    /// the frame was skipped, because GGRS recommended waiting for the other peer
+    Skipped,
+    /// GGRS refused to advance, it is waiting for remote inputs
+    Waiting,
+    /// the frame was advanced and all requests were handled
+    Advanced,
+}
+
+/// The per-tick logic every binary driving a GGRS session shares: skipping frames on `WaitRecommendation`, advancing
+/// the session and handling its requests, waiting at the prediction threshold and draining the events.
+#[derive(Default)]
+pub struct GameLoop {
+    frames_to_skip: u32,
+    /// rollbacks, stalls, skipped frames, simulation time and saved state sizes
+    pub metrics: Metrics,
+}
+
+impl GameLoop {
+    /// Runs one simulation tick. `advance` calls `advance_frame` on the session, with whatever input this peer sends,
+    /// and `game` handles the resulting requests. Returns what happened, along with the events of the session, which
+    /// were already logged. Any error but the prediction threshold ends the match.
+    pub fn tick<S: Session>(
+        &mut self,
+        sess: &mut S,
+        game: &mut Simulation,
+        advance: impl FnOnce(&mut S) -> Result<Vec<GGRSRequest>, GGRSError>,
+    ) -> Result<(Advance, Vec<GGRSEvent>), GameError> {
+        if self.frames_to_skip > 0 {
+            self.frames_to_skip -= 1;
+            self.metrics.increment("skipped_frames", 1);
+            tracing::debug!("Skipping a frame: WaitRecommendation");
+            return Ok((Advance::Skipped, Vec::new()));
+        }
+        if sess.current_state() != SessionState::Running {
+            return Ok((Advance::NotRunning, Vec::new()));
+        }
+
+        let frame = game.current_frame();
+        let span = tracing::debug_span!("advance_frame", frame);
+        let advanced = match span.in_scope(|| advance(sess)) {
+            Ok(requests) => {
+                self.metrics.record_requests(&requests);
+                let burst = Burst::of(&requests);
+                let simulation_start = Instant::now();
+                game.handle_requests(requests)?;
+                let simulation_time = simulation_start.elapsed();
+                self.metrics
+                    .record("simulation_time_ms", simulation_time.as_secs_f64() * 1000.0);
+                self.metrics
+                    .record_burst(burst, simulation_time, Instant::now());
+                self.metrics
+                    .record("save_state_bytes", game.last_save_size() as f64);
+                self.metrics
+                    .record("held_state_bytes", game.held_state_bytes() as f64);
+                Advance::Advanced
+            }
+            Err(GGRSError::PredictionThreshold) => {
+                self.metrics.increment("prediction_threshold_stalls", 1);
+                tracing::debug!("Skipping a frame: PredictionThreshold");
+                Advance::Waiting
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let events = sess.take_events();
+        for event in &events {
+            match event {
+                GGRSEvent::WaitRecommendation { skip_frames } => {
+                    self.frames_to_skip += skip_frames;
+                    tracing::debug!(target: "ggrs_events", "Event: {:?}", event);
+                }
+                GGRSEvent::Synchronizing { .. } => {
+                    tracing::debug!(target: "ggrs_events", "Event: {:?}", event)
+                }
+                GGRSEvent::NetworkInterrupted { .. } | GGRSEvent::Disconnected { .. } => {
+                    tracing::warn!(target: "ggrs_events", "Event: {:?}", event)
+                }
+                _ => tracing::info!(target: "ggrs_events", "Event: {:?}", event),
+            }
+        }
+        Ok((advanced, events))
+    }
+
+    /// Processes incoming packets, call it whenever the event loop is idle.
+    pub fn poll<S: Session>(&mut self, sess: &mut S) {
+        tracing::debug_span!("poll_remote_clients").in_scope(|| sess.poll_remote_clients());
+    }
+}
@@ -0,0 +1,127 @@
+//! Where the players spend their time and which buttons they hold, accumulated from confirmed inputs for analysis
+//! during or after a match.
+
+use crate::box_game::{input, InputFlags, Simulation, INPUT_SIZE};
+use boxgame_sim::{ARENA_HEIGHT, ARENA_WIDTH};
+
+/// Side of a heatmap cell, in arena pixels.
+pub const CELL_SIZE: f64 = 40.0;
+/// The buttons counted by `Heatmap::held`, in its order.
+pub const BUTTONS: [(InputFlags, &str); 5] = [
+    (InputFlags::UP, "up"),
+    (InputFlags::LEFT, "left"),
+    (InputFlags::DOWN, "down"),
+    (InputFlags::RIGHT, "right"),
+    (InputFlags::DASH, "dash"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heatmap {
+    pub columns: usize,
+    pub rows: usize,
+    /// by GGRS player handle: how many frames the player's boxes spent in each cell, row by row
+    pub positions: Vec<Vec<u32>>,
+    /// by GGRS player handle: for how many box frames each of `BUTTONS` was held
+    pub held: Vec<[u32; BUTTONS.len()]>,
+    /// recorded frames
+    pub frames: u32,
+}
+
+impl Heatmap {
+    pub fn new(num_players: usize) -> Self {
+        let columns = (ARENA_WIDTH as f64 / CELL_SIZE).ceil() as usize;
+        let rows = (ARENA_HEIGHT as f64 / CELL_SIZE).ceil() as usize;
+        Self {
+            columns,
+            rows,
+            positions: vec![vec![0; columns * rows]; num_players],
+            held: vec![[0; BUTTONS.len()]; num_players],
+            frames: 0,
+        }
+    }
+
+    /// Records the simulation's current state and the inputs it was simulated with, see `Simulation::last_inputs`.
+    /// Only record confirmed frames, rolled back ones would count twice.
+    pub fn record(&mut self, simulation: &Simulation, frame_inputs: &[Option<Vec<u8>>]) {
+        let local_players = simulation.local_players.max(1);
+        for (i, &(x, y)) in simulation.game_state.positions.iter().enumerate() {
+            if let Some(cells) = self.positions.get_mut(i / local_players) {
+                let column = ((x / CELL_SIZE) as usize).min(self.columns - 1);
+                let row = ((y / CELL_SIZE) as usize).min(self.rows - 1);
+                cells[row * self.columns + column] += 1;
+            }
+        }
+        for (handle, bytes) in frame_inputs.iter().enumerate() {
+            let (held, bytes) = match (self.held.get_mut(handle), bytes) {
+                (Some(held), Some(bytes)) => (held, bytes),
+                _ => continue,
+            };
+            for chunk in bytes.chunks(INPUT_SIZE).take(local_players) {
+                let buttons = input::decode(chunk).buttons;
+                for (count, &(flag, _)) in held.iter_mut().zip(BUTTONS.iter()) {
+                    if buttons.contains(flag) {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        self.frames += 1;
+    }
+
+    /// The most frames any of the player's cells was visited, for scaling the colors.
+    pub fn max_cell(&self, handle: usize) -> u32 {
+        self.positions
+            .get(handle)
+            .and_then(|cells| cells.iter().copied().max())
+            .unwrap_or(0)
+    }
+
+    /// A line for the HUD with the share of the frames each button was held by the player, e.g. "up 40% dash 5%".
+    pub fn held_line(&self, handle: usize, local_players: usize) -> String {
+        let box_frames = (self.frames as usize * local_players.max(1)).max(1) as f64;
+        self.held
+            .get(handle)
+            .map(|held| {
+                BUTTONS
+                    .iter()
+                    .zip(held.iter())
+                    .map(|(&(_, name), &count)| {
+                        format!("{} {:.0}%", name, 100.0 * count as f64 / box_frames)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::box_game::Input;
+
+    #[test]
+    fn counts_positions_and_held_buttons() {
+        let mut simulation = Simulation::new(2, 1);
+        simulation.game_state.positions[0] = (10.0, 10.0);
+        simulation.game_state.positions[1] = (ARENA_WIDTH as f64, ARENA_HEIGHT as f64);
+        let mut heatmap = Heatmap::new(2);
+        let dash = Input {
+            buttons: InputFlags::DASH,
+            ..Input::default()
+        };
+        let inputs = [Some(input::encode(dash).to_vec()), None];
+        heatmap.record(&simulation, &inputs);
+        heatmap.record(&simulation, &inputs);
+        assert_eq!(heatmap.positions[0][0], 2);
+        // a box on the far border counts for the last cell
+        assert_eq!(heatmap.positions[1][heatmap.columns * heatmap.rows - 1], 2);
+        assert_eq!(heatmap.max_cell(0), 2);
+        assert_eq!(heatmap.held[0], [0, 0, 0, 0, 2]);
+        assert_eq!(heatmap.held[1], [0; 5]);
+        assert_eq!(
+            heatmap.held_line(0, 1),
+            "up 0% left 0% down 0% right 0% dash 100%"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Drawing a player's input the way fighting game streams show it: a d-pad with the pressed directions lit, the dash
+//! button and the position of the analog stick. Only built with the render feature.
+
+use crate::box_game::{Input, InputFlags};
+use boxgame_sim::ANALOG_STEPS;
+use graphics::math::Matrix2d;
+use graphics::{ellipse, rectangle, DrawState, Ellipse, Graphics, Rectangle};
+
+/// Size of a single input display.
+pub const SIZE: [f64; 2] = [3.0 * CELL + GAP + BUTTON, 3.0 * CELL];
+const CELL: f64 = 12.0;
+const GAP: f64 = 8.0;
+const BUTTON: f64 = 2.0 * CELL;
+const STICK_DOT: f64 = 4.0;
+
+const OFF: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+const ON: [f32; 4] = [1.0; 4];
+const STICK: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+
+/// The d-pad cells around the center, as column and row.
+const DIRECTIONS: [(InputFlags, [f64; 2]); 4] = [
+    (InputFlags::UP, [1.0, 0.0]),
+    (InputFlags::LEFT, [0.0, 1.0]),
+    (InputFlags::RIGHT, [2.0, 1.0]),
+    (InputFlags::DOWN, [1.0, 2.0]),
+];
+
+fn color(pressed: bool) -> [f32; 4] {
+    if pressed {
+        ON
+    } else {
+        OFF
+    }
+}
+
+/// Draws `input` with its top left corner at the origin of `transform`.
+pub fn draw_input<G: Graphics>(
+    input: Input,
+    draw_state: &DrawState,
+    transform: Matrix2d,
+    gl: &mut G,
+) {
+    for &(flag, [column, row]) in DIRECTIONS.iter() {
+        let cell = rectangle::square(column * CELL, row * CELL, CELL - 1.0);
+        Rectangle::new(color(input.buttons.contains(flag))).draw(cell, draw_state, transform, gl);
+    }
+    // the stick moves a dot across the d-pad
+    let center = 1.5 * CELL;
+    let reach = 1.5 * CELL - STICK_DOT / 2.0;
+    let x = center + reach * input.stick_x as f64 / ANALOG_STEPS as f64;
+    let y = center + reach * input.stick_y as f64 / ANALOG_STEPS as f64;
+    let dot = ellipse::circle(x, y, STICK_DOT / 2.0);
+    Ellipse::new(STICK).draw(dot, draw_state, transform, gl);
+
+    let dash = [3.0 * CELL + GAP, CELL / 2.0, BUTTON, BUTTON];
+    Ellipse::new(color(input.buttons.contains(InputFlags::DASH)))
+        .draw(dash, draw_state, transform, gl);
+}
@@ -0,0 +1,69 @@
+use ggrs::Frame;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+/// Records the raw local input bytes together with the frame they were submitted for.
+/// One line per frame: the frame number followed by the input bytes in hex, e.g. `120 0900ff`.
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(Self { writer })
+    }
+
+    pub fn record(&mut self, frame: Frame, input: &[u8]) -> std::io::Result<()> {
+        let hex: String = input.iter().map(|byte| format!("{:02x}", byte)).collect();
+        writeln!(self.writer, "{} {}", frame, hex)?;
+        // flush right away, the interesting recordings are the ones that end in a crash
+        self.writer.flush()
+    }
+}
+
+/// Plays back inputs written by an `InputRecorder`, so a player can be driven by a script instead of the keyboard.
+pub struct InputPlayback {
+    inputs: HashMap<Frame, Vec<u8>>,
+}
+
+impl InputPlayback {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut inputs = HashMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (frame, input) = parse_line(&line).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid input line: {}", line),
+                )
+            })?;
+            inputs.insert(frame, input);
+        }
+        Ok(Self { inputs })
+    }
+
+    /// The recorded input for the given frame, or `None` if the recording has no input for it.
+    pub fn input(&self, frame: Frame) -> Option<&[u8]> {
+        self.inputs.get(&frame).map(|input| input.as_slice())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Frame, Vec<u8>)> {
+    let mut parts = line.split_whitespace();
+    let frame = parts.next()?.parse().ok()?;
+    let hex = parts.next()?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let input = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((frame, input))
+}
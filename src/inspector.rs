@@ -0,0 +1,88 @@
+use crate::box_game::TreeNode;
+use std::collections::HashSet;
+
+/// Debug panel showing the game state as a tree. Nodes are selected with up and down and expanded and collapsed
+/// with right and left.
+#[derive(Default)]
+pub struct Inspector {
+    pub open: bool,
+    /// expanded nodes, as the child indices leading to them from the root
+    expanded: HashSet<Vec<usize>>,
+    cursor: usize,
+}
+
+impl Inspector {
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    pub fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn down(&mut self) {
+        self.cursor += 1;
+    }
+
+    pub fn expand(&mut self, tree: &TreeNode) {
+        if let Some((path, _, node)) = self.visible(tree).into_iter().nth(self.cursor) {
+            if !node.children.is_empty() {
+                self.expanded.insert(path);
+            }
+        }
+    }
+
+    pub fn collapse(&mut self, tree: &TreeNode) {
+        if let Some((path, _, _)) = self.visible(tree).into_iter().nth(self.cursor) {
+            self.expanded.remove(&path);
+        }
+    }
+
+    /// The lines to render for the given tree, with the selected node marked.
+    pub fn lines(&mut self, tree: &TreeNode) -> Vec<String> {
+        let visible = self.visible(tree);
+        self.cursor = self.cursor.min(visible.len().saturating_sub(1));
+        visible
+            .iter()
+            .enumerate()
+            .map(|(i, (path, depth, node))| {
+                let cursor = if i == self.cursor { ">" } else { " " };
+                let marker = match (node.children.is_empty(), self.expanded.contains(path)) {
+                    (true, _) => " ",
+                    (false, true) => "-",
+                    (false, false) => "+",
+                };
+                format!(
+                    "{} {}{} {}",
+                    cursor,
+                    "    ".repeat(*depth),
+                    marker,
+                    node.label
+                )
+            })
+            .collect()
+    }
+
+    /// The nodes below the root that are currently visible, in order, with their path and depth.
+    fn visible<'a>(&self, tree: &'a TreeNode) -> Vec<(Vec<usize>, usize, &'a TreeNode)> {
+        let mut visible = Vec::new();
+        let mut stack: Vec<(Vec<usize>, &TreeNode)> = tree
+            .children
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, child)| (vec![i], child))
+            .collect();
+        while let Some((path, node)) = stack.pop() {
+            if self.expanded.contains(&path) {
+                for (i, child) in node.children.iter().enumerate().rev() {
+                    let mut child_path = path.clone();
+                    child_path.push(i);
+                    stack.push((child_path, child));
+                }
+            }
+            visible.push((path.clone(), path.len() - 1, node));
+        }
+        visible
+    }
+}
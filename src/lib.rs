@@ -0,0 +1,37 @@
+//! The box game on top of `boxgame-sim`: the rollback simulation driven by GGRS, rendering, input, replays and the
+//! command line plumbing shared by the binaries in `src/bin`. Everything that needs a display is behind the render
+//! feature, which is on by default.
+
+pub mod alloc_counter;
+pub mod archive;
+pub mod box_game;
+pub mod camera;
+pub mod chat;
+pub mod cli;
+pub mod config;
+pub mod crash;
+pub mod game_loop;
+pub mod heatmap;
+#[cfg(feature = "render")]
+pub mod input_display;
+pub mod input_recording;
+pub mod inspector;
+pub mod match_stats;
+pub mod notifications;
+pub mod overlay;
+pub mod presence;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+pub mod rewind;
+#[cfg(feature = "render")]
+pub mod rumble;
+pub mod scenario;
+pub mod sim_thread;
+#[cfg(feature = "render")]
+pub mod text;
+pub mod timestep;
+pub mod trace;
+pub mod tuning_file;
+#[cfg(feature = "render")]
+pub mod window;
@@ -0,0 +1,156 @@
+//! A summary of a spectated match for record keeping, written as JSON once the match is over. The game has no score
+//! or game over: the players' hits stand in for the score, and a match is over when its session ends.
+
+use crate::box_game::{HitEvent, Simulation, FPS};
+use ggrs::Frame;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MatchSummary {
+    pub players: Vec<PlayerStats>,
+    /// simulated frames
+    pub frames: Frame,
+    /// game time, i.e. the frames at the game's frame rate
+    pub duration_secs: f64,
+    /// how long it was watched, longer than the game time if the spectator joined late or fell behind
+    pub watched_secs: f64,
+    pub final_frame: Frame,
+    pub final_checksum: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlayerStats {
+    pub name: String,
+    pub hits: u32,
+    /// how far the player's boxes moved together, in pixels
+    pub distance: f64,
+    /// the highest speed of any of the player's boxes, in pixels per frame
+    pub top_speed: f64,
+}
+
+/// Collects the stats of a match while it is simulated. Spectators only simulate confirmed frames, so everything
+/// recorded is final.
+pub struct MatchStats {
+    players: Vec<PlayerStats>,
+    first_frame: Option<Frame>,
+    /// box positions at the last recorded frame
+    positions: Vec<(f64, f64)>,
+    started: Instant,
+}
+
+impl MatchStats {
+    /// `names` are the players' names, by GGRS player handle.
+    pub fn new(names: Vec<String>, now: Instant) -> Self {
+        Self {
+            players: names
+                .into_iter()
+                .map(|name| PlayerStats {
+                    name,
+                    hits: 0,
+                    distance: 0.0,
+                    top_speed: 0.0,
+                })
+                .collect(),
+            first_frame: None,
+            positions: Vec::new(),
+            started: now,
+        }
+    }
+
+    /// Renames a player, e.g. once its name arrived.
+    pub fn rename(&mut self, handle: usize, name: &str) {
+        if let Some(stats) = self.players.get_mut(handle) {
+            stats.name = name.to_string();
+        }
+    }
+
+    /// Records the simulation's current state and the hits confirmed with it. Frames that were simulated in between
+    /// count as a straight line.
+    pub fn record(&mut self, simulation: &Simulation, hits: &[HitEvent]) {
+        let state = &simulation.game_state;
+        let local_players = simulation.local_players.max(1);
+        self.first_frame.get_or_insert(state.frame - 1);
+        for hit in hits {
+            let (a, b) = hit.players;
+            for &player in [a, b].iter() {
+                if let Some(stats) = self.players.get_mut(player / local_players) {
+                    stats.hits += 1;
+                }
+            }
+        }
+        for (i, &(x, y)) in state.positions.iter().enumerate() {
+            let stats = match self.players.get_mut(i / local_players) {
+                Some(stats) => stats,
+                None => continue,
+            };
+            if let Some(&(old_x, old_y)) = self.positions.get(i) {
+                stats.distance += (x - old_x).hypot(y - old_y);
+            }
+            let (vx, vy) = state.velocities[i];
+            stats.top_speed = stats.top_speed.max(vx.hypot(vy));
+        }
+        self.positions.clear();
+        self.positions.extend(state.positions.iter().copied());
+    }
+
+    /// The summary of the match up to the simulation's current state.
+    pub fn summary(&self, simulation: &Simulation, now: Instant) -> MatchSummary {
+        let (final_frame, final_checksum) = simulation.last_checksum();
+        let frames = self
+            .first_frame
+            .map_or(0, |first_frame| final_frame - first_frame);
+        MatchSummary {
+            players: self.players.clone(),
+            frames,
+            duration_secs: frames as f64 / FPS as f64,
+            watched_secs: now.saturating_duration_since(self.started).as_secs_f64(),
+            final_frame,
+            final_checksum,
+        }
+    }
+}
+
+impl MatchSummary {
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::box_game::Input;
+    use std::time::Duration;
+
+    #[test]
+    fn summary_counts_hits_and_movement() {
+        let start = Instant::now();
+        let mut game = Simulation::new(2, 1);
+        let mut stats = MatchStats::new(vec!["Alice".to_string(), "Bob".to_string()], start);
+        let hit = HitEvent {
+            frame: 1,
+            players: (0, 1),
+        };
+        for frame in 1..=120 {
+            let input = Input::default();
+            let bytes = crate::box_game::input::encode(input).to_vec();
+            game.simulate_frame(vec![Some(bytes.clone()), Some(bytes)])
+                .unwrap();
+            let hits = if frame == 1 { vec![hit] } else { Vec::new() };
+            stats.record(&game, &hits);
+        }
+        let summary = stats.summary(&game, start + Duration::from_secs(3));
+        assert_eq!(summary.frames, 120);
+        assert_eq!(summary.duration_secs, 2.0);
+        assert_eq!(summary.watched_secs, 3.0);
+        assert_eq!(summary.final_frame, 120);
+        assert_eq!(summary.players[0].hits, 1);
+        assert_eq!(summary.players[1].hits, 1);
+        // without inputs, nobody moves
+        assert_eq!(summary.players[0].distance, 0.0);
+    }
+}
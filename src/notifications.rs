@@ -0,0 +1,137 @@
+use ggrs::GGRSEvent;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a toast stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+/// Older toasts are dropped early when more than this many are shown at once.
+const MAX_TOASTS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    /// something went wrong, but the match goes on
+    Warning,
+    /// the match is most likely broken, e.g. the peers desynced
+    Error,
+}
+
+/// Something the players should know about while playing, e.g. that the other player's connection is interrupted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    pub severity: Severity,
+    pub text: String,
+}
+
+impl Notification {
+    pub fn info(text: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Info,
+            text: text.into(),
+        }
+    }
+
+    pub fn warning(text: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            text: text.into(),
+        }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            text: text.into(),
+        }
+    }
+
+    /// The notification for a GGRS event, `None` for the events that are only of interest to the log.
+    pub fn from_event(event: &GGRSEvent) -> Option<Self> {
+        match event {
+            GGRSEvent::Synchronized { player_handle } => Some(Self::info(format!(
+                "Synchronized with player {}",
+                player_handle
+            ))),
+            GGRSEvent::NetworkInterrupted {
+                player_handle,
+                disconnect_timeout,
+            } => Some(Self::warning(format!(
+                "Connection to player {} interrupted, disconnecting in {} ms",
+                player_handle, disconnect_timeout
+            ))),
+            GGRSEvent::NetworkResumed { player_handle } => Some(Self::info(format!(
+                "Connection to player {} resumed",
+                player_handle
+            ))),
+            GGRSEvent::Disconnected { player_handle } => Some(Self::error(format!(
+                "Player {} disconnected",
+                player_handle
+            ))),
+            GGRSEvent::Synchronizing { .. } | GGRSEvent::WaitRecommendation { .. } => None,
+        }
+    }
+
+    fn log(&self) {
+        match self.severity {
+            Severity::Info => tracing::info!(target: "notifications", "{}", self.text),
+            Severity::Warning => tracing::warn!(target: "notifications", "{}", self.text),
+            Severity::Error => tracing::error!(target: "notifications", "{}", self.text),
+        }
+    }
+}
+
+/// The notifications currently shown as toasts, newest last.
+#[derive(Default)]
+pub struct Toasts {
+    shown: VecDeque<(Notification, Instant)>,
+}
+
+impl Toasts {
+    /// Logs the notification and shows it from `now` on.
+    pub fn push(&mut self, notification: Notification, now: Instant) {
+        notification.log();
+        self.shown.push_back((notification, now));
+        if self.shown.len() > MAX_TOASTS {
+            self.shown.pop_front();
+        }
+    }
+
+    /// The toasts to render at `now`, expired ones are dropped.
+    pub fn visible(&mut self, now: Instant) -> Vec<Notification> {
+        while let Some((_, shown_at)) = self.shown.front() {
+            if now.saturating_duration_since(*shown_at) < TOAST_DURATION {
+                break;
+            }
+            self.shown.pop_front();
+        }
+        self.shown
+            .iter()
+            .map(|(notification, _)| notification.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_expire_and_are_capped() {
+        let start = Instant::now();
+        let mut toasts = Toasts::default();
+        toasts.push(Notification::info("first"), start);
+        for i in 0..MAX_TOASTS {
+            toasts.push(
+                Notification::warning(format!("later {}", i)),
+                start + Duration::from_secs(1),
+            );
+        }
+        let visible = toasts.visible(start);
+        assert_eq!(visible.len(), MAX_TOASTS);
+        assert_eq!(visible[0].text, "later 0");
+        assert_eq!(toasts.visible(start + TOAST_DURATION).len(), MAX_TOASTS);
+        assert!(toasts
+            .visible(start + Duration::from_secs(1) + TOAST_DURATION)
+            .is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Live match data for stream overlays, e.g. an OBS browser source: a JSON file that is replaced every frame. It is
+//! written to a temporary file first and renamed over the old one, so the overlay never reads half of it.
+
+use crate::box_game::HitEvent;
+use ggrs::Frame;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What an overlay gets to show. The game has no score, the players' hits stand in for it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OverlayData {
+    pub frame: Frame,
+    pub checksum: u64,
+    pub players: Vec<OverlayPlayer>,
+    pub host: String,
+    pub frames_behind_host: i32,
+    /// no new frames arrived for a while, see `FeedMonitor::stalled`
+    pub stalled: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OverlayPlayer {
+    pub name: String,
+    /// how often one of the player's boxes started touching another one
+    pub hits: u32,
+}
+
+/// The connection details written alongside the match data.
+pub struct Link {
+    pub host: String,
+    pub frames_behind_host: i32,
+    pub stalled: bool,
+}
+
+/// Writes `OverlayData` to a file. The match data is recorded as the spectator simulates it, but only written once
+/// the shown frame reaches it, so an overlay on a delayed stream is delayed as well.
+pub struct OverlayFeed {
+    path: PathBuf,
+    names: Vec<String>,
+    hits: Vec<u32>,
+    /// hits of frames that were not shown yet
+    pending_hits: VecDeque<HitEvent>,
+    /// the checksums of simulated frames that were not shown yet, and of the last shown one
+    checksums: VecDeque<(Frame, u64)>,
+}
+
+impl OverlayFeed {
+    /// `names` are the players' names, by GGRS player handle. Each of their boxes counts for them.
+    pub fn new(path: &Path, names: Vec<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            hits: vec![0; names.len()],
+            names,
+            pending_hits: VecDeque::new(),
+            checksums: VecDeque::new(),
+        }
+    }
+
+    /// Renames a player, e.g. once its name arrived.
+    pub fn rename(&mut self, handle: usize, name: &str) {
+        if let Some(old) = self.names.get_mut(handle) {
+            *old = name.to_string();
+        }
+    }
+
+    /// Starts over with no hits, for a match that starts over.
+    pub fn reset(&mut self) {
+        self.hits.iter_mut().for_each(|hits| *hits = 0);
+        self.pending_hits.clear();
+        self.checksums.clear();
+    }
+
+    /// Records the confirmed hits and the checksum of a simulated frame.
+    pub fn record(&mut self, hits: Vec<HitEvent>, checksum: (Frame, u64)) {
+        self.pending_hits.extend(hits);
+        self.checksums.push_back(checksum);
+    }
+
+    /// The data of the last recorded frame up to `frame`. `local_players` is the number of boxes per player.
+    pub fn data(&mut self, frame: Frame, local_players: usize, link: Link) -> OverlayData {
+        while let Some(hit) = self.pending_hits.front() {
+            if hit.frame > frame {
+                break;
+            }
+            let (a, b) = hit.players;
+            for player in [a, b].iter() {
+                if let Some(hits) = self.hits.get_mut(player / local_players.max(1)) {
+                    *hits += 1;
+                }
+            }
+            self.pending_hits.pop_front();
+        }
+        while self.checksums.len() > 1 && self.checksums[1].0 <= frame {
+            self.checksums.pop_front();
+        }
+        let (frame, checksum) = self.checksums.front().copied().unwrap_or((frame, 0));
+        OverlayData {
+            frame,
+            checksum,
+            players: self
+                .names
+                .iter()
+                .zip(self.hits.iter())
+                .map(|(name, &hits)| OverlayPlayer {
+                    name: name.clone(),
+                    hits,
+                })
+                .collect(),
+            host: link.host,
+            frames_behind_host: link.frames_behind_host,
+            stalled: link.stalled,
+        }
+    }
+
+    /// Replaces the file with `data`.
+    pub fn write(&self, data: &OverlayData) -> io::Result<()> {
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(data)?)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link() -> Link {
+        Link {
+            host: "127.0.0.1:7000".to_string(),
+            frames_behind_host: 2,
+            stalled: false,
+        }
+    }
+
+    #[test]
+    fn data_follows_the_shown_frame() {
+        let path = std::env::temp_dir().join(format!("overlay-{}.json", std::process::id()));
+        let mut feed = OverlayFeed::new(&path, vec!["Alice".to_string(), "Bob".to_string()]);
+        for frame in 1..=10 {
+            let hits = match frame {
+                // with two boxes per player, boxes 1 and 2 belong to different players
+                5 => vec![HitEvent {
+                    frame,
+                    players: (1, 2),
+                }],
+                _ => Vec::new(),
+            };
+            feed.record(hits, (frame, frame as u64 * 100));
+        }
+        let data = feed.data(4, 2, link());
+        assert_eq!((data.frame, data.checksum), (4, 400));
+        assert_eq!(data.players[0].hits + data.players[1].hits, 0);
+        let data = feed.data(7, 2, link());
+        assert_eq!((data.frame, data.checksum), (7, 700));
+        assert_eq!(data.players[0].hits, 1);
+        assert_eq!(data.players[1].hits, 1);
+
+        feed.write(&data).unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["players"][1]["name"], "Bob");
+        assert_eq!(written["frame"], 7);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! What the local player is doing, for Discord Rich Presence: "In match, frame X" and "vs <name>", with a join
+//! secret carrying the host address. The Discord client itself is only built with the discord feature.
+//!
+//! GGRS 0.2 sessions are started with the address of the other peer, so a join secret can only bring in the friend
+//! the host was started for. It saves the friend from typing the address and handle, see `--join`.
+
+use anyhow::{bail, Context};
+use ggrs::{Frame, PlayerHandle};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+
+/// Prefix of join secrets, so secrets of other games or versions are rejected.
+const JOIN_SECRET_PREFIX: &str = "boxgame1:";
+
+/// Where to join a match: the host's address as reachable by friends and the host's player handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinSecret {
+    pub addr: SocketAddr,
+    pub handle: PlayerHandle,
+}
+
+impl JoinSecret {
+    /// e.g. `boxgame1:0@203.0.113.7:7000`
+    pub fn encode(&self) -> String {
+        format!("{}{}@{}", JOIN_SECRET_PREFIX, self.handle, self.addr)
+    }
+
+    /// The id of the match's party. Party ids are shown to everyone who sees the presence, unlike the join secret, so
+    /// the id is a hash of the secret keyed with `key`: the same for the whole match, but the address cannot be
+    /// recovered by hashing every possible one.
+    pub fn party_id(&self, key: &RandomState) -> String {
+        format!("{:016x}", key.hash_one(self.encode()))
+    }
+
+    pub fn parse(secret: &str) -> anyhow::Result<Self> {
+        let rest = match secret.strip_prefix(JOIN_SECRET_PREFIX) {
+            Some(rest) => rest,
+            None => bail!("{} is not a join secret of this game", secret),
+        };
+        let (handle, addr) = rest
+            .split_once('@')
+            .with_context(|| format!("invalid join secret {}", secret))?;
+        Ok(Self {
+            addr: addr
+                .parse()
+                .with_context(|| format!("invalid address in join secret {}", secret))?,
+            handle: handle
+                .parse()
+                .with_context(|| format!("invalid player handle in join secret {}", secret))?,
+        })
+    }
+}
+
+/// The presence shown for a player in a match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub frame: Frame,
+    pub opponent: Option<String>,
+    /// whether the other peer joined the match, which leaves no room in the party
+    pub opponent_synchronized: bool,
+    pub join_secret: Option<JoinSecret>,
+}
+
+impl Status {
+    pub fn state(&self) -> String {
+        format!("In match, frame {}", self.frame)
+    }
+
+    pub fn details(&self) -> String {
+        match &self.opponent {
+            Some(name) => format!("vs {}", name),
+            None => "Waiting for the opponent's name".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "discord")]
+pub use discord::DiscordPresence;
+
+#[cfg(feature = "discord")]
+mod discord {
+    use super::Status;
+    use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+    use std::collections::hash_map::RandomState;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    /// Discord accepts about one activity update every 15 seconds, more are queued up or dropped.
+    const UPDATE_PERIOD: Duration = Duration::from_secs(15);
+
+    /// The connection to the local Discord client. Its errors are boxed without `Send`, so they are turned into
+    /// messages.
+    pub struct DiscordPresence {
+        client: DiscordIpcClient,
+        /// when the match started, in seconds since the epoch, Discord counts the elapsed time from it
+        started: i64,
+        last_update: Option<Instant>,
+        /// keys the party ids, see `JoinSecret::party_id`
+        party_key: RandomState,
+    }
+
+    impl DiscordPresence {
+        /// Connects to the Discord client running on this machine, `app_id` is the application ID from the Discord
+        /// developer portal.
+        pub fn connect(app_id: &str) -> anyhow::Result<Self> {
+            let mut client = DiscordIpcClient::new(app_id)
+                .map_err(|e| anyhow::anyhow!("cannot create the Discord client: {}", e))?;
+            client
+                .connect()
+                .map_err(|e| anyhow::anyhow!("cannot connect to Discord: {}", e))?;
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs() as i64);
+            Ok(Self {
+                client,
+                started,
+                last_update: None,
+                party_key: RandomState::new(),
+            })
+        }
+
+        /// Shows `status`, unless the last update was less than `UPDATE_PERIOD` ago.
+        pub fn update(&mut self, status: &Status, now: Instant) -> anyhow::Result<()> {
+            if let Some(last_update) = self.last_update {
+                if now.saturating_duration_since(last_update) < UPDATE_PERIOD {
+                    return Ok(());
+                }
+            }
+            self.last_update = Some(now);
+            let state = status.state();
+            let details = status.details();
+            let mut activity = activity::Activity::new()
+                .state(&state)
+                .details(&details)
+                .timestamps(activity::Timestamps::new().start(self.started));
+            let party = status
+                .join_secret
+                .map(|secret| (secret.party_id(&self.party_key), secret.encode()));
+            if let Some((party_id, secret)) = &party {
+                let size = if status.opponent_synchronized { 2 } else { 1 };
+                activity = activity.party(activity::Party::new().id(party_id).size([size, 2]));
+                // Discord only offers to join a party that has room left, the secret is of no use to anyone else
+                if !status.opponent_synchronized {
+                    activity = activity.secrets(activity::Secrets::new().join(secret));
+                }
+            }
+            self.client
+                .set_activity(activity)
+                .map_err(|e| anyhow::anyhow!("cannot update the Discord presence: {}", e))
+        }
+
+        pub fn close(mut self) {
+            // the presence disappears with the connection anyway
+            let _ = self.client.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_secrets_round_trip() {
+        let secret = JoinSecret {
+            addr: "203.0.113.7:7000".parse().unwrap(),
+            handle: 1,
+        };
+        assert_eq!(secret.encode(), "boxgame1:1@203.0.113.7:7000");
+        assert_eq!(JoinSecret::parse(&secret.encode()).unwrap(), secret);
+        assert!(JoinSecret::parse("othergame:1@203.0.113.7:7000").is_err());
+        assert!(JoinSecret::parse("boxgame1:1").is_err());
+        assert!(JoinSecret::parse("boxgame1:x@203.0.113.7:7000").is_err());
+    }
+
+    #[test]
+    fn party_ids_hide_the_address() {
+        let secret = JoinSecret {
+            addr: "203.0.113.7:7000".parse().unwrap(),
+            handle: 0,
+        };
+        let other = JoinSecret {
+            handle: 1,
+            ..secret
+        };
+        let key = RandomState::new();
+        let party_id = secret.party_id(&key);
+        assert_eq!(party_id, secret.party_id(&key));
+        assert_ne!(party_id, other.party_id(&key));
+        assert_ne!(party_id, secret.party_id(&RandomState::new()));
+        // nothing but the hash
+        assert_eq!(party_id.len(), 16);
+        assert!(party_id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
@@ -0,0 +1,306 @@
+//! Drawing the box game with OpenGL, only built with the render feature. Text goes through the `text` module.
+
+use crate::box_game::{GameError, Presentation, Simulation};
+use crate::camera::Camera;
+use crate::heatmap::{self, Heatmap};
+use crate::input_display;
+use crate::notifications::Severity;
+use crate::text::{Align, Style, Text};
+use boxgame_sim::{ARENA_HEIGHT, ARENA_WIDTH, PLAYER_SIZE};
+use graphics::math::Matrix2d;
+use graphics::{DrawState, Graphics, Rectangle, Transformed};
+use opengl_graphics::GlGraphics;
+use piston::input::RenderArgs;
+
+const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const WHITE: [f32; 4] = [1.0; 4];
+const BLUE: [f32; 4] = [0.0, 0.35, 0.78, 1.0];
+const ORANGE: [f32; 4] = [0.78, 0.59, 0.2, 1.0];
+const GREEN: [f32; 4] = [0.2, 0.7, 0.3, 1.0];
+const RED: [f32; 4] = [0.8, 0.2, 0.2, 1.0];
+const PLAYER_COLORS: [[f32; 4]; 4] = [BLUE, ORANGE, GREEN, RED];
+
+const WINDOW_HEIGHT: u32 = 800;
+/// Distance of the text from the window border.
+const MARGIN: f64 = 10.0;
+const LINE_HEIGHT: f64 = 25.0;
+/// The checksums are drawn over the arena, the outline keeps them readable when a box passes below.
+const CHECKSUM_STYLE: Style = Style::new(40).outline();
+const LINE_STYLE: Style = Style::new(20).outline();
+const BANNER_STYLE: Style = Style::new(30).outline().align(Align::Center);
+/// Player insets are drawn at this fraction of the arena size, side by side in the bottom right corner.
+const INSET_SCALE: f64 = 0.25;
+const INSET_BORDER: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+/// Height of the lag graph, one pixel wide bar per sample.
+const LAG_GRAPH_HEIGHT: f64 = 40.0;
+/// The lag graph scales to its highest sample, but never shows fewer frames than this.
+const LAG_GRAPH_MIN_FRAMES: i32 = 10;
+/// Opacity of the most visited heatmap cell of a player.
+const HEATMAP_ALPHA: f32 = 0.6;
+const LAG_GRAPH_BACKGROUND: [f32; 4] = [0.15, 0.15, 0.15, 0.8];
+
+fn severity_color(severity: Severity) -> [f32; 4] {
+    match severity {
+        Severity::Info => WHITE,
+        Severity::Warning => ORANGE,
+        Severity::Error => RED,
+    }
+}
+
+impl Presentation {
+    /// Draws the simulation's current state, with the checksums and the text lines above it.
+    pub fn render(
+        &self,
+        gl: &mut GlGraphics,
+        text: &mut Text,
+        args: &RenderArgs,
+        simulation: &Simulation,
+    ) -> Result<(), GameError> {
+        let (frame, checksum) = simulation.last_checksum();
+        let checksum_line = format!("Frame {}: Checksum {}", frame, checksum);
+        let (frame, checksum) = simulation.periodic_checksum();
+        let periodic_line = format!("Frame {}: Checksum {}", frame, checksum);
+        text.prepare(&checksum_line, CHECKSUM_STYLE.size)?;
+        text.prepare(&periodic_line, CHECKSUM_STYLE.size)?;
+        let lines = self
+            .debug_lines
+            .iter()
+            .chain(self.chat_lines.iter())
+            .chain(self.toasts.iter().map(|toast| &toast.text));
+        for line in lines {
+            text.prepare(line, LINE_STYLE.size)?;
+        }
+        if let Some(banner) = &self.banner {
+            text.prepare(banner, BANNER_STYLE.size)?;
+        }
+        for name in &self.player_names {
+            text.prepare(name, LINE_STYLE.size)?;
+        }
+        let width = args.window_size[0];
+        // scissor rectangles are in framebuffer pixels, which differ from window coordinates on high DPI screens
+        let pixels_per_point = args.draw_size[0] as f64 / width;
+        let text = &*text;
+
+        gl.draw(args.viewport(), |c, gl| {
+            // Clear the screen.
+            graphics::clear(BLACK, gl);
+            let window = [0.0, 0.0, width, args.window_size[1]];
+            let arena = camera_transform(&self.camera, window, c.transform);
+            if let Some(heatmap) = &self.heatmap {
+                draw_heatmap(heatmap, simulation.local_players, &c.draw_state, arena, gl);
+            }
+            draw_boxes(simulation, &c.draw_state, arena, gl);
+            self.draw_insets(simulation, pixels_per_point, window, &c, gl);
+            self.draw_player_names(simulation, text, window, &c, gl);
+
+            text.draw(&checksum_line, CHECKSUM_STYLE, [MARGIN, 40.0], &c, gl);
+            text.draw(&periodic_line, CHECKSUM_STYLE, [MARGIN, 80.0], &c, gl);
+            for (i, line) in self.debug_lines.iter().enumerate() {
+                text.draw(
+                    line,
+                    LINE_STYLE,
+                    [MARGIN, 110.0 + LINE_HEIGHT * i as f64],
+                    &c,
+                    gl,
+                );
+            }
+            // below the baseline of the last debug line
+            let graph_top = 110.0 + LINE_HEIGHT * (self.debug_lines.len() as f64 - 1.0) + MARGIN;
+            self.draw_lag_graph([MARGIN, graph_top], &c, gl);
+            if let Some(banner) = &self.banner {
+                let position = [width / 2.0, args.window_size[1] / 3.0];
+                text.draw(banner, BANNER_STYLE, position, &c, gl);
+            }
+            let chat_top =
+                WINDOW_HEIGHT as f64 - MARGIN - LINE_HEIGHT * self.chat_lines.len() as f64;
+            for (i, line) in self.chat_lines.iter().enumerate() {
+                text.draw(
+                    line,
+                    LINE_STYLE,
+                    [MARGIN, chat_top + LINE_HEIGHT * (i + 1) as f64],
+                    &c,
+                    gl,
+                );
+            }
+            // toasts are centered above the chat
+            let toast_top = chat_top - MARGIN - LINE_HEIGHT * self.toasts.len() as f64;
+            for (i, toast) in self.toasts.iter().enumerate() {
+                let style = LINE_STYLE
+                    .color(severity_color(toast.severity))
+                    .align(Align::Center);
+                let position = [width / 2.0, toast_top + LINE_HEIGHT * (i + 1) as f64];
+                text.draw(&toast.text, style, position, &c, gl);
+            }
+        });
+        Ok(())
+    }
+
+    /// Draws every box's player name centered above it, in the box's color.
+    fn draw_player_names(
+        &self,
+        simulation: &Simulation,
+        text: &Text,
+        window: [f64; 4],
+        c: &graphics::Context,
+        gl: &mut GlGraphics,
+    ) {
+        let state = &simulation.game_state;
+        let local_players = simulation.local_players.max(1);
+        for (i, &position) in state.positions.iter().enumerate() {
+            let name = match self.player_names.get(i / local_players) {
+                Some(name) => name,
+                None => continue,
+            };
+            let (x, y) = camera_point(&self.camera, window, position);
+            let above = PLAYER_SIZE / 2.0 * self.camera.zoom + MARGIN;
+            let style = LINE_STYLE
+                .color(PLAYER_COLORS[i % PLAYER_COLORS.len()])
+                .align(Align::Center);
+            text.draw(name, style, [x, y - above], c, gl);
+        }
+    }
+
+    /// Draws `lag_graph` as bars growing up from the bottom, with its top left corner at `[x, y]`.
+    fn draw_lag_graph(&self, [x, y]: [f64; 2], c: &graphics::Context, gl: &mut GlGraphics) {
+        if self.lag_graph.is_empty() {
+            return;
+        }
+        let scale = self
+            .lag_graph
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(LAG_GRAPH_MIN_FRAMES);
+        let width = self.lag_graph.len() as f64;
+        graphics::rectangle(
+            LAG_GRAPH_BACKGROUND,
+            [x, y, width, LAG_GRAPH_HEIGHT],
+            c.transform,
+            gl,
+        );
+        for (i, &frames) in self.lag_graph.iter().enumerate() {
+            let height = LAG_GRAPH_HEIGHT * frames as f64 / scale as f64;
+            let bar = [x + i as f64, y + LAG_GRAPH_HEIGHT - height, 1.0, height];
+            graphics::rectangle(GREEN, bar, c.transform, gl);
+        }
+    }
+
+    /// Draws the insets in a row, from the bottom right corner of `window` to the left, each with the input display
+    /// of its player below it.
+    fn draw_insets(
+        &self,
+        simulation: &Simulation,
+        pixels_per_point: f64,
+        [_, _, width, height]: [f64; 4],
+        c: &graphics::Context,
+        gl: &mut GlGraphics,
+    ) {
+        let size = [
+            ARENA_WIDTH as f64 * INSET_SCALE,
+            ARENA_HEIGHT as f64 * INSET_SCALE,
+        ];
+        let top = height - MARGIN - input_display::SIZE[1] - MARGIN - size[1];
+        for (i, inset) in self.insets.iter().enumerate() {
+            let left = width - (MARGIN + size[0]) * (i + 1) as f64;
+            let view = [left, top, size[0], size[1]];
+            let scissor = view.map(|value| (value * pixels_per_point).max(0.0) as u32);
+            let clipped = c.draw_state.scissor(scissor);
+            graphics::rectangle(BLACK, view, c.transform, gl);
+            let arena = camera_transform(&inset.camera, view, c.transform);
+            draw_boxes(simulation, &clipped, arena, gl);
+            Rectangle::new_border(INSET_BORDER, 1.0).draw(view, &c.draw_state, c.transform, gl);
+
+            for (slot, &input) in inset.inputs.iter().enumerate() {
+                let transform = c.transform.trans(
+                    left + (input_display::SIZE[0] + MARGIN) * slot as f64,
+                    top + size[1] + MARGIN,
+                );
+                input_display::draw_input(input, &c.draw_state, transform, gl);
+            }
+        }
+    }
+}
+
+/// Maps arena coordinates into the `[x, y, width, height]` rectangle `view` of the window as seen by `camera`. At
+/// zoom 1 the arena fills the view.
+fn camera_transform(
+    camera: &Camera,
+    [x, y, width, height]: [f64; 4],
+    transform: Matrix2d,
+) -> Matrix2d {
+    let (center_x, center_y) = camera.center;
+    transform
+        .trans(x + width / 2.0, y + height / 2.0)
+        .scale(width / ARENA_WIDTH as f64, height / ARENA_HEIGHT as f64)
+        .zoom(camera.zoom)
+        .trans(-center_x, -center_y)
+}
+
+/// Where `camera_transform` puts the arena point `(x, y)` in the window.
+fn camera_point(
+    camera: &Camera,
+    [left, top, width, height]: [f64; 4],
+    (x, y): (f64, f64),
+) -> (f64, f64) {
+    let (center_x, center_y) = camera.center;
+    (
+        left + width / 2.0 + (x - center_x) * camera.zoom * width / ARENA_WIDTH as f64,
+        top + height / 2.0 + (y - center_y) * camera.zoom * height / ARENA_HEIGHT as f64,
+    )
+}
+
+/// Draws every player's heatmap cells in the player's color, more opaque the longer they were visited, with
+/// `transform` mapping arena coordinates to the screen. Players with several boxes use the color of the first one.
+fn draw_heatmap<G: Graphics>(
+    heatmap: &Heatmap,
+    local_players: usize,
+    draw_state: &DrawState,
+    transform: Matrix2d,
+    gl: &mut G,
+) {
+    let local_players = local_players.max(1);
+    for (handle, cells) in heatmap.positions.iter().enumerate() {
+        let max = heatmap.max_cell(handle).max(1) as f32;
+        let [r, g, b, _] = PLAYER_COLORS[(handle * local_players) % PLAYER_COLORS.len()];
+        for (i, &count) in cells.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let column = (i % heatmap.columns) as f64;
+            let row = (i / heatmap.columns) as f64;
+            let cell = [
+                column * heatmap::CELL_SIZE,
+                row * heatmap::CELL_SIZE,
+                heatmap::CELL_SIZE,
+                heatmap::CELL_SIZE,
+            ];
+            let color = [r, g, b, HEATMAP_ALPHA * count as f32 / max];
+            Rectangle::new(color).draw(cell, draw_state, transform, gl);
+        }
+    }
+}
+
+/// Draws the player rectangles, `transform` maps arena coordinates to the screen.
+pub fn draw_boxes<G: Graphics>(
+    simulation: &Simulation,
+    draw_state: &DrawState,
+    transform: Matrix2d,
+    gl: &mut G,
+) {
+    use graphics::*;
+
+    let state = &simulation.game_state;
+    for i in 0..state.positions.len() {
+        let square = rectangle::square(0.0, 0.0, PLAYER_SIZE);
+        let (x, y) = state.positions[i];
+        let rotation = state.rotations[i];
+
+        let transform = transform
+            .trans(x, y)
+            .rot_rad(rotation)
+            .trans(-PLAYER_SIZE / 2.0, -PLAYER_SIZE / 2.0);
+        Rectangle::new(PLAYER_COLORS[i % PLAYER_COLORS.len()])
+            .draw(square, draw_state, transform, gl);
+    }
+}
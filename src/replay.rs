@@ -0,0 +1,634 @@
+use crate::box_game::{self, encoding, tuning::TuningSchedule, FrameRecord, InputFlags};
+use anyhow::Context;
+use boxgame_sim::versioned;
+use ggrs::Frame;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the replay format, written in front of the header. Older versions are migrated when loading.
+///
+/// - 1: initial format
+/// - 2: the header contains the state the replay starts from, for replays of resumed matches
+/// - 3: keyframes start with their state version, see `boxgame_sim::versioned`
+/// - 4: frames record the tuning changes that apply from them on
+pub const REPLAY_VERSION: u32 = 4;
+
+/// Metadata about a replay and the session it was recorded in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReplayHeader {
+    /// version of the game that recorded the replay
+    pub build_version: String,
+    pub input_layout_hash: u64,
+    pub player_names: Vec<String>,
+    pub num_players: usize,
+    pub local_players: usize,
+    pub input_size: usize,
+    pub fps: u64,
+    pub map_hash: u64,
+    /// recording date in seconds since the unix epoch
+    pub date: u64,
+    /// the serialized state the replay starts from, `None` for the starting arena
+    pub start_keyframe: Option<Vec<u8>>,
+}
+
+/// Header of version 1 replays, which always started in the starting arena.
+#[derive(Deserialize)]
+struct ReplayHeaderV1 {
+    build_version: String,
+    input_layout_hash: u64,
+    player_names: Vec<String>,
+    num_players: usize,
+    local_players: usize,
+    input_size: usize,
+    fps: u64,
+    map_hash: u64,
+    date: u64,
+}
+
+impl From<ReplayHeaderV1> for ReplayHeader {
+    fn from(header: ReplayHeaderV1) -> Self {
+        Self {
+            build_version: header.build_version,
+            input_layout_hash: header.input_layout_hash,
+            player_names: header.player_names,
+            num_players: header.num_players,
+            local_players: header.local_players,
+            input_size: header.input_size,
+            fps: header.fps,
+            map_hash: header.map_hash,
+            date: header.date,
+            start_keyframe: None,
+        }
+    }
+}
+
+/// Frames of replays before version 4, which did not record tuning changes.
+#[derive(Deserialize)]
+struct FrameRecordV3 {
+    frame: Frame,
+    inputs: Vec<Option<Vec<u8>>>,
+    checksum: u64,
+    keyframe: Option<Vec<u8>>,
+}
+
+impl From<FrameRecordV3> for FrameRecord {
+    fn from(record: FrameRecordV3) -> Self {
+        Self {
+            frame: record.frame,
+            inputs: record.inputs,
+            checksum: record.checksum,
+            keyframe: record.keyframe,
+            tuning: None,
+        }
+    }
+}
+
+impl ReplayHeader {
+    /// Creates a header for a replay recorded by this build right now.
+    pub fn new(
+        player_names: Vec<String>,
+        num_players: usize,
+        local_players: usize,
+        input_size: usize,
+        map_hash: u64,
+        start_keyframe: Option<Vec<u8>>,
+    ) -> Self {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        Self {
+            build_version: env!("CARGO_PKG_VERSION").to_string(),
+            input_layout_hash: box_game::input::layout_hash(),
+            player_names,
+            num_players,
+            local_players,
+            input_size,
+            fps: box_game::FPS,
+            map_hash,
+            date,
+            start_keyframe,
+        }
+    }
+
+    /// The recording date as `YYYY-MM-DD` (UTC).
+    pub fn formatted_date(&self) -> String {
+        // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = (self.date / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// Checks whether this build can play back the replay. `map_hash` is the hash of the arena it would be played in.
+    pub fn check_compatible(&self, map_hash: u64) -> Result<(), String> {
+        if self.input_layout_hash != box_game::input::layout_hash() {
+            return Err("the replay was recorded with a different input layout".to_string());
+        }
+        if self.fps != box_game::FPS {
+            return Err(format!(
+                "the replay was recorded at {} ticks per second, this build runs at {}",
+                self.fps,
+                box_game::FPS
+            ));
+        }
+        if self.map_hash != map_hash {
+            return Err("the replay was recorded in a different arena".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Replays are flushed to disk every this many frames, so a crash loses at most this many confirmed frames.
+const FLUSH_PERIOD: usize = 60;
+const ZSTD_LEVEL: i32 = 3;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Writes a replay: the format version and header, followed by one `FrameRecord` per confirmed frame.
+/// The whole stream is zstd compressed, since inputs are tiny and very repetitive.
+pub struct ReplayWriter {
+    writer: Box<dyn Write>,
+    unflushed_frames: usize,
+}
+
+impl ReplayWriter {
+    pub fn create(path: &Path, header: &ReplayHeader) -> anyhow::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        // finishes the zstd frame when dropped, replays cut short by a crash are still readable up to the last flush
+        let mut writer = zstd::Encoder::new(file, ZSTD_LEVEL)?.auto_finish();
+        encoding::serialize_into(&mut writer, &REPLAY_VERSION)?;
+        encoding::serialize_into(&mut writer, header)?;
+        writer.flush()?;
+        Ok(Self {
+            writer: Box::new(writer),
+            unflushed_frames: 0,
+        })
+    }
+
+    pub fn write_frames(&mut self, records: &[FrameRecord]) -> anyhow::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        for record in records {
+            encoding::serialize_into(&mut self.writer, record)?;
+        }
+        self.unflushed_frames += records.len();
+        if self.unflushed_frames >= FLUSH_PERIOD {
+            self.writer.flush()?;
+            self.unflushed_frames = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes out everything recorded so far, e.g. before copying the replay somewhere else.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.unflushed_frames = 0;
+        self.writer.flush()
+    }
+}
+
+/// Whether a replay was written before the format was versioned. Those start right away with the header, whose first
+/// field is the number of players as a u64. Versioned replays have the u32 version there, followed by the length of
+/// the build version as a u64, so bytes 4 to 8 are only zero in unversioned replays, which also were never compressed.
+fn is_unversioned(start: &[u8]) -> bool {
+    !start.starts_with(&ZSTD_MAGIC) && start.get(4..8) == Some(&[0; 4])
+}
+
+/// A replay loaded from disk.
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<FrameRecord>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        // replays written before compression was added are read as they are
+        let mut file = BufReader::new(File::open(path)?);
+        let start = file.fill_buf()?;
+        if is_unversioned(start) {
+            anyhow::bail!(
+                "the replay was recorded before replays were versioned and cannot be played back, record it again"
+            );
+        }
+        let mut reader: Box<dyn BufRead> = if start.starts_with(&ZSTD_MAGIC) {
+            Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?))
+        } else {
+            Box::new(file)
+        };
+        let version: u32 = encoding::deserialize_from(&mut reader)?;
+        let mut header: ReplayHeader = match version {
+            1 => encoding::deserialize_from::<_, ReplayHeaderV1>(&mut reader)?.into(),
+            2..=REPLAY_VERSION => encoding::deserialize_from(&mut reader)?,
+            _ => anyhow::bail!(
+                "unsupported replay version {}, this build reads versions 1 to {}",
+                version,
+                REPLAY_VERSION
+            ),
+        };
+        if header.num_players == 0 || header.local_players == 0 {
+            anyhow::bail!(
+                "invalid replay header: {} players with {} local players each",
+                header.num_players,
+                header.local_players
+            );
+        }
+        if header.input_size != box_game::INPUT_SIZE * header.local_players {
+            anyhow::bail!(
+                "invalid replay header: inputs of {} bytes for {} local players",
+                header.input_size,
+                header.local_players
+            );
+        }
+        if header.build_version != env!("CARGO_PKG_VERSION") {
+            tracing::warn!(
+                "the replay was recorded with version {}, this is version {}",
+                header.build_version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        let mut frames: Vec<FrameRecord> = Vec::new();
+        let read_record = |reader: &mut Box<dyn BufRead>| -> bincode::Result<FrameRecord> {
+            match version {
+                1..=3 => {
+                    encoding::deserialize_from::<_, FrameRecordV3>(reader).map(FrameRecord::from)
+                }
+                _ => encoding::deserialize_from(reader),
+            }
+        };
+        loop {
+            // the file ends after the last complete frame. A compressed replay cut short by a crash ends at the last
+            // flush without finishing its zstd frame, which the decoder reports as an unexpected end of file.
+            match reader.fill_buf() {
+                Ok(rest) if rest.is_empty() => break,
+                Ok(_) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error.into()),
+            }
+            let record = read_record(&mut reader)
+                .with_context(|| format!("cannot read the replay after {} frames", frames.len()))?;
+            if record.inputs.len() != header.num_players {
+                anyhow::bail!(
+                    "frame {} has inputs for {} players instead of {}",
+                    record.frame,
+                    record.inputs.len(),
+                    header.num_players
+                );
+            }
+            frames.push(record);
+        }
+        if version < 3 {
+            // keyframes without a state version
+            let migrate =
+                |keyframe: &mut Vec<u8>| *keyframe = versioned::from_unversioned(keyframe);
+            header.start_keyframe.iter_mut().for_each(migrate);
+            for record in &mut frames {
+                record.keyframe.iter_mut().for_each(migrate);
+            }
+        }
+        Ok(Self { header, frames })
+    }
+
+    /// The gameplay constants of every frame, as they changed during the match.
+    pub fn tuning(&self) -> TuningSchedule {
+        let mut schedule = TuningSchedule::default();
+        for record in &self.frames {
+            if let Some(tuning) = record.tuning {
+                schedule.schedule(record.frame, tuning);
+            }
+        }
+        schedule
+    }
+}
+
+/// The first point where two replays of the same match disagree.
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    /// the peers simulated different inputs, e.g. a bug in input handling or the netcode
+    Input { frame: Frame, peer: usize },
+    /// the inputs agree but the resulting states do not, i.e. the simulation is not deterministic
+    Simulation { frame: Frame },
+}
+
+/// Compares the frames both replays contain, in order, and returns the first difference.
+pub fn first_difference(a: &Replay, b: &Replay) -> Option<Difference> {
+    let b_frames: std::collections::HashMap<Frame, &FrameRecord> = b
+        .frames
+        .iter()
+        .map(|record| (record.frame, record))
+        .collect();
+    for record_a in &a.frames {
+        let record_b = match b_frames.get(&record_a.frame) {
+            Some(record_b) => record_b,
+            None => continue,
+        };
+        if let Some(peer) = (0..record_a.inputs.len())
+            .find(|&peer| record_a.inputs.get(peer) != record_b.inputs.get(peer))
+        {
+            return Some(Difference::Input {
+                frame: record_a.frame,
+                peer,
+            });
+        }
+        if record_a.checksum != record_b.checksum {
+            return Some(Difference::Simulation {
+                frame: record_a.frame,
+            });
+        }
+    }
+    None
+}
+
+/// One player's input during one frame and the state of their box after it, as exported for external analysis.
+#[derive(Serialize)]
+struct InputRow {
+    frame: Frame,
+    player: usize,
+    connected: bool,
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+    dash: bool,
+    stick_x: i8,
+    stick_y: i8,
+    x: f64,
+    y: f64,
+    velocity_x: f64,
+    velocity_y: f64,
+    rotation: f64,
+    checksum: u64,
+}
+
+/// Exports the inputs of every player and frame along with the resulting box states, as JSON if the path ends in
+/// `.json` and as CSV otherwise. The states are simulated again from the inputs.
+pub fn export_inputs(replay: &Replay, path: &Path) -> anyhow::Result<()> {
+    let header = &replay.header;
+    let mut game = box_game::Simulation::new(header.num_players, header.local_players);
+    game.set_tuning(replay.tuning());
+    if let Some(keyframe) = &header.start_keyframe {
+        game.load_keyframe(keyframe)?;
+    }
+    let mut rows = Vec::new();
+    for record in &replay.frames {
+        game.simulate_frame(record.inputs.clone())?;
+        for player in 0..header.num_players * header.local_players {
+            let peer = player / header.local_players;
+            let slot = player % header.local_players;
+            let input = match record.inputs.get(peer) {
+                Some(Some(bytes)) => {
+                    let bytes =
+                        bytes.get(slot * box_game::INPUT_SIZE..(slot + 1) * box_game::INPUT_SIZE);
+                    Some(box_game::input::decode(bytes.unwrap_or_default()))
+                }
+                _ => None,
+            };
+            let buttons = input.map(|input| input.buttons).unwrap_or_default();
+            let state = game.player_state(player).unwrap_or_default();
+            rows.push(InputRow {
+                frame: record.frame,
+                player,
+                connected: input.is_some(),
+                up: buttons.contains(InputFlags::UP),
+                down: buttons.contains(InputFlags::DOWN),
+                left: buttons.contains(InputFlags::LEFT),
+                right: buttons.contains(InputFlags::RIGHT),
+                dash: buttons.contains(InputFlags::DASH),
+                stick_x: input.map(|input| input.stick_x).unwrap_or(0),
+                stick_y: input.map(|input| input.stick_y).unwrap_or(0),
+                x: state.position.0,
+                y: state.position.1,
+                velocity_x: state.velocity.0,
+                velocity_y: state.velocity.1,
+                rotation: state.rotation,
+                checksum: record.checksum,
+            });
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::to_writer_pretty(&mut writer, &rows)?;
+    } else {
+        writeln!(
+            writer,
+            "frame,player,connected,up,down,left,right,dash,stick_x,stick_y,x,y,velocity_x,velocity_y,rotation,checksum"
+        )?;
+        for row in &rows {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                row.frame,
+                row.player,
+                row.connected,
+                row.up,
+                row.down,
+                row.left,
+                row.right,
+                row.dash,
+                row.stick_x,
+                row.stick_y,
+                row.x,
+                row.y,
+                row.velocity_x,
+                row.velocity_y,
+                row.rotation,
+                row.checksum
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Replays of older versions, checked in as that version wrote them: `v0.replay` from before replays were
+    /// versioned, `v1.replay` uncompressed, `v2.replay` zstd compressed. The keyframes of the versioned ones are the
+    /// state of `tests/golden/two_players.bin`, without the state version.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/replays")
+            .join(name)
+    }
+
+    fn header() -> ReplayHeader {
+        ReplayHeader::new(
+            vec!["alice".to_string(), "bob".to_string()],
+            2,
+            1,
+            box_game::INPUT_SIZE,
+            0x5678,
+            None,
+        )
+    }
+
+    fn record(frame: Frame, inputs: Vec<Option<Vec<u8>>>, checksum: u64) -> FrameRecord {
+        FrameRecord {
+            frame,
+            inputs,
+            checksum,
+            keyframe: None,
+            tuning: None,
+        }
+    }
+
+    #[test]
+    fn old_versions_are_migrated() {
+        let golden =
+            fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/two_players.bin"))
+                .unwrap();
+        // keyframes get the state version in front, so they decode as version 1 states
+        let assert_migrated = |keyframe: &[u8]| {
+            assert_eq!(
+                versioned::encode(&versioned::decode(keyframe).unwrap()).unwrap(),
+                golden
+            );
+        };
+        for name in &["v1.replay", "v2.replay"] {
+            let replay = Replay::load(&fixture(name)).unwrap();
+            let header = &replay.header;
+            assert_eq!(header.player_names, ["alice", "bob"]);
+            assert_eq!(
+                (header.num_players, header.local_players, header.input_size),
+                (2, 1, 3)
+            );
+            assert_eq!((header.map_hash, header.date), (0x5678, 1_700_000_000));
+            assert_eq!(replay.frames.len(), 3);
+            assert_eq!(
+                replay.frames[2],
+                record(3, vec![Some(vec![2, 0, 0]), None], 333)
+            );
+            assert!(replay.frames.iter().all(|record| record.tuning.is_none()));
+            assert_migrated(replay.frames[1].keyframe.as_ref().unwrap());
+            match *name {
+                "v1.replay" => assert_eq!(header.start_keyframe, None),
+                _ => assert_migrated(header.start_keyframe.as_ref().unwrap()),
+            }
+        }
+    }
+
+    #[test]
+    fn unversioned_replays_are_rejected() {
+        let error = Replay::load(&fixture("v0.replay")).err().unwrap();
+        assert!(error.to_string().contains("before replays were versioned"));
+    }
+
+    #[test]
+    fn truncated_replays() {
+        let frames = vec![
+            record(1, vec![Some(vec![1, 0, 0]), Some(vec![0, 0, 0])], 1),
+            record(2, vec![Some(vec![1, 0, 0]), None], 2),
+        ];
+        let mut bytes = encoding::serialize(&REPLAY_VERSION).unwrap();
+        bytes.extend(encoding::serialize(&header()).unwrap());
+        bytes.extend(encoding::serialize(&frames[0]).unwrap());
+        let boundary = bytes.len();
+        bytes.extend(encoding::serialize(&frames[1]).unwrap());
+        let path = std::env::temp_dir().join(format!("replay-cut-{}.replay", std::process::id()));
+
+        // cut between two frames
+        fs::write(&path, &bytes[..boundary]).unwrap();
+        assert_eq!(Replay::load(&path).unwrap().frames, frames[..1]);
+        // cut in the middle of a frame
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let error = Replay::load(&path).err().unwrap();
+        assert!(error.to_string().contains("after 1 frames"));
+
+        // a compressed replay as a crash leaves it: flushed, but without the end of the zstd frame
+        let mut writer = ReplayWriter::create(&path, &header()).unwrap();
+        writer.write_frames(&frames).unwrap();
+        writer.flush().unwrap();
+        let flushed = fs::read(&path).unwrap();
+        drop(writer);
+        fs::write(&path, flushed).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.frames, frames);
+    }
+
+    #[test]
+    fn compressed_and_uncompressed_replays_load_the_same() {
+        let uncompressed = fs::read(fixture("v1.replay")).unwrap();
+        assert!(!uncompressed.starts_with(&ZSTD_MAGIC));
+        let path = std::env::temp_dir().join(format!("replay-test-{}.replay", std::process::id()));
+        fs::write(
+            &path,
+            zstd::encode_all(&uncompressed[..], ZSTD_LEVEL).unwrap(),
+        )
+        .unwrap();
+        let compressed = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let replay = Replay::load(&fixture("v1.replay")).unwrap();
+        assert_eq!(compressed.header, replay.header);
+        assert_eq!(compressed.frames, replay.frames);
+    }
+
+    #[test]
+    fn written_replays_load_the_same() {
+        let mut frames = vec![
+            record(1, vec![Some(vec![1, 0, 0]), Some(vec![0, 0, 0])], 1),
+            record(2, vec![Some(vec![1, 0, 0]), None], 2),
+        ];
+        frames[1].tuning = Some(box_game::Tuning::DEFAULT);
+        let path = std::env::temp_dir().join(format!("replay-write-{}.replay", std::process::id()));
+        let mut writer = ReplayWriter::create(&path, &header()).unwrap();
+        writer.write_frames(&frames).unwrap();
+        drop(writer);
+        assert!(fs::read(&path).unwrap().starts_with(&ZSTD_MAGIC));
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.header, header());
+        assert_eq!(replay.frames, frames);
+        assert_eq!(
+            replay.tuning().change_at(2),
+            Some(&box_game::Tuning::DEFAULT)
+        );
+    }
+
+    #[test]
+    fn first_difference_cases() {
+        let replay = |frames: Vec<FrameRecord>| Replay {
+            header: header(),
+            frames,
+        };
+        let frames = vec![
+            record(1, vec![Some(vec![1, 0, 0]), Some(vec![0, 0, 0])], 1),
+            record(2, vec![Some(vec![1, 0, 0]), Some(vec![2, 0, 0])], 2),
+            record(3, vec![Some(vec![1, 0, 0]), None], 3),
+        ];
+        let a = replay(frames.clone());
+        assert_eq!(first_difference(&a, &replay(frames.clone())), None);
+        // only the frames both replays contain are compared
+        assert_eq!(first_difference(&a, &replay(frames[1..].to_vec())), None);
+
+        let mut input = frames.clone();
+        input[1].inputs[1] = None;
+        input[2].checksum = 4;
+        assert_eq!(
+            first_difference(&a, &replay(input)),
+            Some(Difference::Input { frame: 2, peer: 1 })
+        );
+        let mut checksum = frames;
+        checksum[2].checksum = 4;
+        assert_eq!(
+            first_difference(&a, &replay(checksum)),
+            Some(Difference::Simulation { frame: 3 })
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use ggrs::Frame;
+use std::collections::VecDeque;
+
+/// Slow-motion speeds the local playback can run at, in frames per tick.
+pub const SPEEDS: [f64; 4] = [0.125, 0.25, 0.5, 1.0];
+
+/// The states of the last frames a spectator simulated, as keyframes. Spectators only simulate confirmed frames, so
+/// these are final and can be shown again while the match goes on. A spectator catching up advances several frames in
+/// one tick, only the last of them is kept then.
+///
+/// With a broadcast delay, the newest `delay` frames are held back: `live` and the playback never get to them, so a
+/// public view of the match is of no use to the players watching it.
+pub struct RewindBuffer {
+    capacity: usize,
+    delay: Frame,
+    /// in frame order
+    states: VecDeque<Buffered>,
+}
+
+struct Buffered {
+    frame: Frame,
+    keyframe: Vec<u8>,
+    /// the inputs the frame was simulated with, for input displays
+    inputs: Vec<Option<Vec<u8>>>,
+}
+
+impl RewindBuffer {
+    /// Keeps `capacity` frames to rewind through, besides the `delay` held back ones.
+    pub fn new(capacity: usize, delay: Frame) -> Self {
+        let capacity = capacity + delay as usize;
+        Self {
+            capacity,
+            delay,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Adds the state of a frame after the buffered ones, with the inputs it was simulated with. `write` fills the
+    /// keyframe buffer, which is recycled from the oldest state once the buffer is full.
+    pub fn push(
+        &mut self,
+        frame: Frame,
+        inputs: &[Option<Vec<u8>>],
+        write: impl FnOnce(&mut Vec<u8>),
+    ) {
+        let mut buffered = match self.states.len() >= self.capacity {
+            true => self.states.pop_front(),
+            false => None,
+        }
+        .unwrap_or(Buffered {
+            frame,
+            keyframe: Vec::new(),
+            inputs: Vec::new(),
+        });
+        buffered.frame = frame;
+        buffered.keyframe.clear();
+        write(&mut buffered.keyframe);
+        buffered.inputs.clear();
+        buffered.inputs.extend(inputs.iter().cloned());
+        self.states.push_back(buffered);
+    }
+
+    /// The state of `frame`, or of the closest buffered frame before it. `None` if it is older than the buffer.
+    pub fn get(&self, frame: Frame) -> Option<&[u8]> {
+        self.find(frame)
+            .map(|buffered| buffered.keyframe.as_slice())
+    }
+
+    /// The inputs of the state `get` returns for `frame`.
+    pub fn inputs(&self, frame: Frame) -> Option<&[Option<Vec<u8>>]> {
+        self.find(frame).map(|buffered| buffered.inputs.as_slice())
+    }
+
+    fn find(&self, frame: Frame) -> Option<&Buffered> {
+        let after = self
+            .states
+            .partition_point(|buffered| buffered.frame <= frame);
+        self.states.get(after.checked_sub(1)?)
+    }
+
+    /// The newest frame that may be shown, `None` until more than the delay is buffered.
+    pub fn live(&self) -> Option<Frame> {
+        let oldest = self.states.front()?.frame;
+        let newest = self.states.back()?.frame;
+        Some(newest - self.delay).filter(|&live| live >= oldest)
+    }
+
+    /// The oldest buffered frame and the live one.
+    pub fn range(&self) -> Option<(Frame, Frame)> {
+        Some((self.states.front()?.frame, self.live()?))
+    }
+}
+
+/// Local playback of buffered frames while the spectated match goes on underneath.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+    /// the frame shown
+    pub frame: Frame,
+    pub paused: bool,
+    /// index into `SPEEDS`
+    pub speed_index: usize,
+    /// fractional frames owed at slow speeds
+    pending: f64,
+}
+
+impl Playback {
+    /// Starts playing back `frames` frames before the live one, or from the oldest buffered one.
+    pub fn rewind(buffer: &RewindBuffer, frames: Frame) -> Option<Self> {
+        let (oldest, newest) = buffer.range()?;
+        Some(Self {
+            frame: (newest - frames).max(oldest),
+            paused: false,
+            speed_index: SPEEDS.len() - 1,
+            pending: 0.0,
+        })
+    }
+
+    /// Seeks `frames` further back, staying in the buffer.
+    pub fn seek_back(&mut self, buffer: &RewindBuffer, frames: Frame) {
+        if let Some((oldest, _)) = buffer.range() {
+            self.frame = (self.frame - frames).max(oldest);
+        }
+    }
+
+    /// Shows the next frame, for stepping through while paused.
+    pub fn step(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn slower(&mut self) {
+        self.speed_index = self.speed_index.saturating_sub(1);
+    }
+
+    pub fn faster(&mut self) {
+        self.speed_index = (self.speed_index + 1).min(SPEEDS.len() - 1);
+    }
+
+    /// Advances the playback by one tick. Returns false once it caught up with the live frame while playing, then it is
+    /// over.
+    pub fn tick(&mut self, buffer: &RewindBuffer) -> bool {
+        let (oldest, newest) = match buffer.range() {
+            Some(range) => range,
+            None => return false,
+        };
+        if !self.paused {
+            self.pending += SPEEDS[self.speed_index];
+            let frames = self.pending as Frame;
+            self.pending -= frames as f64;
+            self.frame += frames;
+        }
+        // the buffer keeps moving while paused, the shown frame must not fall out of it
+        self.frame = self.frame.clamp(oldest, newest);
+        self.paused || self.frame < newest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(frames: std::ops::RangeInclusive<Frame>) -> RewindBuffer {
+        let mut buffer = RewindBuffer::new(100, 0);
+        for frame in frames {
+            buffer.push(frame, &[], |keyframe| keyframe.push(frame as u8));
+        }
+        buffer
+    }
+
+    #[test]
+    fn keeps_the_last_frames() {
+        let buffer = buffer(1..=150);
+        assert_eq!(buffer.range(), Some((51, 150)));
+        assert_eq!(buffer.get(51), Some(&[51][..]));
+        assert_eq!(buffer.get(150), Some(&[150][..]));
+        assert_eq!(buffer.get(50), None);
+        assert_eq!(buffer.get(151), Some(&[150][..]));
+    }
+
+    #[test]
+    fn skipped_frames_show_the_frame_before() {
+        let mut buffer = buffer(1..=10);
+        buffer.push(13, &[Some(vec![1])], |keyframe| keyframe.push(13));
+        assert_eq!(buffer.get(12), Some(&[10][..]));
+        assert_eq!(buffer.get(13), Some(&[13][..]));
+        assert_eq!(buffer.inputs(13), Some(&[Some(vec![1])][..]));
+    }
+
+    #[test]
+    fn delay_holds_back_the_newest_frames() {
+        let mut buffer = RewindBuffer::new(10, 5);
+        for frame in 1..=5 {
+            buffer.push(frame, &[], |keyframe| keyframe.push(frame as u8));
+        }
+        assert_eq!(buffer.live(), None);
+        for frame in 6..=30 {
+            buffer.push(frame, &[], |keyframe| keyframe.push(frame as u8));
+        }
+        assert_eq!(buffer.range(), Some((16, 25)));
+        let mut playback = Playback::rewind(&buffer, 0).unwrap();
+        playback.step();
+        assert!(!playback.tick(&buffer));
+        assert_eq!(playback.frame, 25);
+    }
+
+    #[test]
+    fn slow_motion_catches_up_with_live() {
+        let mut live = buffer(1..=100);
+        let mut playback = Playback::rewind(&live, 10).unwrap();
+        assert_eq!(playback.frame, 90);
+        playback.slower();
+        playback.slower();
+        // a quarter of the speed, while the live match keeps going
+        for tick in 0..4 {
+            assert!(playback.tick(&live));
+            live.push(101 + tick, &[], |_| ());
+        }
+        assert_eq!(playback.frame, 91);
+        playback.faster();
+        playback.faster();
+        let mut ticks = 0;
+        while playback.tick(&live) {
+            ticks += 1;
+        }
+        assert_eq!(ticks, 12);
+        assert_eq!(playback.frame, 104);
+        // pausing at the live frame keeps the playback going
+        playback.paused = true;
+        assert!(playback.tick(&live));
+        // seeking never leaves the buffer
+        playback.seek_back(&live, 1000);
+        assert_eq!(playback.frame, 5);
+    }
+}
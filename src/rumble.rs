@@ -0,0 +1,67 @@
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+use gilrs::Gilrs;
+
+const RUMBLE_DURATION_MS: u32 = 150;
+
+/// Rumbles all connected gamepads that support force feedback.
+pub struct Rumble {
+    gilrs: Gilrs,
+    /// strength between 0.0 (off) and 1.0
+    intensity: f32,
+    /// effects stop playing when dropped, so the current one is kept around
+    effect: Option<Effect>,
+}
+
+impl Rumble {
+    /// Returns `None` if gamepads are not supported on this platform.
+    pub fn new(intensity: f32) -> Option<Self> {
+        let gilrs = Gilrs::new().ok()?;
+        Some(Self {
+            gilrs,
+            intensity: intensity.max(0.0).min(1.0),
+            effect: None,
+        })
+    }
+
+    /// Processes gamepad events, needs to be called regularly to keep the list of gamepads up to date.
+    pub fn update(&mut self) {
+        while self.gilrs.next_event().is_some() {}
+    }
+
+    pub fn play(&mut self) {
+        if self.intensity <= 0.0 {
+            return;
+        }
+        let gamepads: Vec<_> = self
+            .gilrs
+            .gamepads()
+            .filter(|(_, gamepad)| gamepad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect();
+        if gamepads.is_empty() {
+            return;
+        }
+
+        let magnitude = (self.intensity * u16::MAX as f32) as u16;
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude },
+                scheduling: Replay {
+                    play_for: Ticks::from_ms(RUMBLE_DURATION_MS),
+                    ..Default::default()
+                },
+                envelope: Default::default(),
+            })
+            .gamepads(&gamepads)
+            .finish(&mut self.gilrs);
+        match effect {
+            Ok(effect) => {
+                if let Err(e) = effect.play() {
+                    tracing::warn!("Could not play rumble: {}", e);
+                }
+                self.effect = Some(effect);
+            }
+            Err(e) => tracing::warn!("Could not create rumble: {}", e),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::box_game::{self, Input, InputFlags, Simulation};
+use ggrs::Frame;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Allowed difference for expected floating point values that do not give a tolerance.
+const DEFAULT_TOLERANCE: f64 = 0.001;
+
+/// A value of the game state a scenario makes assertions about.
+#[derive(Clone, Copy, Debug)]
+enum Expectation {
+    Position { x: f64, y: f64 },
+    Velocity { x: f64, y: f64 },
+    Rotation(f64),
+}
+
+/// One line of a scenario.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    /// from `frame` on, the player holds this input (`None` for a disconnected player)
+    Input {
+        frame: Frame,
+        player: usize,
+        input: Option<Input>,
+    },
+    /// after simulating `frame`, the value has to match within the tolerance
+    Expect {
+        frame: Frame,
+        player: usize,
+        expectation: Expectation,
+        tolerance: f64,
+    },
+    /// after simulating `frame`, the game state has to have this checksum
+    Checksum { frame: Frame, checksum: u64 },
+}
+
+/// A scripted match with assertions about its course, so gameplay bugs can be captured as repeatable tests.
+///
+/// Scenarios are text files with one command per line, `#` starts a comment:
+///
+/// ```text
+/// players 2                        # number of peers, default 2
+/// local 1                          # boxes per peer, default 1
+/// input 0 0 up,right               # from frame 0 on, box 0 holds up and right
+/// input 0 1 dash stick=-4,0        # box 1 dashes every frame and holds the stick to the left
+/// input 120 0 none                 # box 0 releases everything at frame 120
+/// input 300 1 disconnected         # the peer of box 1 disconnects at frame 300
+/// expect 600 position 0 300 400 5  # after frame 600, box 0 is at (300, 400), give or take 5
+/// expect 600 velocity 1 0 0
+/// expect 600 rotation 0 3.14 0.01
+/// checksum 600 12345
+/// ```
+///
+/// The last number of an `expect` line is the optional tolerance.
+pub struct Scenario {
+    pub path: PathBuf,
+    num_peers: usize,
+    local_players: usize,
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(path, &text)
+    }
+
+    /// Parses the text of a scenario, `path` only names it in errors and failures.
+    pub fn parse(path: &Path, text: &str) -> Result<Self, String> {
+        let mut scenario = Self {
+            path: path.to_path_buf(),
+            num_peers: 2,
+            local_players: 1,
+            steps: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            scenario
+                .parse_line(&words)
+                .ok_or_else(|| format!("{}:{}: invalid line", path.display(), number + 1))?;
+        }
+        Ok(scenario)
+    }
+
+    fn parse_line(&mut self, words: &[&str]) -> Option<()> {
+        let number = |i: usize| -> Option<f64> { words.get(i)?.parse().ok() };
+        match words[0] {
+            "players" => self.num_peers = words.get(1)?.parse().ok()?,
+            "local" => self.local_players = words.get(1)?.parse().ok()?,
+            "input" => self.steps.push(Step::Input {
+                frame: words.get(1)?.parse().ok()?,
+                player: words.get(2)?.parse().ok()?,
+                input: parse_input(words.get(3..)?)?,
+            }),
+            "expect" => {
+                let (expectation, tolerance_index) = match *words.get(2)? {
+                    "position" => (
+                        Expectation::Position {
+                            x: number(4)?,
+                            y: number(5)?,
+                        },
+                        6,
+                    ),
+                    "velocity" => (
+                        Expectation::Velocity {
+                            x: number(4)?,
+                            y: number(5)?,
+                        },
+                        6,
+                    ),
+                    "rotation" => (Expectation::Rotation(number(4)?), 5),
+                    _ => return None,
+                };
+                let tolerance = match words.get(tolerance_index) {
+                    Some(_) => number(tolerance_index)?,
+                    None => DEFAULT_TOLERANCE,
+                };
+                self.steps.push(Step::Expect {
+                    frame: words.get(1)?.parse().ok()?,
+                    player: words.get(3)?.parse().ok()?,
+                    expectation,
+                    tolerance,
+                });
+            }
+            "checksum" => self.steps.push(Step::Checksum {
+                frame: words.get(1)?.parse().ok()?,
+                checksum: words.get(2)?.parse().ok()?,
+            }),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Plays the scenario without a window and returns every failed assertion.
+    pub fn run(&self) -> Vec<String> {
+        let num_players = self.num_peers * self.local_players;
+        let mut game = Simulation::new(self.num_peers, self.local_players);
+        let mut held: Vec<Option<Input>> = vec![Some(Input::default()); num_players];
+        let last_frame = self
+            .steps
+            .iter()
+            .map(|step| match *step {
+                Step::Input { frame, .. }
+                | Step::Expect { frame, .. }
+                | Step::Checksum { frame, .. } => frame,
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut failures = Vec::new();
+        for frame in 0..=last_frame {
+            // state frame 0 is the starting arena, every later frame is simulated with the inputs held during it
+            if frame > 0 {
+                for step in &self.steps {
+                    if let Step::Input {
+                        frame: input_frame,
+                        player,
+                        input,
+                    } = *step
+                    {
+                        if input_frame == frame - 1 && player < num_players {
+                            held[player] = input;
+                        }
+                    }
+                }
+                let inputs = (0..self.num_peers)
+                    .map(|peer| {
+                        let slots =
+                            &held[peer * self.local_players..(peer + 1) * self.local_players];
+                        // a peer is disconnected as soon as one of its boxes is
+                        let mut bytes = Vec::new();
+                        for input in slots {
+                            bytes.extend_from_slice(&box_game::input::encode((*input)?));
+                        }
+                        Some(bytes)
+                    })
+                    .collect();
+                if let Err(e) = game.simulate_frame(inputs) {
+                    failures.push(format!("frame {}: {}", frame, e));
+                    return failures;
+                }
+            }
+            for step in self.steps.iter() {
+                if let Some(failure) = check(&game, frame, step) {
+                    failures.push(failure);
+                }
+            }
+        }
+        failures
+    }
+}
+
+/// Checks a step that applies to the given frame, returning a description if it failed.
+fn check(game: &Simulation, frame: Frame, step: &Step) -> Option<String> {
+    match *step {
+        Step::Expect {
+            frame: expected_frame,
+            player,
+            expectation,
+            tolerance,
+        } if expected_frame == frame => {
+            let state = match game.player_state(player) {
+                Some(state) => state,
+                None => return Some(format!("frame {}: there is no player {}", frame, player)),
+            };
+            let (name, expected, actual) = match expectation {
+                Expectation::Position { x, y } => (
+                    "position",
+                    vec![x, y],
+                    vec![state.position.0, state.position.1],
+                ),
+                Expectation::Velocity { x, y } => (
+                    "velocity",
+                    vec![x, y],
+                    vec![state.velocity.0, state.velocity.1],
+                ),
+                Expectation::Rotation(rotation) => {
+                    ("rotation", vec![rotation], vec![state.rotation])
+                }
+            };
+            let matches = expected
+                .iter()
+                .zip(actual.iter())
+                .all(|(expected, actual)| (expected - actual).abs() <= tolerance);
+            (!matches).then(|| {
+                format!(
+                    "frame {}: player {} {} is {:?}, expected {:?} within {}",
+                    frame, player, name, actual, expected, tolerance
+                )
+            })
+        }
+        Step::Checksum {
+            frame: expected_frame,
+            checksum,
+        } if expected_frame == frame => {
+            let actual = game.last_checksum().1;
+            (actual != checksum).then(|| {
+                format!(
+                    "frame {}: checksum is {}, expected {}",
+                    frame, actual, checksum
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parses the input of an `input` line: comma separated buttons, `none` or `disconnected`, then an optional stick.
+fn parse_input(words: &[&str]) -> Option<Option<Input>> {
+    let mut input = Input::default();
+    match *words.first()? {
+        "disconnected" => return Some(None),
+        "none" => (),
+        buttons => {
+            for button in buttons.split(',') {
+                input.buttons |= match button {
+                    "up" => InputFlags::UP,
+                    "down" => InputFlags::DOWN,
+                    "left" => InputFlags::LEFT,
+                    "right" => InputFlags::RIGHT,
+                    "dash" => InputFlags::DASH,
+                    _ => return None,
+                };
+            }
+        }
+    }
+    if let Some(stick) = words.get(1) {
+        let (x, y) = stick.strip_prefix("stick=")?.split_once(',')?;
+        input.stick_x = x.parse().ok()?;
+        input.stick_y = y.parse().ok()?;
+    }
+    Some(Some(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Scenario, String> {
+        Scenario::parse(Path::new("test.txt"), text)
+    }
+
+    #[test]
+    fn bundled_scenarios_pass() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let mut paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "no scenarios in {}", dir.display());
+        for path in paths {
+            let scenario = Scenario::load(&path).unwrap();
+            assert_eq!(scenario.run(), Vec::<String>::new(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn failed_assertions_are_reported() {
+        let scenario =
+            parse("expect 1 position 0 0 0\nexpect 1 position 5 0 0\nchecksum 1 0").unwrap();
+        assert_eq!(scenario.run().len(), 3);
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(
+            parse("# only a comment\n\ninput 0 0 up,right stick=-4,0 # trailing comment").is_ok()
+        );
+        let malformed = [
+            "jump 0 0",
+            "players",
+            "players two",
+            "input 0 0",
+            "input x 0 up",
+            "input 0 0 up,jump",
+            "input 0 0 up stick=1",
+            "input 0 0 up stick=1,x",
+            "input 0 0 up 1,1",
+            "expect 10 position 0 1",
+            "expect 10 speed 0 1 1",
+            "expect 10 rotation 0 1 tight",
+            "checksum 10",
+            "checksum 10 -1",
+        ];
+        for line in malformed.iter() {
+            let error = parse(&format!("players 2\n{}", line)).err();
+            assert_eq!(
+                error.as_deref(),
+                Some("test.txt:2: invalid line"),
+                "{}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::box_game::{
+    input, FrameRecord, HitEvent, InputFlags, Simulation, StateCopy, Tuning, INPUT_SIZE,
+};
+use crate::game_loop::{Advance, GameLoop};
+use crate::input_recording::InputPlayback;
+use crate::notifications::Notification;
+use crate::timestep::FixedTimestep;
+use boxgame_net::metrics::BurstWindow;
+use ggrs::{Frame, P2PSession, PlayerHandle};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Packets are processed at least this often, between ticks as well.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// With fake lag, incoming packets are only processed this often.
+const FAKE_LAG: Duration = Duration::from_millis(100);
+/// Runtime metrics are written this often while the session runs, and once more when it ends.
+const METRICS_PERIOD: Duration = Duration::from_secs(60);
+/// After `Command::Stop` with linger, the session keeps answering for this long.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// What the render thread tells the simulation thread.
+pub enum Command {
+    /// the encoded input of all local players, sent from the next tick on
+    Input(Vec<u8>),
+    SetFrameDelay(u32),
+    /// hold back polling, so incoming packets are only processed every `FAKE_LAG`
+    FakeLag(bool),
+    /// in frame-step mode, the simulation only advances once per `Step`
+    FrameStep(bool),
+    Step,
+    StressSaveLoad,
+    /// disconnects a remote player, e.g. because it announced that it is leaving
+    Disconnect(PlayerHandle),
+    /// simulates the given frame and all later ones with new gameplay constants
+    ScheduleTuning(Frame, Tuning),
+    /// ends the session. With `linger`, it keeps answering for a moment first, so the other peer receives our last
+    /// inputs and can confirm its frames too.
+    Stop {
+        linger: bool,
+    },
+}
+
+/// What the simulation thread reports after each tick that advanced the game.
+pub struct Update {
+    /// the current state, to load into the render thread's copy of the simulation
+    pub state: StateCopy,
+    /// the frame the input was sent for, along with the input
+    pub sent_input: (Frame, Vec<u8>),
+    pub confirmed_frames: Vec<FrameRecord>,
+    pub confirmed_hits: Vec<HitEvent>,
+    pub last_save_size: usize,
+    pub held_state_bytes: usize,
+    /// the cost of the request bursts in the last complete second
+    pub burst_window: Option<BurstWindow>,
+}
+
+/// The settings of the simulation thread.
+pub struct SimConfig {
+    pub fps: u64,
+    pub local_handle: PlayerHandle,
+    pub remote_handle: PlayerHandle,
+    /// size of the encoded input of all local players
+    pub input_size: usize,
+    /// with a playback, the local inputs come from a recording instead of `Command::Input`
+    pub playback: Option<InputPlayback>,
+    /// runtime metrics are written to this JSON file
+    pub metrics_path: Option<PathBuf>,
+}
+
+/// The GGRS session and the simulation, ticking on their own thread. The render loop only sends commands and
+/// receives updates, so a slow frame on the render thread does not delay polling or advancing the session.
+pub struct SimThread {
+    commands: Sender<Command>,
+    updates: Receiver<Update>,
+    notifications: Receiver<Notification>,
+    handle: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl SimThread {
+    pub fn spawn(sess: P2PSession, game: Simulation, config: SimConfig) -> Self {
+        let (commands, command_receiver) = mpsc::channel();
+        let (update_sender, updates) = mpsc::channel();
+        let (notification_sender, notifications) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || {
+                run(
+                    sess,
+                    game,
+                    config,
+                    command_receiver,
+                    update_sender,
+                    notification_sender,
+                )
+            })
+            .expect("cannot spawn the simulation thread");
+        Self {
+            commands,
+            updates,
+            notifications,
+            handle: Some(handle),
+        }
+    }
+
+    /// Sends a command. Commands to a thread that has already ended are dropped, `finish` reports why it ended.
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+
+    /// The updates since the last call, or `None` once the thread has ended.
+    pub fn updates(&mut self) -> Option<Vec<Update>> {
+        let mut updates = Vec::new();
+        loop {
+            match self.updates.try_recv() {
+                Ok(update) => updates.push(update),
+                Err(TryRecvError::Empty) => return Some(updates),
+                Err(TryRecvError::Disconnected) if updates.is_empty() => return None,
+                Err(TryRecvError::Disconnected) => return Some(updates),
+            }
+        }
+    }
+
+    /// The session events and non-fatal errors since the last call, to show to the player.
+    pub fn notifications(&self) -> Vec<Notification> {
+        self.notifications.try_iter().collect()
+    }
+
+    /// Stops the thread if it still runs and returns the error it ended with, if any.
+    pub fn finish(mut self, linger: bool) -> anyhow::Result<()> {
+        self.send(Command::Stop { linger });
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => anyhow::bail!("the simulation thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SimThread {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.send(Command::Stop { linger: false });
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Buttons that act once per press, like dashing, instead of for as long as they are held.
+const PRESS_BUTTONS: InputFlags = InputFlags::DASH;
+
+/// Keeps the presses of an input that no tick has sent yet. They would be lost if the render thread sends two inputs
+/// between ticks.
+fn merge_unsent(unsent: &[u8], input: &mut [u8]) {
+    for (old, new) in unsent.chunks(INPUT_SIZE).zip(input.chunks_mut(INPUT_SIZE)) {
+        let mut merged = input::decode(new);
+        merged.buttons |= input::decode(old).buttons & PRESS_BUTTONS;
+        new.copy_from_slice(&input::encode(merged));
+    }
+}
+
+/// The input sent on every tick. The render thread hands every press over once, it is latched here until a tick that
+/// sent it was accepted by the session, and never sent again after that.
+struct LocalInput {
+    bytes: Vec<u8>,
+}
+
+impl LocalInput {
+    /// No buttons until the render thread sends the first input.
+    fn new(input_size: usize) -> Self {
+        Self {
+            bytes: vec![0; input_size],
+        }
+    }
+
+    /// Takes a new input from the render thread, keeping the presses not sent yet.
+    fn receive(&mut self, mut input: Vec<u8>) {
+        merge_unsent(&self.bytes, &mut input);
+        self.bytes = input;
+    }
+
+    fn current(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The session accepted a tick with the current input: the presses are used up, the held buttons stay until the
+    /// next input arrives, e.g. during a slow render frame.
+    fn accepted(&mut self) {
+        for bytes in self.bytes.chunks_mut(INPUT_SIZE) {
+            let mut held = input::decode(bytes);
+            held.buttons.remove(PRESS_BUTTONS);
+            bytes.copy_from_slice(&input::encode(held));
+        }
+    }
+}
+
+fn run(
+    mut sess: P2PSession,
+    mut game: Simulation,
+    config: SimConfig,
+    commands: Receiver<Command>,
+    updates: Sender<Update>,
+    notifications: Sender<Notification>,
+) -> anyhow::Result<()> {
+    // the render thread shows and logs them, if it is gone nobody is left to tell
+    let notify = |notification| {
+        let _ = notifications.send(notification);
+    };
+    let mut game_loop = GameLoop::default();
+    let mut timestep = FixedTimestep::new(config.fps, Instant::now());
+    let mut local_input = LocalInput::new(config.input_size);
+    let mut fake_lag = false;
+    let mut frame_step = false;
+    let mut step_requested = false;
+    let mut last_poll = Instant::now();
+    let mut last_metrics_dump = Instant::now();
+
+    let linger = 'session: loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Input(input)) => local_input.receive(input),
+                Ok(Command::SetFrameDelay(frame_delay)) => {
+                    if let Err(e) = sess.set_frame_delay(frame_delay, config.local_handle) {
+                        notify(Notification::warning(format!(
+                            "Could not set frame delay: {}",
+                            e
+                        )));
+                    }
+                }
+                Ok(Command::FakeLag(on)) => fake_lag = on,
+                Ok(Command::FrameStep(on)) => frame_step = on,
+                Ok(Command::Step) => step_requested = true,
+                Ok(Command::StressSaveLoad) => game.stress_save_load(),
+                Ok(Command::Disconnect(handle)) => {
+                    if let Err(e) = sess.disconnect_player(handle) {
+                        notify(Notification::warning(format!(
+                            "Could not disconnect player {}: {}",
+                            handle, e
+                        )));
+                    }
+                }
+                Ok(Command::ScheduleTuning(frame, tuning)) => {
+                    if !game.schedule_tuning(frame, tuning) {
+                        notify(Notification::error(format!(
+                            "The tuning for frame {} arrived after simulating frame {}, the peers will desync",
+                            frame,
+                            game.current_frame()
+                        )));
+                    }
+                }
+                Ok(Command::Stop { linger }) => break 'session linger,
+                Err(TryRecvError::Empty) => break,
+                // the render thread is gone
+                Err(TryRecvError::Disconnected) => break 'session false,
+            }
+        }
+
+        for _ in 0..timestep.advance(Instant::now()) {
+            if frame_step && !step_requested {
+                // hold the simulation until the next step
+                continue;
+            }
+            let frame = game.current_frame();
+            let input = match &config.playback {
+                // frames missing from the recording (e.g. after it ended) send no buttons
+                Some(playback) => playback
+                    .input(frame)
+                    .map(|input| input.to_vec())
+                    .unwrap_or_else(|| vec![0; config.input_size]),
+                None => local_input.current().to_vec(),
+            };
+            let (advanced, events) = game_loop.tick(&mut sess, &mut game, |sess| {
+                sess.advance_frame(config.local_handle, &input)
+            })?;
+            events
+                .iter()
+                .filter_map(Notification::from_event)
+                .for_each(notify);
+            if advanced == Advance::Advanced {
+                local_input.accepted();
+                step_requested = false;
+                let update = Update {
+                    state: game.copy_state()?,
+                    sent_input: (frame, input),
+                    confirmed_frames: game.take_confirmed_frames(),
+                    confirmed_hits: game.take_confirmed_hits(),
+                    last_save_size: game.last_save_size(),
+                    held_state_bytes: game.held_state_bytes(),
+                    burst_window: game_loop.metrics.last_burst_window(),
+                };
+                if updates.send(update).is_err() {
+                    break 'session false;
+                }
+            }
+            if let Ok(stats) = sess.network_stats(config.remote_handle) {
+                game_loop.metrics.record("ping_ms", stats.ping as f64);
+                game_loop
+                    .metrics
+                    .record("kbps_sent", stats.kbps_sent as f64);
+                game_loop
+                    .metrics
+                    .record("send_queue_len", stats.send_queue_len as f64);
+            }
+        }
+
+        if !fake_lag || last_poll.elapsed() >= FAKE_LAG {
+            game_loop.poll(&mut sess);
+            last_poll = Instant::now();
+        }
+        if let Some(path) = &config.metrics_path {
+            if last_metrics_dump.elapsed() >= METRICS_PERIOD {
+                game_loop.metrics.write_json(path)?;
+                last_metrics_dump = Instant::now();
+            }
+        }
+        thread::sleep(timestep.until_next_tick(Instant::now()).min(POLL_INTERVAL));
+    };
+
+    if let Some(path) = &config.metrics_path {
+        game_loop.metrics.write_json(path)?;
+    }
+    if linger {
+        let linger_start = Instant::now();
+        while linger_start.elapsed() < LINGER {
+            sess.poll_remote_clients();
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::box_game::Input;
+
+    #[test]
+    fn unsent_dashes_are_kept() {
+        let dash = Input {
+            buttons: InputFlags::DASH | InputFlags::UP,
+            ..Default::default()
+        };
+        let left = Input {
+            buttons: InputFlags::LEFT,
+            stick_x: 2,
+            ..Default::default()
+        };
+        let mut bytes = [input::encode(left), input::encode(left)].concat();
+        merge_unsent(
+            &[input::encode(dash), input::encode(left)].concat(),
+            &mut bytes,
+        );
+        let merged = Input {
+            buttons: InputFlags::LEFT | InputFlags::DASH,
+            ..left
+        };
+        assert_eq!(bytes, [input::encode(merged), input::encode(left)].concat());
+    }
+
+    #[test]
+    fn dashes_are_sent_once() {
+        let dash = Input {
+            buttons: InputFlags::DASH | InputFlags::UP,
+            ..Default::default()
+        };
+        let buttons = |bytes: &[u8]| input::decode(bytes).buttons;
+        let mut local_input = LocalInput::new(INPUT_SIZE);
+        local_input.receive(input::encode(dash).to_vec());
+        // a tick the session did not accept, e.g. at the prediction threshold, keeps the dash
+        assert_eq!(buttons(local_input.current()), dash.buttons);
+        // two accepted ticks without a new input in between, e.g. during a slow render frame
+        let first = local_input.current().to_vec();
+        local_input.accepted();
+        let second = local_input.current().to_vec();
+        local_input.accepted();
+        assert_eq!(buttons(&first), InputFlags::DASH | InputFlags::UP);
+        assert_eq!(buttons(&second), InputFlags::UP);
+    }
+}
@@ -0,0 +1,181 @@
+//! Text rendering with FreeType, only built with the render feature. Every glyph is rasterized once per size and kept
+//! as a texture, so drawing the same lines every frame costs no rasterization or texture uploads.
+
+use crate::box_game::GameError;
+use freetype as ft;
+use graphics::{Context, Graphics, Image, Transformed};
+use opengl_graphics::{Texture, TextureSettings};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The font in the assets folder all text is drawn with.
+const FONT_FILE: &str = "FiraSans-Regular.ttf";
+const OUTLINE_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+/// Offsets of the copies drawn behind outlined text.
+const OUTLINE_OFFSETS: [[f64; 2]; 4] = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// How a line of text is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    /// pixel height
+    pub size: u32,
+    pub color: [f32; 4],
+    /// which end of the line the position given to `Text::draw` is
+    pub align: Align,
+    /// a dark outline keeps the text readable on top of the boxes
+    pub outline: bool,
+}
+
+impl Style {
+    pub const fn new(size: u32) -> Self {
+        Self {
+            size,
+            color: [1.0; 4],
+            align: Align::Left,
+            outline: false,
+        }
+    }
+
+    pub const fn color(self, color: [f32; 4]) -> Self {
+        Self { color, ..self }
+    }
+
+    pub const fn align(self, align: Align) -> Self {
+        Self { align, ..self }
+    }
+
+    pub const fn outline(self) -> Self {
+        Self {
+            outline: true,
+            ..self
+        }
+    }
+}
+
+struct Glyph {
+    /// `None` for glyphs without pixels, e.g. spaces
+    texture: Option<Texture>,
+    /// offset of the bitmap from the pen position on the baseline
+    left: f64,
+    top: f64,
+    advance: f64,
+}
+
+/// A font and the glyphs rasterized from it. Lines are `prepare`d before drawing, drawing itself cannot fail and
+/// happens inside `GlGraphics::draw`.
+pub struct Text {
+    // dropped before the library it was created from
+    face: ft::Face,
+    _library: ft::Library,
+    glyphs: HashMap<(char, u32), Glyph>,
+}
+
+impl Text {
+    pub fn load(font: &Path) -> anyhow::Result<Self> {
+        let library = ft::Library::init()?;
+        let face = library.new_face(font, 0)?;
+        Ok(Self {
+            face,
+            _library: library,
+            glyphs: HashMap::new(),
+        })
+    }
+
+    /// Loads the font the game ships with from the assets folder.
+    pub fn from_assets() -> anyhow::Result<Self> {
+        let assets = find_folder::Search::ParentsThenKids(3, 3).for_folder("assets")?;
+        Self::load(&assets.join(FONT_FILE))
+    }
+
+    /// Rasterizes the glyphs of `text` at `size` that are not cached yet.
+    pub fn prepare(&mut self, text: &str, size: u32) -> Result<(), GameError> {
+        for ch in text.chars() {
+            if self.glyphs.contains_key(&(ch, size)) {
+                continue;
+            }
+            self.face.set_pixel_sizes(0, size)?;
+            self.face
+                .load_char(ch as usize, ft::face::LoadFlag::RENDER)?;
+            let glyph = self.face.glyph();
+            let bitmap = glyph.bitmap();
+            let texture = if bitmap.width() > 0 && bitmap.rows() > 0 {
+                Some(
+                    Texture::from_memory_alpha(
+                        bitmap.buffer(),
+                        bitmap.width() as u32,
+                        bitmap.rows() as u32,
+                        &TextureSettings::new(),
+                    )
+                    .map_err(GameError::Texture)?,
+                )
+            } else {
+                None
+            };
+            self.glyphs.insert(
+                (ch, size),
+                Glyph {
+                    texture,
+                    left: glyph.bitmap_left() as f64,
+                    top: glyph.bitmap_top() as f64,
+                    advance: (glyph.advance().x >> 6) as f64,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Width of the prepared `text` at `size`, characters that were not prepared count as empty.
+    pub fn width(&self, text: &str, size: u32) -> f64 {
+        text.chars()
+            .filter_map(|ch| self.glyphs.get(&(ch, size)))
+            .map(|glyph| glyph.advance)
+            .sum()
+    }
+
+    /// Draws a prepared line with its baseline at `y`, `x` is its left end, center or right end depending on the
+    /// alignment. Characters that were not prepared are skipped.
+    pub fn draw<G>(&self, text: &str, style: Style, [x, y]: [f64; 2], c: &Context, gl: &mut G)
+    where
+        G: Graphics<Texture = Texture>,
+    {
+        let x = match style.align {
+            Align::Left => x,
+            Align::Center => x - self.width(text, style.size) / 2.0,
+            Align::Right => x - self.width(text, style.size),
+        };
+        if style.outline {
+            for &[dx, dy] in OUTLINE_OFFSETS.iter() {
+                self.draw_glyphs(text, style.size, OUTLINE_COLOR, [x + dx, y + dy], c, gl);
+            }
+        }
+        self.draw_glyphs(text, style.size, style.color, [x, y], c, gl);
+    }
+
+    fn draw_glyphs<G>(
+        &self,
+        text: &str,
+        size: u32,
+        color: [f32; 4],
+        [mut x, y]: [f64; 2],
+        c: &Context,
+        gl: &mut G,
+    ) where
+        G: Graphics<Texture = Texture>,
+    {
+        let image = Image::new_color(color);
+        for glyph in text.chars().filter_map(|ch| self.glyphs.get(&(ch, size))) {
+            if let Some(texture) = &glyph.texture {
+                let transform = c.transform.trans(x + glyph.left, y - glyph.top);
+                image.draw(texture, &c.draw_state, transform, gl);
+            }
+            x += glyph.advance;
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longer gaps between two calls of `FixedTimestep::advance`, e.g. after a breakpoint or while the window was being
+/// dragged, count as this long. Catching up on all of it would take a burst of ticks long enough to fall behind again.
+pub const MAX_FRAME_DELTA: Duration = Duration::from_millis(250);
+/// At most this many ticks are run in a row before rendering or polling again, ticks beyond that are dropped.
+pub const MAX_TICKS_PER_FRAME: u32 = 4;
+
+/// Where loops that do their own timing get the time from, so tests and headless runs can replace the wall clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    /// Waits for `duration` to pass on this clock.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when it is told to. Sleeping on it passes the time right away, so a loop that sleeps until
+/// its next tick runs its ticks back to back: the same ticks as in real time, just fast-forwarded.
+pub struct ManualClock {
+    now: Cell<Instant>,
+}
+
+impl ManualClock {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            now: Cell::new(start),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Decides how many simulation ticks are due, for loops that do their own timing instead of using piston's events.
+/// The time since the last call is accumulated and paid out in whole ticks, so the tick rate stays steady even when
+/// single iterations of the loop take longer than a tick.
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    last: Instant,
+    dropped_ticks: u64,
+}
+
+impl FixedTimestep {
+    pub fn new(fps: u64, now: Instant) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / fps as f64),
+            accumulator: Duration::ZERO,
+            last: now,
+            dropped_ticks: 0,
+        }
+    }
+
+    /// Accumulates the time since the last call and returns the number of ticks to run now.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.last);
+        let delta = elapsed.min(MAX_FRAME_DELTA);
+        self.last = now;
+        // the whole ticks in the part of a long gap that is not caught up with
+        self.dropped_ticks += ((elapsed - delta).as_nanos() / self.step.as_nanos()) as u64;
+        self.accumulator += delta;
+        let due = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+        self.accumulator -= self.step * due;
+        if due > MAX_TICKS_PER_FRAME {
+            self.dropped_ticks += (due - MAX_TICKS_PER_FRAME) as u64;
+            tracing::debug!("Dropping {} ticks to catch up", due - MAX_TICKS_PER_FRAME);
+        }
+        due.min(MAX_TICKS_PER_FRAME)
+    }
+
+    /// How long to sleep until the next tick is due.
+    pub fn until_next_tick(&self, now: Instant) -> Duration {
+        let elapsed = self.accumulator + now.saturating_duration_since(self.last);
+        self.step.checked_sub(elapsed).unwrap_or_default()
+    }
+
+    /// Ticks that were due but not run, because of `MAX_FRAME_DELTA` or `MAX_TICKS_PER_FRAME`.
+    pub fn dropped_ticks(&self) -> u64 {
+        self.dropped_ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPS: u64 = 50;
+    const STEP: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn ticks_at_a_steady_rate() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(FPS, start);
+        assert_eq!(timestep.advance(start + STEP / 2), 0);
+        assert_eq!(timestep.until_next_tick(start + STEP / 2), STEP / 2);
+        assert_eq!(timestep.advance(start + STEP), 1);
+        // a slow iteration is caught up with on the next one
+        assert_eq!(timestep.advance(start + STEP * 3 + STEP / 2), 2);
+        assert_eq!(timestep.advance(start + STEP * 4), 1);
+        assert_eq!(timestep.dropped_ticks(), 0);
+    }
+
+    #[test]
+    fn caps_the_catch_up() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(FPS, start);
+        assert_eq!(timestep.advance(start + STEP * 6), MAX_TICKS_PER_FRAME);
+        assert_eq!(timestep.dropped_ticks(), 2);
+        // a long pause counts as MAX_FRAME_DELTA, the rest of it is forgotten
+        let resumed = start + Duration::from_secs(10);
+        assert_eq!(timestep.advance(resumed), MAX_TICKS_PER_FRAME);
+        assert_eq!(timestep.advance(resumed + STEP), 1);
+        // 481 ticks in the forgotten 9630 ms, 8 beyond MAX_TICKS_PER_FRAME in the 250 ms that count
+        assert_eq!(timestep.dropped_ticks(), 2 + 481 + 8);
+    }
+
+    #[test]
+    fn manual_clock_fast_forwards() {
+        let clock = ManualClock::new(Instant::now());
+        let start = clock.now();
+        let mut timestep = FixedTimestep::new(FPS, start);
+        for _ in 0..FPS {
+            clock.sleep(timestep.until_next_tick(clock.now()));
+            assert_eq!(timestep.advance(clock.now()), 1);
+        }
+        assert_eq!(clock.now() - start, STEP * FPS as u32);
+        assert_eq!(timestep.dropped_ticks(), 0);
+    }
+}
@@ -0,0 +1,30 @@
+use tracing_subscriber::prelude::*;
+
+/// Sets up logging to the console, and with `trace_path` a chrome tracing file of all spans
+/// (open it in chrome://tracing or https://ui.perfetto.dev). Keep the returned guard alive until the program ends,
+/// the trace file is completed when it is dropped.
+pub fn init(trace_path: Option<&str>) -> Option<tracing_chrome::FlushGuard> {
+    // RUST_LOG overrides the level, per target as well: RUST_LOG=warn for long soaks, RUST_LOG=debug to see skipped
+    // frames, RUST_LOG=info,ggrs_events=debug for every GGRS event or RUST_LOG=warn,soak::stats=info for the
+    // statistics of a soak without the chaos outages. Targets are module paths unless set explicitly.
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let console = tracing_subscriber::fmt::layer().with_filter(filter);
+    match trace_path {
+        Some(path) => {
+            let (chrome, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            tracing_subscriber::registry()
+                .with(console)
+                .with(chrome)
+                .init();
+            Some(guard)
+        }
+        None => {
+            tracing_subscriber::registry().with(console).init();
+            None
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::box_game::Tuning;
+use anyhow::Context;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A TOML file of gameplay constants, reloaded whenever it changes, for tuning the game during playtests.
+/// Values left out of the file keep their defaults, e.g. a file with just `friction = 0.95`.
+pub struct TuningFile {
+    path: PathBuf,
+    /// modification time of the last version read, `None` until the first one was
+    modified: Option<SystemTime>,
+}
+
+impl TuningFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+        }
+    }
+
+    /// The new tuning if the file changed since the last call, on the first call the current one. Errors are only
+    /// reported once per change, a broken file is retried after the next save.
+    pub fn poll(&mut self) -> Option<anyhow::Result<Tuning>> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(
+            std::fs::read_to_string(&self.path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| parse(&text))
+                .with_context(|| format!("cannot load the tuning from {}", self.path.display())),
+        )
+    }
+}
+
+pub fn parse(text: &str) -> anyhow::Result<Tuning> {
+    Ok(toml::from_str(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_values_keep_their_defaults() {
+        let tuning = parse("max_speed = 9.0\nfriction = 0.95\n").unwrap();
+        assert_eq!(
+            tuning,
+            Tuning {
+                max_speed: 9.0,
+                friction: 0.95,
+                ..Tuning::DEFAULT
+            }
+        );
+        assert!(parse("gravity = 1.0").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use glutin_window::GlutinWindow;
+use opengl_graphics::{GlGraphics, OpenGL};
+use piston::input::Event;
+use piston::window::{NoWindow, Size, Window, WindowSettings};
+use std::time::Duration;
+
+/// The game window, or with `--headless` a stand-in that never opens anything. The event loop keeps producing
+/// update events either way, so the session and simulation run the same without a display or GPU.
+pub enum GameWindow {
+    Glutin(GlutinWindow),
+    Headless(NoWindow),
+}
+
+/// Opens a window with the given title and size, or a headless one. Only real windows come with a renderer.
+pub fn open(
+    title: &str,
+    size: [u32; 2],
+    headless: bool,
+) -> anyhow::Result<(GameWindow, Option<GlGraphics>)> {
+    // Change this to OpenGL::V2_1 if not working
+    let opengl = OpenGL::V3_2;
+    let settings = WindowSettings::new(title, size)
+        .graphics_api(opengl)
+        .exit_on_esc(true);
+    if headless {
+        return Ok((GameWindow::Headless(NoWindow::new(&settings)), None));
+    }
+
+    // Create a Glutin window
+    let window: GlutinWindow = settings
+        .build()
+        .map_err(|e| anyhow::anyhow!("cannot open a window, try --headless: {}", e))?;
+    Ok((GameWindow::Glutin(window), Some(GlGraphics::new(opengl))))
+}
+
+impl Window for GameWindow {
+    fn set_should_close(&mut self, value: bool) {
+        match self {
+            GameWindow::Glutin(window) => window.set_should_close(value),
+            GameWindow::Headless(window) => window.set_should_close(value),
+        }
+    }
+
+    fn should_close(&self) -> bool {
+        match self {
+            GameWindow::Glutin(window) => window.should_close(),
+            GameWindow::Headless(window) => window.should_close(),
+        }
+    }
+
+    fn size(&self) -> Size {
+        match self {
+            GameWindow::Glutin(window) => window.size(),
+            GameWindow::Headless(window) => window.size(),
+        }
+    }
+
+    fn swap_buffers(&mut self) {
+        match self {
+            GameWindow::Glutin(window) => window.swap_buffers(),
+            GameWindow::Headless(window) => window.swap_buffers(),
+        }
+    }
+
+    fn wait_event(&mut self) -> Event {
+        match self {
+            GameWindow::Glutin(window) => window.wait_event(),
+            GameWindow::Headless(window) => window.wait_event(),
+        }
+    }
+
+    fn wait_event_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        match self {
+            GameWindow::Glutin(window) => window.wait_event_timeout(timeout),
+            GameWindow::Headless(window) => window.wait_event_timeout(timeout),
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        match self {
+            GameWindow::Glutin(window) => window.poll_event(),
+            GameWindow::Headless(window) => window.poll_event(),
+        }
+    }
+
+    fn draw_size(&self) -> Size {
+        match self {
+            GameWindow::Glutin(window) => window.draw_size(),
+            GameWindow::Headless(window) => window.draw_size(),
+        }
+    }
+}